@@ -8,26 +8,53 @@ wifi_interface_name=$(iw dev | awk '$1=="Interface"{print $2}')
 # Update the list of available WiFi networks before launch
 iw dev "$wifi_interface_name" scan &> /dev/null || printf 'Error updating WiFi network list with IW\n'
 
-# Configuration via environment variables with defaults
-SSID="${EMBER_WIFI_SSID:-NetFire Ember}"
+# Configuration via environment variables
+SSID="${EMBER_WIFI_SSID:-}"
 PASSWORD="${EMBER_WIFI_PASSWORD:-}"
-ACTIVITY_TIMEOUT="${EMBER_ACTIVITY_TIMEOUT:-120}"
-NETWORK_TIMEOUT="${EMBER_NETWORK_TIMEOUT:-300}"
-ETH_INTERFACE="${EMBER_ETHERNET_INTERFACE:-eth0}"
+ACTIVITY_TIMEOUT="${EMBER_ACTIVITY_TIMEOUT:-}"
+NETWORK_TIMEOUT="${EMBER_NETWORK_TIMEOUT:-}"
+ETH_INTERFACE="${EMBER_ETHERNET_INTERFACE:-}"
+CONFIG_FILE="${EMBER_CONFIG_FILE:-}"
+
+# Variables that are set take precedence over a configuration file. Without
+# a file, the ones that are not set fall back to the defaults below.
+CMD_ARGS=()
+if [ -n "$CONFIG_FILE" ]; then
+    CMD_ARGS+=(--config "$CONFIG_FILE")
+else
+    SSID="${SSID:-NetFire Ember}"
+    ACTIVITY_TIMEOUT="${ACTIVITY_TIMEOUT:-120}"
+    NETWORK_TIMEOUT="${NETWORK_TIMEOUT:-300}"
+    ETH_INTERFACE="${ETH_INTERFACE:-eth0}"
+fi
 
 # Build command arguments
-CMD_ARGS="-s \"$SSID\" -a $ACTIVITY_TIMEOUT -n $NETWORK_TIMEOUT -e $ETH_INTERFACE"
+if [ -n "$SSID" ]; then
+    CMD_ARGS+=(-s "$SSID")
+fi
 if [ -n "$PASSWORD" ]; then
-    CMD_ARGS="$CMD_ARGS -p \"$PASSWORD\""
+    CMD_ARGS+=(-p "$PASSWORD")
+fi
+if [ -n "$ACTIVITY_TIMEOUT" ]; then
+    CMD_ARGS+=(-a "$ACTIVITY_TIMEOUT")
+fi
+if [ -n "$NETWORK_TIMEOUT" ]; then
+    CMD_ARGS+=(-n "$NETWORK_TIMEOUT")
+fi
+if [ -n "$ETH_INTERFACE" ]; then
+    CMD_ARGS+=(-e "$ETH_INTERFACE")
 fi
 
 # Launch Ember Network Connect
 printf 'Starting Ember Network Connect\n'
-printf '  SSID: %s\n' "$SSID"
-printf '  Ethernet interface: %s\n' "$ETH_INTERFACE"
-printf '  Activity timeout: %s seconds\n' "$ACTIVITY_TIMEOUT"
-printf '  Overall timeout: %s seconds\n' "$NETWORK_TIMEOUT"
+if [ -n "$CONFIG_FILE" ]; then
+    printf '  Config file: %s\n' "$CONFIG_FILE"
+fi
+printf '  SSID: %s\n' "${SSID:-not set}"
+printf '  Ethernet interface: %s\n' "${ETH_INTERFACE:-not set}"
+printf '  Activity timeout: %s\n' "${ACTIVITY_TIMEOUT:-not set}${ACTIVITY_TIMEOUT:+ seconds}"
+printf '  Overall timeout: %s\n' "${NETWORK_TIMEOUT:-not set}${NETWORK_TIMEOUT:+ seconds}"
 
 # Sleep infinity when we exit successfully; this has the effect of deactivating 
 # the AP and keeping it that way until an update or device reboot.
-eval ./ember-network-connect $CMD_ARGS && sleep infinity
+./ember-network-connect "${CMD_ARGS[@]}" && sleep infinity
@@ -0,0 +1,271 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::backend::{DeviceState, Ip4Config};
+use crate::config::{AuditCommand, Config};
+use crate::errors::Result;
+use crate::ethernet::EthernetStatus;
+
+/// Audit log file in the state directory, unless configured
+pub const AUDIT_LOG_FILE: &str = "audit.jsonl";
+
+/// Change made through the portal
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Reset,
+    Restore,
+    ConfigureStatic,
+    Extend,
+}
+
+impl AuditAction {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Reset => "reset",
+            Self::Restore => "restore",
+            Self::ConfigureStatic => "configure_static",
+            Self::Extend => "extend",
+        }
+    }
+}
+
+/// Configuration of an ethernet device around a change
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConnectionSummary {
+    pub interface: String,
+    pub state: DeviceState,
+    pub ipv4: Ip4Config,
+    /// Wired profiles as `<name> (<method>)`
+    pub profiles: Vec<String>,
+}
+
+impl From<EthernetStatus> for ConnectionSummary {
+    fn from(status: EthernetStatus) -> Self {
+        Self {
+            interface: status.interface,
+            state: status.state,
+            ipv4: status.ipv4,
+            profiles: status
+                .profiles
+                .iter()
+                .map(|profile| format!("{} ({})", profile.id, profile.method))
+                .collect(),
+        }
+    }
+}
+
+/// One line of the audit log
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    pub client: Option<IpAddr>,
+    /// MAC address of the client from its DHCP lease
+    pub hw_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub parameters: serde_json::Value,
+    /// Ethernet connections before and after the change
+    ///
+    /// Empty for extensions, which record the overall timeout deadlines in
+    /// the outcome instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<ConnectionSummary>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<ConnectionSummary>,
+    /// HTTP status of the response
+    pub status: u16,
+    pub outcome: serde_json::Value,
+}
+
+/// Append-only JSON lines log of the changes made through the portal
+pub struct AuditLog {
+    path: PathBuf,
+    /// Keeps records from concurrent requests on separate lines
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append a record, logging failures since the change already happened
+    pub fn append(&self, record: &AuditRecord) {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Err(e) = self.write(record) {
+            error!("Writing audit record to {} failed: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&self, record: &AuditRecord) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+/// Records of an audit log, oldest first
+///
+/// Lines that cannot be parsed, such as one cut short by a power loss, are
+/// skipped with a warning rather than hiding the rest of the log.
+fn read_records(path: &Path) -> Result<Vec<AuditRecord>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let records = fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(index, line)| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                warn!("Skipping line {} of {}: {}", index + 1, path.display(), e);
+                None
+            },
+        })
+        .collect();
+    Ok(records)
+}
+
+/// Keep the `last` most recent records, all of them if None
+fn keep_last(records: &mut Vec<AuditRecord>, last: Option<usize>) {
+    if let Some(last) = last {
+        records.drain(..records.len().saturating_sub(last));
+    }
+}
+
+/// Run an `audit` subcommand
+pub fn run_audit_command(command: &AuditCommand, config: &Config) -> Result<()> {
+    match command {
+        AuditCommand::List { json, last } => {
+            let path = config.audit_log();
+            let mut records = read_records(&path)?;
+            if records.is_empty() && !json {
+                println!("No audit records in {}", path.display());
+            }
+
+            keep_last(&mut records, *last);
+
+            for record in records {
+                if *json {
+                    println!("{}", serde_json::to_string(&record)?);
+                    continue;
+                }
+
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    record.timestamp.to_rfc3339(),
+                    record.action.name(),
+                    record.client.map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+                    record.hw_address.as_deref().unwrap_or("-"),
+                    record.status,
+                    record.parameters,
+                    record.user_agent.as_deref().unwrap_or("-")
+                );
+            }
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(action: AuditAction, seconds: u64) -> AuditRecord {
+        AuditRecord {
+            timestamp: Utc::now(),
+            action,
+            client: Some("192.168.42.10".parse().unwrap()),
+            hw_address: None,
+            user_agent: None,
+            parameters: json!({ "seconds": seconds }),
+            before: Vec::new(),
+            after: Vec::new(),
+            status: 200,
+            outcome: json!({ "ok": true }),
+        }
+    }
+
+    fn seconds(records: &[AuditRecord]) -> Vec<u64> {
+        records
+            .iter()
+            .map(|record| record.parameters["seconds"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn appended_records_read_back_in_order() {
+        let path = Config::for_tests(&[]).audit_log();
+        assert!(read_records(&path).unwrap().is_empty());
+
+        let log = AuditLog::new(path.clone());
+        for seconds in [60, 120, 180] {
+            log.append(&record(AuditAction::Extend, seconds));
+        }
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(seconds(&records), [60, 120, 180]);
+        assert!(matches!(records[0].action, AuditAction::Extend));
+    }
+
+    #[test]
+    fn unparsable_lines_are_skipped() {
+        let path = Config::for_tests(&[]).audit_log();
+        let log = AuditLog::new(path.clone());
+        log.append(&record(AuditAction::Reset, 1));
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"timestamp\":\"2026-01-01T00:00:00Z\",\"act\n\n")
+            .unwrap();
+        log.append(&record(AuditAction::Restore, 2));
+
+        assert_eq!(seconds(&read_records(&path).unwrap()), [1, 2]);
+    }
+
+    #[test]
+    fn last_keeps_the_most_recent_records() {
+        let all = || {
+            (1..=4)
+                .map(|seconds| record(AuditAction::Extend, seconds))
+                .collect()
+        };
+
+        let mut records: Vec<AuditRecord> = all();
+        keep_last(&mut records, Some(2));
+        assert_eq!(seconds(&records), [3, 4]);
+
+        let mut records = all();
+        keep_last(&mut records, Some(10));
+        assert_eq!(seconds(&records), [1, 2, 3, 4]);
+
+        let mut records = all();
+        keep_last(&mut records, Some(0));
+        assert!(records.is_empty());
+
+        let mut records = all();
+        keep_last(&mut records, None);
+        assert_eq!(records.len(), 4);
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordVerifier};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use crate::backend::create_backend;
+use crate::config::{AuthCommand, Config};
+use crate::errors::{AppError, Result};
+
+/// Number of digits in a per-device code
+const DEVICE_CODE_DIGITS: u32 = 8;
+
+/// Multiple of the per-client failure limit after which all clients are
+/// locked out, so that changing address does not give a fresh budget
+const GLOBAL_FAILURES_FACTOR: u32 = 4;
+
+/// Password hashes verified at the same time
+const MAX_CONCURRENT_VERIFICATIONS: usize = 2;
+
+/// Access granted to a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// No access to protected endpoints
+    Anonymous,
+    /// Can read status but not change anything
+    View,
+    /// Can reset and reconfigure the ethernet devices
+    Admin,
+}
+
+/// Why a request was refused
+#[derive(Debug)]
+pub enum AuthError {
+    /// No credential or a wrong credential
+    Unauthorized,
+    /// Valid credential without enough access
+    Forbidden,
+    /// Too many failed attempts, retry after the given time
+    LockedOut(Duration),
+}
+
+/// Consecutive failed attempts and the lockout they caused
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    /// End of the lockout, forgetting it once it is over
+    fn locked_until(&mut self, now: Instant) -> Option<Instant> {
+        if self.locked_until.is_some_and(|until| until <= now) {
+            *self = Self::default();
+        }
+        self.locked_until
+    }
+
+    /// Count an attempt, locking out once the limit is reached
+    fn count(&mut self, limit: u32, lockout: Duration, now: Instant) -> bool {
+        self.count += 1;
+        let locked = limit > 0 && self.count >= limit;
+        if locked {
+            self.locked_until = Some(now + lockout);
+        }
+        locked
+    }
+}
+
+/// Failed attempts of each client and of all clients together
+#[derive(Default)]
+struct FailureLog {
+    clients: HashMap<IpAddr, Failures>,
+    all: Failures,
+}
+
+/// Checks the credentials presented with portal requests
+pub struct Authenticator {
+    pin: Option<String>,
+    password_hash: Option<String>,
+    device_code: Option<String>,
+    view_pin: Option<String>,
+    max_failures: u32,
+    lockout: Duration,
+    failures: Mutex<FailureLog>,
+    verifications: Semaphore,
+}
+
+impl Authenticator {
+    /// Build the authenticator from the configured credentials
+    ///
+    /// `hw_address` is the permanent MAC address the per-device code is derived
+    /// from.
+    pub fn new(config: &Config, hw_address: Option<&str>) -> Result<Self> {
+        if let Some(ref hash) = config.reset_password_hash {
+            PasswordHash::new(hash).map_err(|e| {
+                AppError::Auth(format!("invalid password hash: {}", e))
+            })?;
+        }
+
+        let device_code = match config.reset_code_secret {
+            Some(ref secret) => {
+                let mac = hw_address.ok_or_else(|| {
+                    AppError::Auth("no MAC address to derive the device code from".to_string())
+                })?;
+                Some(device_code(secret, mac))
+            },
+            None => None,
+        };
+
+        let authenticator = Self {
+            pin: config.reset_pin.clone(),
+            password_hash: config.reset_password_hash.clone(),
+            device_code,
+            view_pin: config.view_pin.clone(),
+            max_failures: config.auth_max_failures,
+            lockout: Duration::from_secs(config.auth_lockout),
+            failures: Mutex::new(FailureLog::default()),
+            verifications: Semaphore::new(MAX_CONCURRENT_VERIFICATIONS),
+        };
+
+        if authenticator.view_pin.is_some() && !authenticator.admin_required() {
+            return Err(AppError::Auth(
+                "a view PIN requires a reset PIN, password hash or code secret".to_string(),
+            ));
+        }
+
+        if authenticator.admin_required() {
+            info!("Reset credential required for changes");
+        }
+
+        Ok(authenticator)
+    }
+
+    /// Whether changes need a credential
+    pub fn admin_required(&self) -> bool {
+        self.pin.is_some() || self.password_hash.is_some() || self.device_code.is_some()
+    }
+
+    /// Check that a credential presented by a client grants at least the
+    /// required role
+    ///
+    /// Wrong credentials count towards a lockout of the client that sent them,
+    /// and of all clients once several times the limit is reached.
+    pub async fn authorize(
+        &self,
+        client: IpAddr,
+        credential: Option<&str>,
+        required: Role,
+    ) -> std::result::Result<(), AuthError> {
+        if let Some(until) = self.locked_until(client) {
+            return Err(AuthError::LockedOut(until - Instant::now()));
+        }
+
+        let role = match credential {
+            Some(credential) => {
+                // The attempt counts as failed until it is verified, so that
+                // concurrent attempts cannot all pass the lockout check
+                self.count_attempt(client)?;
+                match self.role_of(credential).await {
+                    Some(role) => {
+                        self.clear_failures(client);
+                        role
+                    },
+                    None => {
+                        self.warn_failure(client);
+                        return Err(AuthError::Unauthorized);
+                    },
+                }
+            },
+            None => self.default_role(),
+        };
+
+        if role >= required {
+            Ok(())
+        } else if credential.is_some() {
+            Err(AuthError::Forbidden)
+        } else {
+            Err(AuthError::Unauthorized)
+        }
+    }
+
+    fn failures(&self) -> MutexGuard<'_, FailureLog> {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// End of the lockout of a client, if it or all clients are locked out
+    fn locked_until(&self, client: IpAddr) -> Option<Instant> {
+        let now = Instant::now();
+        let mut failures = self.failures();
+
+        let all = failures.all.locked_until(now);
+        let own = failures
+            .clients
+            .get_mut(&client)
+            .and_then(|failures| failures.locked_until(now));
+        all.max(own)
+    }
+
+    /// Count a credential attempt as failed before it is verified
+    ///
+    /// Refuses the attempt if a concurrent one already used up the budget.
+    fn count_attempt(&self, client: IpAddr) -> std::result::Result<(), AuthError> {
+        let now = Instant::now();
+        let mut failures = self.failures();
+        let FailureLog { clients, all } = &mut *failures;
+
+        // Forget lockouts that ended so the map only holds recent clients
+        clients.retain(|_, failures| failures.locked_until.is_none_or(|until| until > now));
+
+        let own = clients.entry(client).or_default();
+        if let Some(until) = own.locked_until.max(all.locked_until(now)) {
+            return Err(AuthError::LockedOut(until - now));
+        }
+
+        if own.count(self.max_failures, self.lockout, now) {
+            warn!("Locking out {} for {}s", client, self.lockout.as_secs());
+        }
+
+        let limit = self.max_failures.saturating_mul(GLOBAL_FAILURES_FACTOR);
+        if all.count(limit, self.lockout, now) {
+            warn!(
+                "{} wrong credentials, locking out all clients for {}s",
+                limit,
+                self.lockout.as_secs()
+            );
+            // The budget starts over once the lockout ends
+            all.count = 0;
+        }
+        Ok(())
+    }
+
+    /// Forget the attempts of a client that presented a valid credential
+    fn clear_failures(&self, client: IpAddr) {
+        let mut failures = self.failures();
+
+        let count = failures.clients.remove(&client).map_or(0, |failures| failures.count);
+        failures.all.count = failures.all.count.saturating_sub(count);
+    }
+
+    fn warn_failure(&self, client: IpAddr) {
+        let failures = self.failures();
+        let count = failures.clients.get(&client).map_or(0, |failures| failures.count);
+        warn!(
+            "Wrong credential from {} ({} of {} attempts)",
+            client, count, self.max_failures
+        );
+    }
+
+    /// Role of requests without a credential
+    fn default_role(&self) -> Role {
+        if !self.admin_required() {
+            Role::Admin
+        } else if self.view_pin.is_none() {
+            Role::View
+        } else {
+            Role::Anonymous
+        }
+    }
+
+    /// Role granted by a credential, if it matches any configured one
+    async fn role_of(&self, credential: &str) -> Option<Role> {
+        let matches = |expected: &Option<String>| {
+            expected
+                .as_deref()
+                .is_some_and(|expected| bool::from(expected.as_bytes().ct_eq(credential.as_bytes())))
+        };
+
+        // The password hash is only checked when nothing cheaper matches
+        let admin = matches(&self.pin)
+            || matches(&self.device_code)
+            || self.password_matches(credential).await;
+
+        if admin {
+            Some(Role::Admin)
+        } else if matches(&self.view_pin) {
+            Some(Role::View)
+        } else {
+            None
+        }
+    }
+
+    /// Verify a credential against the password hash
+    ///
+    /// Hashing is slow on purpose, so it runs on the blocking thread pool,
+    /// a few verifications at a time.
+    async fn password_matches(&self, credential: &str) -> bool {
+        let Some(hash) = self.password_hash.clone() else {
+            return false;
+        };
+        let credential = credential.to_string();
+
+        let Ok(_permit) = self.verifications.acquire().await else {
+            return false;
+        };
+
+        tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(credential.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+/// Derive the per-device code from a secret and a MAC address
+///
+/// The code is the HMAC-SHA256 of the lower case MAC address keyed with the
+/// secret, reduced to a fixed number of decimal digits.
+pub fn device_code(secret: &str, hw_address: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(hw_address.to_ascii_lowercase().as_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    let modulus = 10u64.pow(DEVICE_CODE_DIGITS);
+
+    format!(
+        "{:0width$}",
+        u64::from_be_bytes(prefix) % modulus,
+        width = DEVICE_CODE_DIGITS as usize
+    )
+}
+
+/// Run an auth subcommand
+pub fn run_auth_command(command: &AuthCommand, config: &Config) -> Result<()> {
+    match command {
+        AuthCommand::HashPassword => {
+            let mut password = String::new();
+            std::io::stdin().lock().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+
+            let salt = SaltString::generate(&mut OsRng);
+            let hash = Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map_err(|e| AppError::Auth(format!("hashing password failed: {}", e)))?;
+
+            println!("{}", hash);
+            Ok(())
+        },
+        AuthCommand::DeviceCode { mac } => {
+            let Some(ref secret) = config.reset_code_secret else {
+                return Err(AppError::Auth("no reset code secret configured".to_string()));
+            };
+
+            let mac = match mac {
+                Some(mac) => mac.clone(),
+                None => device_hw_address(config)?,
+            };
+
+            println!("{}\t{}", mac, device_code(secret, &mac));
+            Ok(())
+        },
+    }
+}
+
+/// Permanent MAC address of the first ethernet interface, which device codes are
+/// derived from
+fn device_hw_address(config: &Config) -> Result<String> {
+    let interface = &config.ethernet_interfaces[0];
+
+    let mut backend = create_backend(config);
+    backend.start_service()?;
+
+    backend
+        .get_hw_address(interface)?
+        .ok_or_else(|| AppError::Auth(format!("no MAC address on {}", interface)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use argon2::{Algorithm, Params, Version};
+
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 42, 10));
+    const OTHER_CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 42, 11));
+
+    fn authenticator(args: &[&str]) -> Authenticator {
+        Authenticator::new(&Config::for_tests(args), None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn roles_follow_credentials() {
+        let auth = authenticator(&["--reset-pin", "1234", "--view-pin", "0000"]);
+
+        assert!(auth.authorize(CLIENT, Some("1234"), Role::Admin).await.is_ok());
+        assert!(auth.authorize(CLIENT, Some("0000"), Role::View).await.is_ok());
+        assert!(matches!(
+            auth.authorize(CLIENT, Some("0000"), Role::Admin).await,
+            Err(AuthError::Forbidden)
+        ));
+        assert!(matches!(
+            auth.authorize(CLIENT, None, Role::View).await,
+            Err(AuthError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn no_credentials_configured_allows_everything() {
+        let auth = authenticator(&[]);
+        assert!(auth.authorize(CLIENT, None, Role::Admin).await.is_ok());
+    }
+
+    /// Cheap hash of the password "secret"
+    fn password_hash() -> String {
+        let params = Params::new(8, 1, 1, None).unwrap();
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn password_hash_is_verified() {
+        let hash = password_hash();
+        let auth = authenticator(&["--reset-password-hash", &hash]);
+
+        assert!(auth.authorize(CLIENT, Some("secret"), Role::Admin).await.is_ok());
+        assert!(matches!(
+            auth.authorize(CLIENT, Some("guess"), Role::Admin).await,
+            Err(AuthError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn lockout_applies_to_the_failing_client() {
+        let auth = authenticator(&["--reset-pin", "1234", "--auth-max-failures", "2"]);
+
+        for _ in 0..2 {
+            assert!(matches!(
+                auth.authorize(CLIENT, Some("guess"), Role::Admin).await,
+                Err(AuthError::Unauthorized)
+            ));
+        }
+
+        // Even the right credential is refused during the lockout
+        assert!(matches!(
+            auth.authorize(CLIENT, Some("1234"), Role::Admin).await,
+            Err(AuthError::LockedOut(_))
+        ));
+        assert!(auth.authorize(OTHER_CLIENT, Some("1234"), Role::Admin).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn concurrent_attempts_count_before_verification() {
+        let hash = password_hash();
+        let auth = authenticator(&["--reset-password-hash", &hash, "--auth-max-failures", "2"]);
+
+        let (first, second, third) = tokio::join!(
+            auth.authorize(CLIENT, Some("guess"), Role::Admin),
+            auth.authorize(CLIENT, Some("guess"), Role::Admin),
+            auth.authorize(CLIENT, Some("secret"), Role::Admin),
+        );
+
+        assert!(matches!(first, Err(AuthError::Unauthorized)));
+        assert!(matches!(second, Err(AuthError::Unauthorized)));
+        assert!(matches!(third, Err(AuthError::LockedOut(_))));
+    }
+
+    #[tokio::test]
+    async fn lockout_applies_to_all_clients_after_global_limit() {
+        let auth = authenticator(&["--reset-pin", "1234", "--auth-max-failures", "1"]);
+
+        for host in 0..GLOBAL_FAILURES_FACTOR {
+            let client = IpAddr::V4(Ipv4Addr::new(192, 168, 42, 100 + host as u8));
+            assert!(matches!(
+                auth.authorize(client, Some("guess"), Role::Admin).await,
+                Err(AuthError::Unauthorized)
+            ));
+        }
+
+        assert!(matches!(
+            auth.authorize(OTHER_CLIENT, Some("1234"), Role::Admin).await,
+            Err(AuthError::LockedOut(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn correct_credential_clears_failures() {
+        let auth = authenticator(&["--reset-pin", "1234", "--auth-max-failures", "2"]);
+
+        assert!(auth.authorize(CLIENT, Some("guess"), Role::Admin).await.is_err());
+        assert!(auth.authorize(CLIENT, Some("1234"), Role::Admin).await.is_ok());
+        assert!(auth.authorize(CLIENT, Some("guess"), Role::Admin).await.is_err());
+        assert!(auth.authorize(CLIENT, Some("1234"), Role::Admin).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn lockout_ends() {
+        let auth = authenticator(&[
+            "--reset-pin",
+            "1234",
+            "--auth-max-failures",
+            "1",
+            "--auth-lockout",
+            "0",
+        ]);
+
+        assert!(auth.authorize(CLIENT, Some("guess"), Role::Admin).await.is_err());
+        assert!(auth.authorize(CLIENT, Some("1234"), Role::Admin).await.is_ok());
+        assert!(auth.failures().clients.is_empty());
+    }
+
+    #[test]
+    fn device_code_is_stable_and_ignores_mac_case() {
+        let code = device_code("secret", "02:00:00:00:00:01");
+        assert_eq!(code.len(), DEVICE_CODE_DIGITS as usize);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(code, device_code("secret", "02:00:00:00:00:01".to_uppercase().as_str()));
+        assert_ne!(code, device_code("other", "02:00:00:00:00:01"));
+    }
+}
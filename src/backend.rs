@@ -0,0 +1,213 @@
+#[cfg(any(test, feature = "fake-backend"))]
+mod fake;
+mod networkmanager;
+
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::network::StaticConfig;
+
+#[cfg(any(test, feature = "fake-backend"))]
+pub use fake::{FakeBackend, FAKE_WIFI_INTERFACE};
+pub use networkmanager::NetworkManagerBackend;
+
+/// Available network backend implementations
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// NetworkManager over D-Bus
+    NetworkManager,
+    /// In-memory network state, for development without NetworkManager
+    #[cfg(feature = "fake-backend")]
+    Fake,
+}
+
+/// Type of a network device
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    Ethernet,
+    WiFi,
+    Other,
+}
+
+/// State of a network device
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceState {
+    Unknown,
+    Unmanaged,
+    Unavailable,
+    Disconnected,
+    Prepare,
+    Config,
+    NeedAuth,
+    IpConfig,
+    IpCheck,
+    Secondaries,
+    Activated,
+    Deactivating,
+    Failed,
+}
+
+/// Network device as seen by the backend
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub interface: String,
+    pub device_type: DeviceType,
+    pub state: DeviceState,
+}
+
+/// IPv4 configuration currently applied to a device
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ip4Config {
+    pub addresses: Vec<String>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+}
+
+/// Setting property with its D-Bus type signature, so that it can be sent
+/// back to NetworkManager unchanged
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SettingValue {
+    pub signature: String,
+    pub value: serde_json::Value,
+}
+
+impl SettingValue {
+    #[cfg(any(test, feature = "fake-backend"))]
+    pub fn string(value: &str) -> Self {
+        Self {
+            signature: "s".into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// Properties of a connection profile by setting and property name, as in
+/// `nm-settings-dbus(5)`
+pub type Settings = BTreeMap<String, BTreeMap<String, SettingValue>>;
+
+/// Settings of a connection profile
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProfileSettings {
+    pub id: String,
+    pub uuid: String,
+    pub method: String,
+    /// Every setting of the profile, with the secrets NetworkManager hands out
+    pub settings: Settings,
+}
+
+impl ProfileSettings {
+    pub fn property(&self, setting: &str, property: &str) -> Option<&SettingValue> {
+        self.settings.get(setting)?.get(property)
+    }
+
+    /// Interface name the profile is restricted to
+    pub fn interface_name(&self) -> Option<&str> {
+        self.property("connection", "interface-name")?
+            .value
+            .as_str()
+            .filter(|name| !name.is_empty())
+    }
+
+    /// MAC address the profile is restricted to, like `aa:bb:cc:dd:ee:ff`
+    pub fn mac_address(&self) -> Option<String> {
+        let octets = self.property("802-3-ethernet", "mac-address")?.value.as_array()?;
+        if octets.is_empty() {
+            return None;
+        }
+
+        let octets: Vec<String> = octets
+            .iter()
+            .map(|octet| format!("{:02x}", octet.as_u64().unwrap_or_default()))
+            .collect();
+        Some(octets.join(":"))
+    }
+}
+
+/// Connection profile as seen by the backend
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    pub id: String,
+    pub uuid: String,
+    pub kind: String,
+    pub mode: String,
+    pub ssid: Option<String>,
+}
+
+/// Operations the network handler needs from the system network stack
+pub trait NetworkBackend {
+    /// Make sure the network service is running
+    fn start_service(&mut self) -> Result<()>;
+
+    /// List all network devices
+    fn get_devices(&self) -> Result<Vec<DeviceInfo>>;
+
+    /// Look up a device by interface name
+    fn get_device(&self, interface: &str) -> Result<DeviceInfo>;
+
+    /// Permanent hardware address of the device
+    fn get_hw_address(&self, interface: &str) -> Result<Option<String>>;
+
+    /// Whether the device has a link
+    fn get_carrier(&self, interface: &str) -> Result<bool>;
+
+    /// IPv4 configuration currently applied to the device
+    fn get_ip4_config(&self, interface: &str) -> Result<Ip4Config>;
+
+    /// Lease time in seconds of the DHCP lease held by the device
+    fn get_dhcp_lease_time(&self, interface: &str) -> Result<Option<u64>>;
+
+    /// List all connection profiles
+    fn get_connections(&self) -> Result<Vec<ConnectionInfo>>;
+
+    /// Device a connection profile is active on, if any
+    fn get_active_device(&self, uuid: &str) -> Result<Option<String>>;
+
+    /// Read the settings of a connection profile
+    fn get_profile(&self, uuid: &str) -> Result<ProfileSettings>;
+
+    /// Create a connection profile from previously read settings
+    fn add_profile(&mut self, profile: &ProfileSettings) -> Result<()>;
+
+    /// Activate a connection profile
+    fn activate_connection(&mut self, uuid: &str) -> Result<()>;
+
+    /// Deactivate a connection profile
+    fn deactivate_connection(&mut self, uuid: &str) -> Result<()>;
+
+    /// Delete a connection profile
+    fn delete_connection(&mut self, uuid: &str) -> Result<()>;
+
+    /// Create and activate an access point on a WiFi device
+    fn create_hotspot(
+        &mut self,
+        interface: &str,
+        ssid: &str,
+        passphrase: Option<&str>,
+        gateway: Ipv4Addr,
+    ) -> Result<ConnectionInfo>;
+
+    /// Switch an ethernet device to DHCP addressing
+    fn set_dhcp(&mut self, interface: &str) -> Result<()>;
+
+    /// Apply a static IPv4 configuration to an ethernet device
+    fn set_static(&mut self, interface: &str, static_config: &StaticConfig) -> Result<()>;
+}
+
+/// Create the backend selected in the configuration
+pub fn create_backend(config: &Config) -> Box<dyn NetworkBackend> {
+    match config.network_backend {
+        BackendKind::NetworkManager => Box::new(NetworkManagerBackend::new()),
+        #[cfg(feature = "fake-backend")]
+        BackendKind::Fake => Box::new(FakeBackend::with_defaults(
+            config.interface.as_deref().unwrap_or(FAKE_WIFI_INTERFACE),
+            &config.ethernet_interfaces,
+        )),
+    }
+}
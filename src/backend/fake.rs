@@ -0,0 +1,457 @@
+use std::net::Ipv4Addr;
+
+use serde_json::json;
+use tracing::info;
+
+use crate::backend::{
+    ConnectionInfo, DeviceInfo, DeviceState, DeviceType, Ip4Config, NetworkBackend,
+    ProfileSettings, SettingValue, Settings,
+};
+use crate::errors::{AppError, Result};
+use crate::network::StaticConfig;
+
+pub const FAKE_WIFI_INTERFACE: &str = "wlan0";
+const FAKE_LEASE_TIME: u64 = 3600;
+
+/// Device with its link and addressing state
+#[derive(Clone, Debug)]
+struct FakeDevice {
+    info: DeviceInfo,
+    hw_address: String,
+    carrier: bool,
+    ip4: Ip4Config,
+    lease_time: Option<u64>,
+}
+
+/// Connection profile and the device it is active on
+#[derive(Clone, Debug)]
+struct FakeConnection {
+    info: ConnectionInfo,
+    settings: Settings,
+    active_on: Option<String>,
+}
+
+impl FakeConnection {
+    fn property(&self, setting: &str, property: &str) -> &serde_json::Value {
+        self.settings
+            .get(setting)
+            .and_then(|properties| properties.get(property))
+            .map_or(&serde_json::Value::Null, |value| &value.value)
+    }
+
+    /// IPv4 configuration the profile applies when activated
+    fn ip4(&self) -> Ip4Config {
+        let list = |property| self.property("ipv4", property).as_array().cloned();
+
+        let addresses = list("address-data")
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|address| {
+                let address = &address["value"];
+                let prefix = address["prefix"]["value"].as_u64()?;
+                Some(format!("{}/{}", address["address"]["value"].as_str()?, prefix))
+            })
+            .collect();
+
+        Ip4Config {
+            addresses,
+            gateway: self.property("ipv4", "gateway").as_str().and_then(|g| g.parse().ok()),
+            dns: list("dns-data")
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|dns| dns.as_str()?.parse().ok())
+                .collect(),
+        }
+    }
+}
+
+/// Settings of a wired profile, shaped like those NetworkManager hands out
+fn wired_settings(
+    id: &str,
+    uuid: &str,
+    method: &str,
+    ip4: &Ip4Config,
+    interface: Option<&str>,
+) -> Settings {
+    let value = |signature: &str, value| SettingValue {
+        signature: signature.to_string(),
+        value,
+    };
+
+    let mut connection = vec![
+        ("id", SettingValue::string(id)),
+        ("uuid", SettingValue::string(uuid)),
+        ("type", SettingValue::string("802-3-ethernet")),
+    ];
+    if let Some(interface) = interface {
+        connection.push(("interface-name", SettingValue::string(interface)));
+    }
+
+    let address_data = ip4
+        .addresses
+        .iter()
+        .filter_map(|address| {
+            let (address, prefix) = address.split_once('/')?;
+            Some(json!({
+                "signature": "a{sv}",
+                "value": {
+                    "address": { "signature": "s", "value": address },
+                    "prefix": { "signature": "u", "value": prefix.parse::<u32>().ok()? },
+                },
+            }))
+        })
+        .collect();
+    let dns_data = ip4.dns.iter().map(|dns| json!(dns.to_string())).collect();
+
+    let mut ipv4 = vec![
+        ("method", SettingValue::string(method)),
+        ("address-data", value("aa{sv}", serde_json::Value::Array(address_data))),
+        ("dns-data", value("as", serde_json::Value::Array(dns_data))),
+    ];
+    if let Some(gateway) = ip4.gateway {
+        ipv4.push(("gateway", SettingValue::string(&gateway.to_string())));
+    }
+
+    let collect = |properties: Vec<(&str, SettingValue)>| {
+        properties
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    };
+    Settings::from([
+        ("connection".to_string(), collect(connection)),
+        ("802-3-ethernet".to_string(), Default::default()),
+        ("ipv4".to_string(), collect(ipv4)),
+    ])
+}
+
+/// In-memory backend that simulates devices, profiles and their state
+#[derive(Default)]
+pub struct FakeBackend {
+    devices: Vec<FakeDevice>,
+    connections: Vec<FakeConnection>,
+    next_uuid: u64,
+    /// Whether switching a device to DHCP fails
+    set_dhcp_fails: bool,
+}
+
+impl FakeBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A WiFi device and ethernet devices with a static profile applied to each
+    pub fn with_defaults(wifi_interface: &str, ethernet_interfaces: &[String]) -> Self {
+        let mut backend = Self::new().with_device(wifi_interface, DeviceType::WiFi);
+
+        for (subnet, interface) in (1..).zip(ethernet_interfaces) {
+            backend = backend.with_device(interface, DeviceType::Ethernet);
+
+            let ip4 = Ip4Config {
+                addresses: vec![format!("192.168.{}.50/24", subnet)],
+                gateway: Some(Ipv4Addr::new(192, 168, subnet, 1)),
+                dns: vec![Ipv4Addr::new(192, 168, subnet, 1)],
+            };
+            let id = format!("Wired static {}", interface);
+            backend.add_connection(&id, "802-3-ethernet", "manual", &ip4, interface);
+        }
+        backend
+    }
+
+    /// Add a disconnected device with a link
+    pub fn with_device(mut self, interface: &str, device_type: DeviceType) -> Self {
+        let hw_address = format!("02:00:00:00:00:{:02X}", self.devices.len() + 1);
+        self.devices.push(FakeDevice {
+            info: DeviceInfo {
+                interface: interface.to_string(),
+                device_type,
+                state: DeviceState::Disconnected,
+            },
+            hw_address,
+            carrier: true,
+            ip4: Ip4Config::default(),
+            lease_time: None,
+        });
+        self
+    }
+
+    /// Unplug the cable of a device, which then never obtains an address
+    #[cfg(test)]
+    pub fn without_carrier(mut self, interface: &str) -> Self {
+        if let Some(device) = self.device_mut(interface) {
+            device.carrier = false;
+            device.info.state = DeviceState::Unavailable;
+            device.ip4 = Ip4Config::default();
+        }
+        self
+    }
+
+    /// Make switching devices to DHCP fail
+    #[cfg(test)]
+    pub fn with_failing_set_dhcp(mut self) -> Self {
+        self.set_dhcp_fails = true;
+        self
+    }
+
+    /// Add a wired profile bound to no device, active on the given one
+    #[cfg(test)]
+    pub fn with_unbound_profile(mut self, id: &str, active_on: Option<&str>) -> Self {
+        self.next_uuid += 1;
+        let uuid = format!("00000000-0000-4000-8000-{:012x}", self.next_uuid);
+
+        let settings = wired_settings(id, &uuid, "auto", &Ip4Config::default(), None);
+        self.connections.push(FakeConnection {
+            info: ConnectionInfo {
+                id: id.to_string(),
+                uuid,
+                kind: "802-3-ethernet".into(),
+                mode: String::new(),
+                ssid: None,
+            },
+            settings,
+            active_on: active_on.map(str::to_string),
+        });
+        self
+    }
+
+    /// Add a profile bound to a device and activate it
+    fn add_connection(
+        &mut self,
+        id: &str,
+        kind: &str,
+        method: &str,
+        ip4: &Ip4Config,
+        interface: &str,
+    ) -> ConnectionInfo {
+        self.next_uuid += 1;
+        let uuid = format!("00000000-0000-4000-8000-{:012x}", self.next_uuid);
+
+        let settings = wired_settings(id, &uuid, method, ip4, Some(interface));
+
+        let info = ConnectionInfo {
+            id: id.to_string(),
+            uuid,
+            kind: kind.to_string(),
+            mode: String::new(),
+            ssid: None,
+        };
+
+        self.connections.push(FakeConnection {
+            info: info.clone(),
+            settings,
+            active_on: None,
+        });
+        self.bring_up(&info.uuid);
+        info
+    }
+
+    /// Activate a profile on the device it is bound to
+    fn bring_up(&mut self, uuid: &str) {
+        let Some(connection) = self.connections.iter_mut().find(|c| c.info.uuid == uuid) else {
+            return;
+        };
+
+        let interface = connection
+            .property("connection", "interface-name")
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let ip4 = connection.ip4();
+        connection.active_on = Some(interface.clone());
+
+        if let Some(device) = self.device_mut(&interface).filter(|device| device.carrier) {
+            device.info.state = DeviceState::Activated;
+            device.ip4 = ip4;
+        }
+    }
+
+    fn device_mut(&mut self, interface: &str) -> Option<&mut FakeDevice> {
+        self.devices.iter_mut().find(|d| d.info.interface == interface)
+    }
+
+    fn device(&self, interface: &str) -> Result<&FakeDevice> {
+        self.devices
+            .iter()
+            .find(|d| d.info.interface == interface)
+            .ok_or_else(|| AppError::DeviceNotFound(interface.to_string()))
+    }
+
+    fn device_of_type(&self, interface: &str, device_type: DeviceType) -> Result<&FakeDevice> {
+        let device = self.device(interface)?;
+        if device.info.device_type != device_type {
+            return Err(match device_type {
+                DeviceType::WiFi => AppError::NotAWiFiDevice(interface.to_string()),
+                _ => AppError::NotAnEthernetDevice(interface.to_string()),
+            });
+        }
+        Ok(device)
+    }
+
+    fn connection(&self, uuid: &str) -> Result<&FakeConnection> {
+        self.connections
+            .iter()
+            .find(|c| c.info.uuid == uuid)
+            .ok_or_else(|| AppError::ConnectionNotFound(uuid.to_string()))
+    }
+
+    /// Deactivate a profile, leaving its device disconnected
+    fn take_down(&mut self, uuid: &str) -> Result<()> {
+        let connection = self
+            .connections
+            .iter_mut()
+            .find(|c| c.info.uuid == uuid)
+            .ok_or_else(|| AppError::ConnectionNotFound(uuid.to_string()))?;
+
+        if let Some(interface) = connection.active_on.take() {
+            if let Some(device) = self.device_mut(&interface) {
+                device.info.state = match device.carrier {
+                    true => DeviceState::Disconnected,
+                    false => DeviceState::Unavailable,
+                };
+                device.ip4 = Ip4Config::default();
+                device.lease_time = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl NetworkBackend for FakeBackend {
+    fn start_service(&mut self) -> Result<()> {
+        info!("Using fake network backend");
+        Ok(())
+    }
+
+    fn get_devices(&self) -> Result<Vec<DeviceInfo>> {
+        Ok(self.devices.iter().map(|d| d.info.clone()).collect())
+    }
+
+    fn get_device(&self, interface: &str) -> Result<DeviceInfo> {
+        Ok(self.device(interface)?.info.clone())
+    }
+
+    fn get_hw_address(&self, interface: &str) -> Result<Option<String>> {
+        Ok(Some(self.device(interface)?.hw_address.clone()))
+    }
+
+    fn get_carrier(&self, interface: &str) -> Result<bool> {
+        Ok(self.device(interface)?.carrier)
+    }
+
+    fn get_ip4_config(&self, interface: &str) -> Result<Ip4Config> {
+        Ok(self.device(interface)?.ip4.clone())
+    }
+
+    fn get_dhcp_lease_time(&self, interface: &str) -> Result<Option<u64>> {
+        Ok(self.device(interface)?.lease_time)
+    }
+
+    fn get_connections(&self) -> Result<Vec<ConnectionInfo>> {
+        Ok(self.connections.iter().map(|c| c.info.clone()).collect())
+    }
+
+    fn get_active_device(&self, uuid: &str) -> Result<Option<String>> {
+        Ok(self.connection(uuid)?.active_on.clone())
+    }
+
+    fn get_profile(&self, uuid: &str) -> Result<ProfileSettings> {
+        let connection = self.connection(uuid)?;
+        Ok(ProfileSettings {
+            id: connection.info.id.clone(),
+            uuid: connection.info.uuid.clone(),
+            method: connection
+                .property("ipv4", "method")
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            settings: connection.settings.clone(),
+        })
+    }
+
+    fn add_profile(&mut self, profile: &ProfileSettings) -> Result<()> {
+        self.connections.push(FakeConnection {
+            info: ConnectionInfo {
+                id: profile.id.clone(),
+                uuid: profile.uuid.clone(),
+                kind: "802-3-ethernet".into(),
+                mode: String::new(),
+                ssid: None,
+            },
+            settings: profile.settings.clone(),
+            active_on: None,
+        });
+        Ok(())
+    }
+
+    fn activate_connection(&mut self, uuid: &str) -> Result<()> {
+        self.connection(uuid)?;
+        self.bring_up(uuid);
+        Ok(())
+    }
+
+    fn deactivate_connection(&mut self, uuid: &str) -> Result<()> {
+        self.take_down(uuid)
+    }
+
+    fn delete_connection(&mut self, uuid: &str) -> Result<()> {
+        self.take_down(uuid)?;
+        self.connections.retain(|c| c.info.uuid != uuid);
+        Ok(())
+    }
+
+    fn create_hotspot(
+        &mut self,
+        interface: &str,
+        ssid: &str,
+        _passphrase: Option<&str>,
+        gateway: Ipv4Addr,
+    ) -> Result<ConnectionInfo> {
+        self.device_of_type(interface, DeviceType::WiFi)?;
+
+        let ip4 = Ip4Config {
+            addresses: vec![format!("{}/24", gateway)],
+            ..Ip4Config::default()
+        };
+        let mut info = self.add_connection(ssid, "802-11-wireless", "shared", &ip4, interface);
+        info.mode = "ap".into();
+        info.ssid = Some(ssid.to_string());
+
+        if let Some(connection) = self.connections.last_mut() {
+            connection.info = info.clone();
+        }
+        Ok(info)
+    }
+
+    fn set_dhcp(&mut self, interface: &str) -> Result<()> {
+        self.device_of_type(interface, DeviceType::Ethernet)?;
+        if self.set_dhcp_fails {
+            return Err(AppError::SetDhcp("simulated failure".into()));
+        }
+
+        let lease = Ip4Config {
+            addresses: vec!["10.0.0.50/24".into()],
+            gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            dns: vec![Ipv4Addr::new(10, 0, 0, 1)],
+        };
+        self.add_connection("Wired connection 1", "802-3-ethernet", "auto", &lease, interface);
+
+        if let Some(device) = self.device_mut(interface).filter(|device| device.carrier) {
+            device.lease_time = Some(FAKE_LEASE_TIME);
+        }
+        Ok(())
+    }
+
+    fn set_static(&mut self, interface: &str, static_config: &StaticConfig) -> Result<()> {
+        self.device_of_type(interface, DeviceType::Ethernet)?;
+
+        let ip4 = Ip4Config {
+            addresses: vec![format!("{}/{}", static_config.address, static_config.prefix)],
+            gateway: static_config.gateway,
+            dns: static_config.dns.clone(),
+        };
+        let id = format!("{} static", interface);
+        self.add_connection(&id, "802-3-ethernet", "manual", &ip4, interface);
+        Ok(())
+    }
+}
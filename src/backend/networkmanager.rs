@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use network_manager::{Connection, Device, NetworkManager, ServiceState};
+use tracing::{debug, info};
+use zbus::blocking;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{
+    Array, Dict, ObjectPath, OwnedObjectPath, OwnedValue, Signature, StructureBuilder, Value,
+};
+
+use crate::backend::{
+    ConnectionInfo, DeviceInfo, DeviceState, DeviceType, Ip4Config, NetworkBackend,
+    ProfileSettings, SettingValue, Settings,
+};
+use crate::errors::{AppError, Result};
+use crate::network::StaticConfig;
+
+const NM_SERVICE: &str = "org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+const NM_SETTINGS: &str = "org.freedesktop.NetworkManager.Settings";
+const NM_SETTINGS_CONNECTION: &str = "org.freedesktop.NetworkManager.Settings.Connection";
+const NM_DEVICE: &str = "org.freedesktop.NetworkManager.Device";
+const NM_WIRED: &str = "org.freedesktop.NetworkManager.Device.Wired";
+const NM_IP4_CONFIG: &str = "org.freedesktop.NetworkManager.IP4Config";
+const NM_DHCP4_CONFIG: &str = "org.freedesktop.NetworkManager.DHCP4Config";
+const NM_ACTIVE_CONNECTION: &str = "org.freedesktop.NetworkManager.Connection.Active";
+
+/// Connection settings (`a{sa{sv}}`) as exchanged with NetworkManager
+type DbusSettings = HashMap<String, HashMap<String, OwnedValue>>;
+
+/// Backend talking to NetworkManager over D-Bus
+pub struct NetworkManagerBackend {
+    manager: NetworkManager,
+}
+
+impl NetworkManagerBackend {
+    pub fn new() -> Self {
+        let manager = NetworkManager::new();
+        debug!("NetworkManager initialized");
+        Self { manager }
+    }
+
+    fn find_device(&self, interface: &str) -> Result<Device> {
+        self.manager
+            .get_device_by_interface(interface)
+            .map_err(|_| AppError::DeviceNotFound(interface.to_string()))
+    }
+
+    fn find_connection(&self, uuid: &str) -> Result<Connection> {
+        self.manager
+            .get_connections()?
+            .into_iter()
+            .find(|conn| conn.settings().uuid == uuid)
+            .ok_or_else(|| AppError::ConnectionNotFound(uuid.to_string()))
+    }
+}
+
+impl NetworkBackend for NetworkManagerBackend {
+    fn start_service(&mut self) -> Result<()> {
+        let Ok(state) = NetworkManager::get_service_state() else {
+            info!("Cannot get NetworkManager state, assuming it's running");
+            return Ok(());
+        };
+
+        if state == ServiceState::Active {
+            debug!("NetworkManager already running");
+            return Ok(());
+        }
+
+        info!("Starting NetworkManager service...");
+        let state = NetworkManager::start_service(15)
+            .map_err(|e| AppError::StartNetworkManager(e.to_string()))?;
+
+        if state != ServiceState::Active {
+            return Err(AppError::StartActiveNetworkManager);
+        }
+
+        info!("NetworkManager started");
+        Ok(())
+    }
+
+    fn get_devices(&self) -> Result<Vec<DeviceInfo>> {
+        self.manager.get_devices()?.iter().map(device_info).collect()
+    }
+
+    fn get_device(&self, interface: &str) -> Result<DeviceInfo> {
+        device_info(&self.find_device(interface)?)
+    }
+
+    fn get_hw_address(&self, interface: &str) -> Result<Option<String>> {
+        Ok(device_hw_address(interface)?)
+    }
+
+    fn get_carrier(&self, interface: &str) -> Result<bool> {
+        Ok(device_carrier(interface)?)
+    }
+
+    fn get_ip4_config(&self, interface: &str) -> Result<Ip4Config> {
+        Ok(device_ip4_config(interface)?)
+    }
+
+    fn get_dhcp_lease_time(&self, interface: &str) -> Result<Option<u64>> {
+        Ok(device_dhcp_lease_time(interface)?)
+    }
+
+    fn get_connections(&self) -> Result<Vec<ConnectionInfo>> {
+        Ok(self
+            .manager
+            .get_connections()?
+            .iter()
+            .map(connection_info)
+            .collect())
+    }
+
+    fn get_active_device(&self, uuid: &str) -> Result<Option<String>> {
+        Ok(connection_device(uuid)?)
+    }
+
+    fn get_profile(&self, uuid: &str) -> Result<ProfileSettings> {
+        let info = connection_info(&self.find_connection(uuid)?);
+        let settings = profile_settings(&connection_settings(uuid)?)?;
+        let method = settings
+            .get("ipv4")
+            .and_then(|ipv4| ipv4.get("method"))
+            .and_then(|method| method.value.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        Ok(ProfileSettings {
+            id: info.id,
+            uuid: info.uuid,
+            method,
+            settings,
+        })
+    }
+
+    fn add_profile(&mut self, profile: &ProfileSettings) -> Result<()> {
+        let settings = dbus_settings(&profile.settings)?;
+
+        let connection = blocking::Connection::system()?;
+        let manager =
+            blocking::Proxy::new(&connection, NM_SERVICE, NM_SETTINGS_PATH, NM_SETTINGS)?;
+        let _: OwnedObjectPath = manager.call("AddConnection", &(settings,))?;
+        Ok(())
+    }
+
+    fn activate_connection(&mut self, uuid: &str) -> Result<()> {
+        self.find_connection(uuid)?.activate()?;
+        Ok(())
+    }
+
+    fn deactivate_connection(&mut self, uuid: &str) -> Result<()> {
+        self.find_connection(uuid)?.deactivate()?;
+        Ok(())
+    }
+
+    fn delete_connection(&mut self, uuid: &str) -> Result<()> {
+        self.find_connection(uuid)?.delete()?;
+        Ok(())
+    }
+
+    fn create_hotspot(
+        &mut self,
+        interface: &str,
+        ssid: &str,
+        passphrase: Option<&str>,
+        gateway: Ipv4Addr,
+    ) -> Result<ConnectionInfo> {
+        let device = self.find_device(interface)?;
+        let wifi = device
+            .as_wifi_device()
+            .ok_or_else(|| AppError::NotAWiFiDevice(interface.to_string()))?;
+
+        let (connection, _) = wifi
+            .create_hotspot(ssid, passphrase, Some(gateway))
+            .map_err(|e| AppError::CreateCaptivePortal(e.to_string()))?;
+
+        Ok(connection_info(&connection))
+    }
+
+    fn set_dhcp(&mut self, interface: &str) -> Result<()> {
+        let device = self.find_device(interface)?;
+        let ethernet = device
+            .as_ethernet_device()
+            .ok_or_else(|| AppError::NotAnEthernetDevice(interface.to_string()))?;
+
+        ethernet
+            .set_dhcp()
+            .map_err(|e| AppError::SetDhcp(e.to_string()))
+    }
+
+    fn set_static(&mut self, interface: &str, static_config: &StaticConfig) -> Result<()> {
+        if self.find_device(interface)?.as_ethernet_device().is_none() {
+            return Err(AppError::NotAnEthernetDevice(interface.to_string()));
+        }
+
+        add_static_connection(interface, static_config)
+            .map_err(|e| AppError::SetStatic(e.to_string()))
+    }
+}
+
+/// Proxy for an object of NetworkManager that reads properties on demand
+fn nm_proxy(
+    connection: &blocking::Connection,
+    path: impl TryInto<ObjectPath<'static>, Error = impl Into<zbus::Error>>,
+    interface: &'static str,
+) -> zbus::Result<blocking::Proxy<'static>> {
+    blocking::proxy::Builder::new(connection)
+        .destination(NM_SERVICE)?
+        .path(path)?
+        .interface(interface)?
+        .cache_properties(CacheProperties::No)
+        .build()
+}
+
+/// Object path of the device with the given interface name
+fn device_path(
+    connection: &blocking::Connection,
+    interface: &str,
+) -> zbus::Result<OwnedObjectPath> {
+    nm_proxy(connection, NM_PATH, NM_SERVICE)?.call("GetDeviceByIpIface", &(interface,))
+}
+
+/// Proxy for a configuration object a device links to, if it has one
+fn device_config(
+    connection: &blocking::Connection,
+    interface: &str,
+    property: &str,
+    config_interface: &'static str,
+) -> zbus::Result<Option<blocking::Proxy<'static>>> {
+    let device = nm_proxy(connection, device_path(connection, interface)?, NM_DEVICE)?;
+    let path: OwnedObjectPath = device.get_property(property)?;
+
+    // Devices without the configuration link to the root object
+    if path.as_str() == "/" {
+        return Ok(None);
+    }
+    nm_proxy(connection, path, config_interface).map(Some)
+}
+
+/// Permanent hardware address of a wired device, or the current one of
+/// other devices and devices without a permanent address
+fn device_hw_address(interface: &str) -> zbus::Result<Option<String>> {
+    let connection = blocking::Connection::system()?;
+    let path = device_path(&connection, interface)?;
+
+    let wired = nm_proxy(&connection, path.clone(), NM_WIRED)?;
+    if let Ok(address) = wired.get_property::<String>("PermHwAddress") {
+        if !address.is_empty() {
+            return Ok(Some(address));
+        }
+    }
+
+    let address: String = nm_proxy(&connection, path, NM_DEVICE)?.get_property("HwAddress")?;
+    Ok(Some(address).filter(|address| !address.is_empty()))
+}
+
+/// Whether the wired device reports a carrier
+fn device_carrier(interface: &str) -> zbus::Result<bool> {
+    let connection = blocking::Connection::system()?;
+    nm_proxy(&connection, device_path(&connection, interface)?, NM_WIRED)?.get_property("Carrier")
+}
+
+/// IPv4 addresses, gateway and DNS servers applied to a device
+fn device_ip4_config(interface: &str) -> zbus::Result<Ip4Config> {
+    let connection = blocking::Connection::system()?;
+    let Some(config) = device_config(&connection, interface, "Ip4Config", NM_IP4_CONFIG)? else {
+        return Ok(Ip4Config::default());
+    };
+
+    let addresses: Vec<HashMap<String, OwnedValue>> = config.get_property("AddressData")?;
+    let gateway: String = config.get_property("Gateway")?;
+    let nameservers: Vec<HashMap<String, OwnedValue>> = config.get_property("NameserverData")?;
+
+    Ok(Ip4Config {
+        addresses: addresses
+            .iter()
+            .filter_map(|address| {
+                let prefix: u32 = address.get("prefix")?.downcast_ref().ok()?;
+                let address: &str = address.get("address")?.downcast_ref().ok()?;
+                Some(format!("{}/{}", address, prefix))
+            })
+            .collect(),
+        gateway: gateway.parse().ok(),
+        dns: nameservers
+            .iter()
+            .filter_map(|nameserver| {
+                let address: &str = nameserver.get("address")?.downcast_ref().ok()?;
+                address.parse().ok()
+            })
+            .collect(),
+    })
+}
+
+/// Lease time reported in the DHCPv4 options of a device
+fn device_dhcp_lease_time(interface: &str) -> zbus::Result<Option<u64>> {
+    let connection = blocking::Connection::system()?;
+    let Some(config) = device_config(&connection, interface, "Dhcp4Config", NM_DHCP4_CONFIG)?
+    else {
+        return Ok(None);
+    };
+
+    let options: HashMap<String, OwnedValue> = config.get_property("Options")?;
+    Ok(options
+        .get("dhcp_lease_time")
+        .and_then(|value| value.downcast_ref::<&str>().ok()?.trim().parse().ok()))
+}
+
+/// Device a connection profile is active on
+fn connection_device(uuid: &str) -> zbus::Result<Option<String>> {
+    let connection = blocking::Connection::system()?;
+    let manager = nm_proxy(&connection, NM_PATH, NM_SERVICE)?;
+
+    let active: Vec<OwnedObjectPath> = manager.get_property("ActiveConnections")?;
+    for path in active {
+        let active = nm_proxy(&connection, path, NM_ACTIVE_CONNECTION)?;
+        if active.get_property::<String>("Uuid")? != uuid {
+            continue;
+        }
+
+        let devices: Vec<OwnedObjectPath> = active.get_property("Devices")?;
+        let Some(device) = devices.into_iter().next() else {
+            return Ok(None);
+        };
+        let interface = nm_proxy(&connection, device, NM_DEVICE)?.get_property("Interface")?;
+        return Ok(Some(interface));
+    }
+
+    Ok(None)
+}
+
+/// Create and activate a wired profile with a static IPv4 configuration
+///
+/// The profile is added with `AddAndActivateConnection`, which the D-Bus
+/// crate does not wrap.
+fn add_static_connection(interface: &str, config: &StaticConfig) -> zbus::Result<()> {
+    let connection = blocking::Connection::system()?;
+    let manager = blocking::Proxy::new(&connection, NM_SERVICE, NM_PATH, NM_SERVICE)?;
+
+    let device: OwnedObjectPath = manager.call("GetDeviceByIpIface", &(interface,))?;
+    let _: (OwnedObjectPath, OwnedObjectPath) = manager.call(
+        "AddAndActivateConnection",
+        &(static_settings(interface, config), &device, ObjectPath::from_static_str_unchecked("/")),
+    )?;
+    Ok(())
+}
+
+/// Connection settings (`a{sa{sv}}`) of a static wired profile
+fn static_settings<'a>(
+    interface: &'a str,
+    config: &StaticConfig,
+) -> HashMap<&'static str, HashMap<&'static str, Value<'a>>> {
+    let connection = HashMap::from([
+        ("id", Value::from(format!("{} static", interface))),
+        ("type", Value::from("802-3-ethernet")),
+        ("interface-name", Value::from(interface)),
+        ("autoconnect", Value::from(true)),
+    ]);
+
+    let address = HashMap::from([
+        ("address", Value::from(config.address.to_string())),
+        ("prefix", Value::from(u32::from(config.prefix))),
+    ]);
+    // Addresses in network byte order
+    let dns: Vec<u32> = config
+        .dns
+        .iter()
+        .map(|dns| u32::from_ne_bytes(dns.octets()))
+        .collect();
+
+    let mut ipv4 = HashMap::from([
+        ("method", Value::from("manual")),
+        ("address-data", Value::from(vec![address])),
+        ("dns", Value::from(dns)),
+    ]);
+    if let Some(gateway) = config.gateway {
+        ipv4.insert("gateway", Value::from(gateway.to_string()));
+    }
+
+    HashMap::from([
+        ("connection", connection),
+        ("802-3-ethernet", HashMap::new()),
+        ("ipv4", ipv4),
+    ])
+}
+
+/// Every setting of a connection profile, including its 802.1X secrets
+///
+/// Secrets are left out of `GetSettings` and only merged in when
+/// NetworkManager hands them out.
+fn connection_settings(uuid: &str) -> zbus::Result<DbusSettings> {
+    let connection = blocking::Connection::system()?;
+    let settings = blocking::Proxy::new(&connection, NM_SERVICE, NM_SETTINGS_PATH, NM_SETTINGS)?;
+
+    let path: OwnedObjectPath = settings.call("GetConnectionByUuid", &(uuid,))?;
+    let profile = blocking::Proxy::new(&connection, NM_SERVICE, path, NM_SETTINGS_CONNECTION)?;
+    let mut values: DbusSettings = profile.call("GetSettings", &())?;
+
+    if values.contains_key("802-1x") {
+        match profile.call::<_, _, DbusSettings>("GetSecrets", &("802-1x",)) {
+            Ok(secrets) => {
+                for (setting, properties) in secrets {
+                    values.entry(setting).or_default().extend(properties);
+                }
+            },
+            Err(e) => debug!("Cannot read 802.1X secrets of {}: {}", uuid, e),
+        }
+    }
+
+    Ok(values)
+}
+
+/// Convert D-Bus settings to values that can be saved as JSON
+///
+/// A value serializes with its signature, which is what allows
+/// [`dbus_settings`] to rebuild it with the exact same D-Bus types.
+fn profile_settings(values: &DbusSettings) -> Result<Settings> {
+    let mut settings = Settings::new();
+
+    for (setting, properties) in values {
+        for (property, value) in properties {
+            let value = serde_json::to_value(&**value)
+                .and_then(serde_json::from_value::<SettingValue>)
+                .map_err(|e| {
+                    AppError::ProfileSetting(format!("{}.{}: {}", setting, property, e))
+                })?;
+            settings
+                .entry(setting.clone())
+                .or_default()
+                .insert(property.clone(), value);
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Convert saved settings back to their D-Bus values
+fn dbus_settings(settings: &Settings) -> Result<HashMap<&str, HashMap<&str, Value<'static>>>> {
+    settings
+        .iter()
+        .map(|(setting, properties)| {
+            let properties = properties
+                .iter()
+                .map(|(property, value)| {
+                    let value = setting_value(value).ok_or_else(|| {
+                        AppError::ProfileSetting(format!("{}.{}", setting, property))
+                    })?;
+                    Ok((property.as_str(), value))
+                })
+                .collect::<Result<_>>()?;
+            Ok((setting.as_str(), properties))
+        })
+        .collect()
+}
+
+fn setting_value(value: &SettingValue) -> Option<Value<'static>> {
+    let signature: Signature = value.signature.parse().ok()?;
+    dbus_value(&signature, &value.value)
+}
+
+/// Build a D-Bus value of the given type from its JSON serialization
+fn dbus_value(signature: &Signature, json: &serde_json::Value) -> Option<Value<'static>> {
+    let value = match signature {
+        Signature::U8 => Value::U8(json.as_u64()?.try_into().ok()?),
+        Signature::Bool => Value::Bool(json.as_bool()?),
+        Signature::I16 => Value::I16(json.as_i64()?.try_into().ok()?),
+        Signature::U16 => Value::U16(json.as_u64()?.try_into().ok()?),
+        Signature::I32 => Value::I32(json.as_i64()?.try_into().ok()?),
+        Signature::U32 => Value::U32(json.as_u64()?.try_into().ok()?),
+        Signature::I64 => Value::I64(json.as_i64()?),
+        Signature::U64 => Value::U64(json.as_u64()?),
+        Signature::F64 => Value::F64(json.as_f64()?),
+        Signature::Str => Value::from(json.as_str()?.to_string()),
+        Signature::Signature => Value::Signature(json.as_str()?.parse().ok()?),
+        Signature::ObjectPath => {
+            Value::ObjectPath(ObjectPath::try_from(json.as_str()?.to_string()).ok()?)
+        },
+        Signature::Variant => {
+            let inner = serde_json::from_value(json.clone()).ok()?;
+            Value::Value(Box::new(setting_value(&inner)?))
+        },
+        Signature::Array(element) => {
+            let mut array = Array::new(element);
+            for item in json.as_array()? {
+                array.append(dbus_value(element, item)?).ok()?;
+            }
+            Value::Array(array)
+        },
+        Signature::Dict { key, value } => {
+            let mut dict = Dict::new(key, value);
+            for (name, item) in json.as_object()? {
+                // Object keys are strings whatever the key type
+                let name = match **key {
+                    Signature::Str | Signature::Signature | Signature::ObjectPath => {
+                        serde_json::Value::from(name.as_str())
+                    },
+                    _ => serde_json::from_str(name).ok()?,
+                };
+                dict.append(dbus_value(key, &name)?, dbus_value(value, item)?)
+                    .ok()?;
+            }
+            Value::Dict(dict)
+        },
+        Signature::Structure(fields) => {
+            let items = json.as_array()?;
+            if items.len() != fields.iter().count() {
+                return None;
+            }
+
+            let mut structure = StructureBuilder::new();
+            for (field, item) in fields.iter().zip(items) {
+                structure = structure.append_field(dbus_value(field, item)?);
+            }
+            Value::Structure(structure.build().ok()?)
+        },
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn device_info(device: &Device) -> Result<DeviceInfo> {
+    let device_type = match device.device_type() {
+        network_manager::DeviceType::Ethernet => DeviceType::Ethernet,
+        network_manager::DeviceType::WiFi => DeviceType::WiFi,
+        _ => DeviceType::Other,
+    };
+
+    Ok(DeviceInfo {
+        interface: device.interface().to_string(),
+        device_type,
+        state: device_state(device.get_state()?),
+    })
+}
+
+fn device_state(state: network_manager::DeviceState) -> DeviceState {
+    use network_manager::DeviceState as Nm;
+
+    match state {
+        Nm::Unknown => DeviceState::Unknown,
+        Nm::Unmanaged => DeviceState::Unmanaged,
+        Nm::Unavailable => DeviceState::Unavailable,
+        Nm::Disconnected => DeviceState::Disconnected,
+        Nm::Prepare => DeviceState::Prepare,
+        Nm::Config => DeviceState::Config,
+        Nm::NeedAuth => DeviceState::NeedAuth,
+        Nm::IpConfig => DeviceState::IpConfig,
+        Nm::IpCheck => DeviceState::IpCheck,
+        Nm::Secondaries => DeviceState::Secondaries,
+        Nm::Activated => DeviceState::Activated,
+        Nm::Deactivating => DeviceState::Deactivating,
+        Nm::Failed => DeviceState::Failed,
+    }
+}
+
+fn connection_info(connection: &Connection) -> ConnectionInfo {
+    let settings = connection.settings();
+    let ssid = settings
+        .ssid
+        .as_str()
+        .ok()
+        .filter(|ssid| !ssid.is_empty())
+        .map(str::to_string);
+
+    ConnectionInfo {
+        id: settings.id.clone(),
+        uuid: settings.uuid.clone(),
+        kind: settings.kind.clone(),
+        mode: settings.mode.clone(),
+        ssid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(value: impl Into<Value<'static>>) -> OwnedValue {
+        OwnedValue::try_from(value.into()).unwrap()
+    }
+
+    fn dict(entries: Vec<(&str, Value<'static>)>) -> HashMap<String, Value<'static>> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+
+    fn setting(entries: Vec<(&str, OwnedValue)>) -> HashMap<String, OwnedValue> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+
+    /// Settings covering the D-Bus types NetworkManager uses in profiles
+    fn wired_profile() -> DbusSettings {
+        let address = dict(vec![
+            ("address", Value::from("192.168.1.50")),
+            ("prefix", Value::from(24u32)),
+        ]);
+        let route = dict(vec![
+            ("dest", Value::from("10.0.0.0")),
+            ("prefix", Value::from(8u32)),
+            ("metric", Value::from(100u32)),
+        ]);
+        let ipv6_address = (vec![0xfdu8; 16], 64u32, vec![0u8; 16]);
+
+        let connection = setting(vec![
+            ("id", owned("Wired connection 1")),
+            ("uuid", owned("00000000-0000-4000-8000-000000000001")),
+            ("type", owned("802-3-ethernet")),
+            ("autoconnect", owned(true)),
+            ("autoconnect-priority", owned(-5i32)),
+            ("timestamp", owned(1_700_000_000u64)),
+            ("permissions", owned(Vec::<String>::new())),
+        ]);
+        let ethernet = setting(vec![
+            ("mac-address", owned(vec![2u8, 0, 0, 0, 0, 10])),
+            ("mtu", owned(1500u32)),
+            ("s390-options", owned(HashMap::from([("portname", "eth0")]))),
+        ]);
+        let ipv4 = setting(vec![
+            ("method", owned("manual")),
+            ("addresses", owned(vec![vec![0x3201a8c0u32, 24, 0x0101a8c0]])),
+            ("address-data", owned(vec![address])),
+            ("route-data", owned(vec![route])),
+            ("dns", owned(vec![0x0101a8c0u32])),
+            ("dns-search", owned(vec!["example.com"])),
+            ("route-metric", owned(-1i64)),
+        ]);
+        let ipv6 = setting(vec![
+            ("method", owned("manual")),
+            ("addresses", owned(vec![ipv6_address])),
+        ]);
+        let dot1x = setting(vec![
+            ("eap", owned(vec!["peap"])),
+            ("identity", owned("portal")),
+            ("password", owned("secret")),
+        ]);
+
+        DbusSettings::from([
+            ("connection".to_string(), connection),
+            ("802-3-ethernet".to_string(), ethernet),
+            ("ipv4".to_string(), ipv4),
+            ("ipv6".to_string(), ipv6),
+            ("802-1x".to_string(), dot1x),
+        ])
+    }
+
+    #[test]
+    fn settings_round_trip_through_saved_json() {
+        let profile = wired_profile();
+
+        let saved = serde_json::to_string(&profile_settings(&profile).unwrap()).unwrap();
+        let settings: Settings = serde_json::from_str(&saved).unwrap();
+        let restored = dbus_settings(&settings).unwrap();
+
+        assert_eq!(restored.len(), profile.len());
+        for (setting, properties) in &profile {
+            let restored = &restored[setting.as_str()];
+            assert_eq!(restored.len(), properties.len(), "{}", setting);
+
+            for (property, value) in properties {
+                let value: &Value = value;
+                assert_eq!(&restored[property.as_str()], value, "{}.{}", setting, property);
+                assert_eq!(
+                    restored[property.as_str()].value_signature(),
+                    value.value_signature(),
+                    "{}.{}",
+                    setting,
+                    property
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn setting_value_rejects_mismatched_type() {
+        let value = SettingValue {
+            signature: "u".into(),
+            value: serde_json::json!("not a number"),
+        };
+        assert!(setting_value(&value).is_none());
+
+        let value = SettingValue {
+            signature: "y".into(),
+            value: serde_json::json!(256),
+        };
+        assert!(setting_value(&value).is_none());
+    }
+}
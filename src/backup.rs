@@ -0,0 +1,206 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::backend::{create_backend, ProfileSettings};
+use crate::config::{BackupCommand, Config};
+use crate::errors::{AppError, Result};
+
+const BACKUP_DIRECTORY: &str = "backups";
+
+/// Connection profile saved before it was deleted
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+    pub created: DateTime<Utc>,
+    pub interface: String,
+    pub profile: ProfileSettings,
+}
+
+/// Write a profile to a timestamped file in the backup directory
+///
+/// The file is only readable by its owner, as the profile can hold secrets.
+pub fn save_backup(
+    state_directory: &Path,
+    interface: &str,
+    profile: &ProfileSettings,
+) -> Result<PathBuf> {
+    let directory = state_directory.join(BACKUP_DIRECTORY);
+    fs::create_dir_all(&directory)?;
+
+    let backup = Backup {
+        created: Utc::now(),
+        interface: interface.to_string(),
+        profile: profile.clone(),
+    };
+
+    let name = format!(
+        "{}-{}.json",
+        backup.created.format("%Y%m%dT%H%M%SZ"),
+        profile.uuid
+    );
+    let path = directory.join(name);
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?
+        .write_all(&serde_json::to_vec_pretty(&backup)?)?;
+
+    Ok(path)
+}
+
+/// Saved backups with their names, oldest first
+pub fn list_backups(state_directory: &Path) -> Result<Vec<(String, Backup)>> {
+    let directory = state_directory.join(BACKUP_DIRECTORY);
+    if !directory.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let name = backup_name(&path);
+            backups.push((name, read_backup(&path)?));
+        }
+    }
+
+    backups.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(backups)
+}
+
+/// Load a backup by the name shown in the backup list
+pub fn load_backup(state_directory: &Path, name: &str) -> Result<Backup> {
+    // Names are plain file names inside the backup directory
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(AppError::InvalidBackupName(name.to_string()));
+    }
+
+    let path = state_directory
+        .join(BACKUP_DIRECTORY)
+        .join(format!("{}.json", name));
+
+    if !path.is_file() {
+        return Err(AppError::BackupNotFound(name.to_string()));
+    }
+
+    read_backup(&path)
+}
+
+fn read_backup(path: &Path) -> Result<Backup> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+fn backup_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Run a `backups` subcommand
+pub fn run_backup_command(command: &BackupCommand, config: &Config) -> Result<()> {
+    match command {
+        BackupCommand::List => {
+            let backups = list_backups(&config.state_directory)?;
+            if backups.is_empty() {
+                println!("No backups in {}", config.state_directory.display());
+            }
+
+            for (name, backup) in backups {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    name,
+                    backup.created.to_rfc3339(),
+                    backup.interface,
+                    backup.profile.id,
+                    backup.profile.method
+                );
+            }
+            Ok(())
+        },
+        BackupCommand::Restore { name } => {
+            let backup = load_backup(&config.state_directory, name)?;
+            restore_backup(&backup, config)
+        },
+    }
+}
+
+/// Recreate and activate a saved profile
+fn restore_backup(backup: &Backup, config: &Config) -> Result<()> {
+    let mut backend = create_backend(config);
+    backend.start_service()?;
+
+    let profile = &backup.profile;
+
+    // Replace a profile that still exists under the same UUID
+    if backend
+        .get_connections()?
+        .iter()
+        .any(|conn| conn.uuid == profile.uuid)
+    {
+        info!("Replacing existing connection '{}'", profile.id);
+        backend.delete_connection(&profile.uuid)?;
+    }
+
+    backend.add_profile(profile)?;
+    backend.activate_connection(&profile.uuid)?;
+
+    info!("Restored connection '{}' on {}", profile.id, backup.interface);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> ProfileSettings {
+        ProfileSettings {
+            id: "Wired static eth0".into(),
+            uuid: "00000000-0000-4000-8000-000000000001".into(),
+            method: "manual".into(),
+            settings: Default::default(),
+        }
+    }
+
+    #[test]
+    fn saved_backup_loads_by_listed_name() {
+        let state_directory = Config::for_tests(&[]).state_directory;
+        save_backup(&state_directory, "eth0", &profile()).unwrap();
+
+        let backups = list_backups(&state_directory).unwrap();
+        assert_eq!(backups.len(), 1);
+
+        let backup = load_backup(&state_directory, &backups[0].0).unwrap();
+        assert_eq!(backup.interface, "eth0");
+        assert_eq!(backup.profile.uuid, profile().uuid);
+    }
+
+    #[test]
+    fn load_backup_rejects_paths() {
+        let state_directory = Config::for_tests(&[]).state_directory;
+
+        for name in ["", "../audit", "..", "a/b", "/etc/passwd", "a\\b"] {
+            assert!(
+                matches!(
+                    load_backup(&state_directory, name),
+                    Err(AppError::InvalidBackupName(_))
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn load_backup_reports_missing_backup() {
+        let state_directory = Config::for_tests(&[]).state_directory;
+        let result = load_backup(&state_directory, "20240101T000000Z-missing");
+        assert!(matches!(result, Err(AppError::BackupNotFound(_))));
+    }
+}
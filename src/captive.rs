@@ -0,0 +1,170 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::config::Config;
+use crate::tls::TlsMode;
+
+/// Path of the RFC 8908 captive portal API
+pub const CAPTIVE_API_PATH: &str = "/captive-portal/api";
+
+/// Media type of RFC 8908 captive portal API responses
+pub const CAPTIVE_API_CONTENT_TYPE: &str = "application/captive+json";
+
+/// Body Apple devices expect when they have internet access
+const APPLE_SUCCESS: &str = "<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>";
+
+/// Body Firefox expects from `canonical.html` when it has internet access
+const FIREFOX_CANONICAL: &str =
+    "<meta http-equiv=\"refresh\" content=\"0;url=https://support.mozilla.org/kb/captive-portal\"/>";
+
+/// Connectivity check of an operating system or browser
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// iOS and macOS (`captive.apple.com/hotspot-detect.html`)
+    Apple,
+    /// Android and ChromeOS (`connectivitycheck.gstatic.com/generate_204`)
+    Android,
+    /// Windows 10 and later (`www.msftconnecttest.com/connecttest.txt`)
+    WindowsConnectTest,
+    /// Older Windows NCSI (`www.msftncsi.com/ncsi.txt`)
+    WindowsNcsi,
+    /// Firefox (`detectportal.firefox.com/canonical.html`)
+    FirefoxCanonical,
+    /// Firefox (`detectportal.firefox.com/success.txt`)
+    FirefoxSuccess,
+}
+
+impl Probe {
+    /// Name of the probe in metrics
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Apple => "apple",
+            Self::Android => "android",
+            Self::WindowsConnectTest => "windows_connecttest",
+            Self::WindowsNcsi => "windows_ncsi",
+            Self::FirefoxCanonical => "firefox_canonical",
+            Self::FirefoxSuccess => "firefox_success",
+        }
+    }
+
+    /// Recognise a probe by its request path
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/hotspot-detect.html" | "/library/test/success.html" => Some(Self::Apple),
+            "/generate_204" | "/gen_204" => Some(Self::Android),
+            "/connecttest.txt" => Some(Self::WindowsConnectTest),
+            "/ncsi.txt" => Some(Self::WindowsNcsi),
+            "/canonical.html" => Some(Self::FirefoxCanonical),
+            "/success.txt" => Some(Self::FirefoxSuccess),
+            _ => None,
+        }
+    }
+
+    /// Response that tells the client it is online, closing the captive sheet
+    pub fn online_response(self) -> Response {
+        let (content_type, body) = match self {
+            Self::Android => return no_store(StatusCode::NO_CONTENT.into_response()),
+            Self::Apple => ("text/html", APPLE_SUCCESS),
+            Self::WindowsConnectTest => ("text/plain", "Microsoft Connect Test"),
+            Self::WindowsNcsi => ("text/plain", "Microsoft NCSI"),
+            Self::FirefoxCanonical => ("text/html", FIREFOX_CANONICAL),
+            Self::FirefoxSuccess => ("text/plain", "success\n"),
+        };
+
+        no_store(([(header::CONTENT_TYPE, content_type)], body).into_response())
+    }
+
+    /// Response that tells the client to open the portal
+    pub fn captive_response(self, portal_url: &str) -> Response {
+        no_store((StatusCode::FOUND, [(header::LOCATION, portal_url)]).into_response())
+    }
+}
+
+/// Keep clients from caching the result of a connectivity check
+fn no_store(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+    response
+}
+
+/// URL of the portal web UI, over HTTPS when the HTTPS listener serves it
+pub fn portal_url(config: &Config) -> String {
+    match config.tls_port {
+        Some(port) if config.tls_mode == TlsMode::Serve => url("https", config, port, 443, "/"),
+        _ => url("http", config, config.listening_port, 80, "/"),
+    }
+}
+
+/// URL of the RFC 8908 captive portal API, advertised with DHCP option 114
+///
+/// RFC 8908 requires HTTPS and clients ignore other URLs, so there is only a
+/// URL to advertise when the HTTPS listener serves the portal. In redirect
+/// mode it would only answer with a redirect.
+pub fn captive_api_url(config: &Config) -> Option<String> {
+    match config.tls_port {
+        Some(port) if config.tls_mode == TlsMode::Serve => {
+            Some(url("https", config, port, 443, CAPTIVE_API_PATH))
+        },
+        _ => None,
+    }
+}
+
+fn url(scheme: &str, config: &Config, port: u16, default_port: u16, path: &str) -> String {
+    if port == default_port {
+        format!("{}://{}{}", scheme, config.gateway, path)
+    } else {
+        format!("{}://{}:{}{}", scheme, config.gateway, port, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_use_http_without_tls() {
+        let config = Config::for_tests(&[]);
+        assert_eq!(portal_url(&config), "http://192.168.42.1/");
+        assert_eq!(captive_api_url(&config), None);
+    }
+
+    #[test]
+    fn urls_include_non_default_ports() {
+        let args = ["-o", "8080", "--tls-port", "8443", "--tls-mode", "serve"];
+        let config = Config::for_tests(&args);
+        assert_eq!(portal_url(&config), "https://192.168.42.1:8443/");
+        assert_eq!(
+            captive_api_url(&config).as_deref(),
+            Some("https://192.168.42.1:8443/captive-portal/api")
+        );
+    }
+
+    #[test]
+    fn urls_use_http_when_https_redirects() {
+        let config = Config::for_tests(&["-o", "8080", "--tls-port", "443"]);
+        assert_eq!(portal_url(&config), "http://192.168.42.1:8080/");
+        assert_eq!(captive_api_url(&config), None);
+    }
+
+    #[test]
+    fn urls_use_https_when_https_serves() {
+        let config = Config::for_tests(&["--tls-port", "443", "--tls-mode", "serve"]);
+        assert_eq!(portal_url(&config), "https://192.168.42.1/");
+        assert_eq!(
+            captive_api_url(&config).as_deref(),
+            Some("https://192.168.42.1/captive-portal/api")
+        );
+    }
+
+    #[test]
+    fn probes_are_recognised_by_path() {
+        assert_eq!(Probe::from_path("/hotspot-detect.html"), Some(Probe::Apple));
+        assert_eq!(Probe::from_path("/generate_204"), Some(Probe::Android));
+        assert_eq!(Probe::from_path("/"), None);
+        assert_eq!(Probe::Android.online_response().status(), StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::state::{rearm_activity, StateSender};
+
+/// Lease file dnsmasq keeps in the state directory
+pub const DNSMASQ_LEASE_FILE: &str = "dnsmasq.leases";
+
+/// How often leases are checked for expiry and the lease file is read
+const LEASE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Address handed to a client on the portal network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub hw_address: String,
+    pub address: Ipv4Addr,
+    pub hostname: Option<String>,
+    /// None for leases that never expire
+    pub expires: Option<SystemTime>,
+}
+
+/// Client on the portal network, as returned by `/clients`
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub hw_address: String,
+    pub address: Ipv4Addr,
+    pub hostname: Option<String>,
+    /// Unix time the lease expires
+    pub lease_expires: Option<u64>,
+    /// Unix time of the last HTTP request from the client
+    pub last_activity: Option<u64>,
+}
+
+#[derive(Default)]
+struct Table {
+    /// Leases by client hardware address
+    leases: HashMap<String, Lease>,
+    /// Last HTTP request by client address
+    activity: HashMap<IpAddr, SystemTime>,
+}
+
+/// Clients on the portal network, from DHCP leases and HTTP requests
+pub struct Clients {
+    table: Mutex<Table>,
+    /// Lease renewals count as activity
+    state: StateSender,
+}
+
+impl Clients {
+    pub fn new(state: StateSender) -> Self {
+        Self {
+            table: Mutex::new(Table::default()),
+            state,
+        }
+    }
+
+    /// Record a new or renewed lease
+    pub fn lease(&self, lease: Lease) {
+        let mut table = self.table();
+
+        if table.leases.get(&lease.hw_address) != Some(&lease) {
+            rearm_activity(&self.state);
+        }
+
+        match table.leases.get(&lease.hw_address) {
+            Some(previous) if previous.address == lease.address => {},
+            Some(_) => info!("Client {} moved to {}", lease.hw_address, lease.address),
+            None => info!(
+                "Client {} joined as {} ({})",
+                lease.hw_address,
+                lease.address,
+                lease.hostname.as_deref().unwrap_or("no hostname")
+            ),
+        }
+
+        table.leases.insert(lease.hw_address.clone(), lease);
+    }
+
+    /// Forget a client that released its lease
+    pub fn release(&self, hw_address: &str) {
+        let mut table = self.table();
+        if let Some(lease) = table.leases.remove(hw_address) {
+            table.left(&lease);
+        }
+    }
+
+    /// Replace the leases with those read from a lease file
+    pub fn sync(&self, leases: Vec<Lease>) {
+        let current: HashMap<&str, &Lease> =
+            leases.iter().map(|lease| (lease.hw_address.as_str(), lease)).collect();
+
+        let gone: Vec<String> = self
+            .table()
+            .leases
+            .keys()
+            .filter(|hw_address| !current.contains_key(hw_address.as_str()))
+            .cloned()
+            .collect();
+
+        for hw_address in gone {
+            self.release(&hw_address);
+        }
+        for lease in leases {
+            self.lease(lease);
+        }
+    }
+
+    /// Forget clients whose lease ran out
+    pub fn expire(&self) {
+        let now = SystemTime::now();
+        let mut table = self.table();
+
+        let expired: Vec<Lease> = table
+            .leases
+            .values()
+            .filter(|lease| lease.expires.is_some_and(|expires| expires <= now))
+            .cloned()
+            .collect();
+
+        for lease in expired {
+            table.leases.remove(&lease.hw_address);
+            table.left(&lease);
+        }
+    }
+
+    /// Record an HTTP request from a client
+    pub fn touch(&self, address: IpAddr) {
+        self.table().activity.insert(address, SystemTime::now());
+    }
+
+    /// MAC address of the client leasing an address
+    pub fn hw_address(&self, address: IpAddr) -> Option<String> {
+        self.table()
+            .leases
+            .values()
+            .find(|lease| IpAddr::V4(lease.address) == address)
+            .map(|lease| lease.hw_address.clone())
+    }
+
+    /// Clients with a lease, ordered by address
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let table = self.table();
+
+        let mut clients: Vec<ClientInfo> = table
+            .leases
+            .values()
+            .map(|lease| ClientInfo {
+                hw_address: lease.hw_address.clone(),
+                address: lease.address,
+                hostname: lease.hostname.clone(),
+                lease_expires: lease.expires.map(unix_time),
+                last_activity: table
+                    .activity
+                    .get(&IpAddr::V4(lease.address))
+                    .copied()
+                    .map(unix_time),
+            })
+            .collect();
+
+        clients.sort_by_key(|client| client.address);
+        clients
+    }
+
+    fn table(&self) -> MutexGuard<'_, Table> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Table {
+    fn left(&mut self, lease: &Lease) {
+        self.activity.remove(&IpAddr::V4(lease.address));
+        info!("Client {} ({}) left", lease.hw_address, lease.address);
+    }
+}
+
+/// Expire leases in the background, reading them from a lease file if given
+pub fn spawn_lease_watch(clients: Arc<Clients>, lease_file: Option<PathBuf>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LEASE_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Some(ref path) = lease_file {
+                match tokio::fs::read_to_string(path).await {
+                    Ok(contents) => clients.sync(parse_lease_file(&contents)),
+                    Err(e) => debug!("Reading {} failed: {}", path.display(), e),
+                }
+            }
+
+            clients.expire();
+        }
+    });
+}
+
+/// Parse dnsmasq lease file lines: `<expiry> <mac> <ip> <hostname> <client id>`
+fn parse_lease_file(contents: &str) -> Vec<Lease> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let expiry: u64 = fields.next()?.parse().ok()?;
+            let hw_address = fields.next()?.to_ascii_lowercase();
+            let address: Ipv4Addr = fields.next()?.parse().ok()?;
+            let hostname = fields.next().filter(|name| *name != "*").map(str::to_string);
+
+            Some(Lease {
+                hw_address,
+                address,
+                hostname,
+                expires: (expiry != 0).then(|| UNIX_EPOCH + Duration::from_secs(expiry)),
+            })
+        })
+        .collect()
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease_lines_are_parsed() {
+        let leases = parse_lease_file(
+            "1767225600 AA:BB:CC:DD:EE:01 192.168.42.10 laptop 01:aa:bb:cc:dd:ee:01\n\
+             1767225700 aa:bb:cc:dd:ee:02 192.168.42.11 * *\n",
+        );
+
+        assert_eq!(
+            leases,
+            [
+                Lease {
+                    hw_address: "aa:bb:cc:dd:ee:01".to_string(),
+                    address: Ipv4Addr::new(192, 168, 42, 10),
+                    hostname: Some("laptop".to_string()),
+                    expires: Some(UNIX_EPOCH + Duration::from_secs(1767225600)),
+                },
+                Lease {
+                    hw_address: "aa:bb:cc:dd:ee:02".to_string(),
+                    address: Ipv4Addr::new(192, 168, 42, 11),
+                    hostname: None,
+                    expires: Some(UNIX_EPOCH + Duration::from_secs(1767225700)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_expiry_never_expires() {
+        let leases = parse_lease_file("0 aa:bb:cc:dd:ee:01 192.168.42.10 printer *");
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].expires, None);
+    }
+
+    #[test]
+    fn client_id_and_hostname_are_optional() {
+        let leases = parse_lease_file("1767225600 aa:bb:cc:dd:ee:01 192.168.42.10");
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].hostname, None);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let leases = parse_lease_file(
+            "\n\
+             duid 00:01:00:01:2c:5a:1e:7f:aa:bb:cc:dd:ee:ff\n\
+             soon aa:bb:cc:dd:ee:01 192.168.42.10 laptop *\n\
+             -1 aa:bb:cc:dd:ee:01 192.168.42.10 laptop *\n\
+             1767225600 aa:bb:cc:dd:ee:02 fd00::2 phone *\n\
+             1767225600 aa:bb:cc:dd:ee:03 192.168.42.300 tablet *\n\
+             1767225600 aa:bb:cc:dd:ee:04\n\
+             1767225600 aa:bb:cc:dd:ee:05 192.168.42.15 tv *\n",
+        );
+
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].hw_address, "aa:bb:cc:dd:ee:05");
+        assert_eq!(leases[0].address, Ipv4Addr::new(192, 168, 42, 15));
+    }
+}
@@ -1,25 +1,52 @@
-use clap::Parser;
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use clap::builder::Resettable;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, CommandFactory, Parser, Subcommand};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+
+use crate::audit::AUDIT_LOG_FILE;
+use crate::backend::BackendKind;
+use crate::dhcp::DhcpBackend;
+use crate::dns::{parse_upstream, DnsBackend};
+use crate::errors::{AppError, Result};
+use crate::logger::{parse_syslog_target, LogFormat, LogRotation, SyslogTarget};
+use crate::mqtt::{parse_mqtt_broker, MqttBroker};
+use crate::state::ActivityMode;
+use crate::tls::TlsMode;
+use crate::webhook::parse_webhook_url;
 
 const DEFAULT_GATEWAY: &str = "192.168.42.1";
 const DEFAULT_DHCP_RANGE: &str = "192.168.42.2,192.168.42.254";
 const DEFAULT_SSID: &str = "WiFi Connect";
 const DEFAULT_UI_DIRECTORY: &str = "ui";
 const DEFAULT_ETHERNET_INTERFACE: &str = "eth0";
+const DEFAULT_STATE_DIRECTORY: &str = "/var/lib/ember-network-connect";
+const DEFAULT_PORTAL_HOSTNAME: &str = "ember.portal";
+const DEFAULT_MQTT_TOPIC_PREFIX: &str = "ember-network-connect";
 
 #[derive(Parser, Clone, Debug)]
 #[command(name = "ember-network-connect")]
 #[command(about = "Captive portal for resetting network settings to DHCP")]
 #[command(version)]
 pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML configuration file. Command line options and environment variables take precedence.
+    #[arg(long = "config", env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
     /// Wireless network interface for the captive portal AP
     #[arg(short = 'i', long = "portal-interface", env = "PORTAL_INTERFACE")]
     pub interface: Option<String>,
 
-    /// Ethernet interface to reset to DHCP
-    #[arg(short = 'e', long = "ethernet-interface", env = "ETHERNET_INTERFACE", default_value = DEFAULT_ETHERNET_INTERFACE)]
-    pub ethernet_interface: String,
+    /// Ethernet interfaces to reset to DHCP (comma separated)
+    #[arg(short = 'e', long = "ethernet-interface", env = "ETHERNET_INTERFACE", value_delimiter = ',', default_value = DEFAULT_ETHERNET_INTERFACE)]
+    pub ethernet_interfaces: Vec<String>,
 
     /// SSID of the captive portal WiFi network
     #[arg(short = 's', long = "portal-ssid", env = "PORTAL_SSID", default_value = DEFAULT_SSID)]
@@ -37,24 +64,254 @@ pub struct Config {
     #[arg(short = 'd', long = "portal-dhcp-range", env = "PORTAL_DHCP_RANGE", default_value = DEFAULT_DHCP_RANGE)]
     pub dhcp_range: String,
 
+    /// DHCP server of the captive portal WiFi network
+    #[arg(long = "dhcp-backend", env = "DHCP_BACKEND", value_enum, default_value = "builtin")]
+    pub dhcp_backend: DhcpBackend,
+
+    /// DNS server of the captive portal WiFi network
+    #[arg(long = "dns-backend", env = "DNS_BACKEND", value_enum, default_value = "builtin")]
+    pub dns_backend: DnsBackend,
+
+    /// Names resolved by the upstream resolvers instead of the gateway
+    #[arg(long = "dns-allow", env = "DNS_ALLOW", value_delimiter = ',')]
+    pub dns_allow: Vec<String>,
+
+    /// Upstream resolvers for allowed names, by default those of the host
+    #[arg(
+        long = "dns-upstream",
+        env = "DNS_UPSTREAM",
+        value_delimiter = ',',
+        value_parser = parse_upstream
+    )]
+    pub dns_upstream: Vec<SocketAddr>,
+
     /// Listening port of the captive portal web server
     #[arg(short = 'o', long = "portal-listening-port", env = "PORTAL_LISTENING_PORT", default_value = "80")]
     pub listening_port: u16,
 
+    /// Answer OS connectivity checks as online after a successful reset, closing the captive sheet
+    #[arg(long = "online-after-reset", env = "ONLINE_AFTER_RESET")]
+    pub online_after_reset: bool,
+
+    /// Hostname of the captive portal, also accepted by the portal redirect
+    #[arg(long = "portal-hostname", env = "PORTAL_HOSTNAME", default_value = DEFAULT_PORTAL_HOSTNAME)]
+    pub portal_hostname: String,
+
+    /// Listening port of the HTTPS listener. Disabled if unset.
+    #[arg(long = "tls-port", env = "TLS_PORT")]
+    pub tls_port: Option<u16>,
+
+    /// PEM certificate for the HTTPS listener. A self-signed certificate is generated if unset.
+    #[arg(long = "tls-certificate", env = "TLS_CERTIFICATE", requires = "tls_key")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// PEM private key of the HTTPS certificate
+    #[arg(long = "tls-key", env = "TLS_KEY", requires = "tls_certificate")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Whether the HTTPS listener redirects to the HTTP portal or serves it
+    #[arg(long = "tls-mode", env = "TLS_MODE", value_enum, default_value = "redirect")]
+    pub tls_mode: TlsMode,
+
+    /// Serve Prometheus metrics at /metrics on the portal
+    #[arg(long = "metrics", env = "METRICS")]
+    pub metrics: bool,
+
+    /// Serve Prometheus metrics on this address instead of the portal
+    #[arg(long = "metrics-address", env = "METRICS_ADDRESS")]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// Format of the log lines on standard output and in the log file
+    #[arg(long = "log-format", env = "LOG_FORMAT", value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Also log to this file, with timestamps. The name gets a date suffix unless rotation is disabled.
+    #[arg(long = "log-file", env = "LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// How often the log file is rotated
+    #[arg(long = "log-rotation", env = "LOG_ROTATION", value_enum, default_value = "daily")]
+    pub log_rotation: LogRotation,
+
+    /// Rotated log files to keep. 0 = all.
+    #[arg(long = "log-max-files", env = "LOG_MAX_FILES", default_value = "7")]
+    pub log_max_files: usize,
+
+    /// Also send RFC 5424 syslog messages to this collector, as `[udp://|tcp://]host[:port]`
+    #[arg(long = "syslog", env = "SYSLOG", value_parser = parse_syslog_target)]
+    pub syslog: Option<SyslogTarget>,
+
+    /// URLs receiving a JSON POST for each portal lifecycle event (comma separated)
+    #[arg(
+        long = "webhook-url",
+        env = "WEBHOOK_URL",
+        value_delimiter = ',',
+        value_parser = parse_webhook_url
+    )]
+    pub webhook_urls: Vec<Url>,
+
+    /// Secret the webhook bodies are signed with (HMAC-SHA256 in the X-Webhook-Signature header)
+    #[arg(long = "webhook-secret", env = "WEBHOOK_SECRET")]
+    pub webhook_secret: Option<String>,
+
+    /// MQTT broker the portal state is published to, as `[mqtt://]host[:port]`
+    #[arg(long = "mqtt-broker", env = "MQTT_BROKER", value_parser = parse_mqtt_broker)]
+    pub mqtt_broker: Option<MqttBroker>,
+
+    /// Prefix of the MQTT state, remaining time and command topics
+    #[arg(long = "mqtt-topic-prefix", env = "MQTT_TOPIC_PREFIX", default_value = DEFAULT_MQTT_TOPIC_PREFIX)]
+    pub mqtt_topic_prefix: String,
+
+    /// Username for the MQTT broker
+    #[arg(long = "mqtt-username", env = "MQTT_USERNAME", requires = "mqtt_password")]
+    pub mqtt_username: Option<String>,
+
+    /// Password for the MQTT broker
+    #[arg(long = "mqtt-password", env = "MQTT_PASSWORD", requires = "mqtt_username")]
+    pub mqtt_password: Option<String>,
+
     /// Exit if no activity for the specified time (seconds). 0 = disabled.
     #[arg(short = 'a', long = "activity-timeout", env = "ACTIVITY_TIMEOUT", default_value = "0")]
     pub activity_timeout: u64,
 
+    /// Whether the activity timeout waits for the first visit or for any
+    /// period without activity
+    #[arg(long = "activity-mode", env = "ACTIVITY_MODE", value_enum, default_value = "first-visit")]
+    pub activity_mode: ActivityMode,
+
     /// Overall timeout - exit after this many seconds regardless of activity. 0 = disabled.
     #[arg(short = 'n', long = "overall-timeout", env = "OVERALL_TIMEOUT", default_value = "0")]
     pub overall_timeout: u64,
 
+    /// Longest the portal may run when the overall timeout is extended with
+    /// `POST /extend` (seconds). 0 = no extensions.
+    #[arg(long = "max-lifetime", env = "MAX_LIFETIME", default_value = "0")]
+    pub max_lifetime: u64,
+
+    /// Time to wait for a DHCP lease after a reset (seconds)
+    #[arg(long = "dhcp-timeout", env = "DHCP_TIMEOUT", default_value = "60")]
+    pub dhcp_timeout: u64,
+
+    /// Delete every wired profile on reset, not only those that apply to the ethernet interface
+    #[arg(long = "reset-all-wired", env = "RESET_ALL_WIRED")]
+    pub reset_all_wired: bool,
+
+    /// Restore the deleted profiles if no DHCP lease arrives in time (seconds). 0 = disabled.
+    #[arg(long = "rollback-timeout", env = "ROLLBACK_TIMEOUT", default_value = "120")]
+    pub rollback_timeout: u64,
+
+    /// Directory for backups of deleted connection profiles
+    #[arg(long = "state-directory", env = "STATE_DIRECTORY", default_value = DEFAULT_STATE_DIRECTORY)]
+    pub state_directory: PathBuf,
+
+    /// JSON lines log of the changes made through the portal. Defaults to audit.jsonl in the state directory.
+    #[arg(long = "audit-log", env = "AUDIT_LOG")]
+    audit_log_arg: Option<PathBuf>,
+
+    /// PIN required to reset or reconfigure the ethernet devices
+    #[arg(long = "reset-pin", env = "RESET_PIN")]
+    pub reset_pin: Option<String>,
+
+    /// Argon2 hash of a password accepted like the reset PIN
+    #[arg(long = "reset-password-hash", env = "RESET_PASSWORD_HASH")]
+    pub reset_password_hash: Option<String>,
+
+    /// Secret the per-device reset code is derived from, together with the ethernet MAC address
+    #[arg(long = "reset-code-secret", env = "RESET_CODE_SECRET")]
+    pub reset_code_secret: Option<String>,
+
+    /// PIN granting read-only access to the status endpoints
+    #[arg(long = "view-pin", env = "VIEW_PIN")]
+    pub view_pin: Option<String>,
+
+    /// Failed credential attempts before further attempts from the client are refused, all clients are refused after four times as many. 0 = unlimited.
+    #[arg(long = "auth-max-failures", env = "AUTH_MAX_FAILURES", default_value = "5")]
+    pub auth_max_failures: u32,
+
+    /// How long a client is refused after too many failed attempts (seconds)
+    #[arg(long = "auth-lockout", env = "AUTH_LOCKOUT", default_value = "300")]
+    pub auth_lockout: u64,
+
     /// Web UI directory location
     #[arg(short = 'u', long = "ui-directory", env = "UI_DIRECTORY")]
     ui_directory_arg: Option<PathBuf>,
+
+    /// Network backend (fake simulates devices in memory for development)
+    #[arg(long = "network-backend", env = "NETWORK_BACKEND", value_enum, default_value = "network-manager")]
+    pub network_backend: BackendKind,
+}
+
+/// Maintenance subcommands run instead of the captive portal
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Manage backups of deleted connection profiles
+    #[command(subcommand)]
+    Backups(BackupCommand),
+    /// Generate reset credentials
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// Show the changes made through the portal
+    #[command(subcommand)]
+    Audit(AuditCommand),
+}
+
+impl Command {
+    /// Whether the subcommand talks to NetworkManager, which needs root
+    pub fn requires_root(&self) -> bool {
+        match self {
+            Command::Backups(BackupCommand::Restore { .. }) => true,
+            Command::Backups(BackupCommand::List) => false,
+            Command::Auth(AuthCommand::DeviceCode { mac }) => mac.is_none(),
+            Command::Auth(AuthCommand::HashPassword) => false,
+            Command::Audit(_) => false,
+        }
+    }
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum BackupCommand {
+    /// List saved connection profiles
+    List,
+    /// Recreate and activate a saved connection profile
+    Restore {
+        /// Backup name as shown by `backups list`
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum AuthCommand {
+    /// Hash a password read from standard input for --reset-password-hash
+    HashPassword,
+    /// Print the reset code of a device
+    DeviceCode {
+        /// MAC address of the device, defaults to the first ethernet interface
+        #[arg(long)]
+        mac: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum AuditCommand {
+    /// Print the audit log, oldest first
+    List {
+        /// Print the full records as JSON lines
+        #[arg(long)]
+        json: bool,
+        /// Only print the last records
+        #[arg(long)]
+        last: Option<usize>,
+    },
 }
 
 impl Config {
+    /// Path of the audit log
+    pub fn audit_log(&self) -> PathBuf {
+        self.audit_log_arg
+            .clone()
+            .unwrap_or_else(|| self.state_directory.join(AUDIT_LOG_FILE))
+    }
+
     /// Get the UI directory, checking multiple locations
     pub fn ui_directory(&self) -> PathBuf {
         if let Some(ref dir) = self.ui_directory_arg {
@@ -87,6 +344,317 @@ fn get_install_ui_directory() -> Option<PathBuf> {
     path.is_dir().then_some(path)
 }
 
-pub fn get_config() -> Config {
-    Config::parse()
+/// Parse the command line and environment, filling the remaining options from
+/// the configuration file
+pub fn get_config() -> Result<Config> {
+    let env = |name: &str| std::env::var_os(name);
+    let args = with_config_file_args(std::env::args_os().collect(), env)?;
+    Ok(Config::parse_from(args))
+}
+
+/// Add the options of the configuration file named in the arguments, if any
+///
+/// The arguments are only looked at to find the file and the options they
+/// already set. They are validated once the file options are merged in, so
+/// that options required together can come from different sources. `env`
+/// looks up environment variables, whose settings the file does not override.
+fn with_config_file_args(
+    mut args: Vec<OsString>,
+    env: impl Fn(&str) -> Option<OsString>,
+) -> Result<Vec<OsString>> {
+    let matches = Config::command().ignore_errors(true).get_matches_from(&args);
+
+    if let Some(path) = matches.get_one::<PathBuf>("config_file") {
+        // Options from the file go before the user's arguments so that a
+        // subcommand and its arguments stay last
+        let file_args = config_file_args(path, &matches, &env)?;
+        args.splice(1..1, file_args);
+    }
+
+    Ok(args)
+}
+
+/// Translate the settings of a configuration file into command line options
+///
+/// Keys are the long option names with underscores, which is also the
+/// environment variable name in lower case. Settings already given on the
+/// command line or in the environment are skipped.
+fn config_file_args(
+    path: &Path,
+    matches: &ArgMatches,
+    env: &dyn Fn(&str) -> Option<OsString>,
+) -> Result<Vec<OsString>> {
+    let display = path.display().to_string();
+
+    let contents = fs::read_to_string(path)
+        .map_err(|e| AppError::ConfigFile(display.clone(), e.to_string()))?;
+    let table: toml::Table = contents
+        .parse()
+        .map_err(|e: toml::de::Error| AppError::ConfigFile(display.clone(), e.to_string()))?;
+
+    let command = Config::command();
+    let mut args = Vec::new();
+
+    for (key, value) in &table {
+        let invalid = |message: String| AppError::ConfigSetting {
+            path: display.clone(),
+            key: key.clone(),
+            message,
+        };
+
+        let Some((arg, long)) = command.get_arguments().find_map(|arg| {
+            let long = arg.get_long().filter(|_| is_file_setting(arg))?;
+            (long.replace('-', "_") == *key).then_some((arg, long))
+        }) else {
+            return Err(invalid("unknown setting".to_string()));
+        };
+
+        let on_command_line = matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine)
+        );
+        let in_environment = arg
+            .get_env()
+            .is_some_and(|name| env(&name.to_string_lossy()).is_some());
+        if on_command_line || in_environment {
+            continue;
+        }
+
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            match value {
+                toml::Value::Boolean(true) => args.push(format!("--{}", long).into()),
+                toml::Value::Boolean(false) => {},
+                _ => return Err(invalid("expected true or false".to_string())),
+            }
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(items) if matches!(arg.get_action(), ArgAction::Append) => {
+                items.iter().map(scalar).collect::<Option<Vec<_>>>()
+            },
+            _ => scalar(value).map(|value| vec![value]),
+        };
+        let Some(values) = values else {
+            return Err(invalid("expected a string or a number".to_string()));
+        };
+
+        for value in values {
+            let option = format!("--{}={}", long, value);
+
+            // Parse the option on its own so errors can name the key. Options
+            // it requires are checked with all arguments.
+            clap::Command::new("config")
+                .arg(arg.clone().requires(Resettable::Reset))
+                .try_get_matches_from([OsStr::new("config"), OsStr::new(&option)])
+                .map_err(|e| invalid(invalid_value(arg, &value, &e)))?;
+            args.push(option.into());
+        }
+    }
+
+    Ok(args)
+}
+
+/// Options that can be set in the configuration file
+fn is_file_setting(arg: &Arg) -> bool {
+    !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version)
+        && arg.get_id() != "config_file"
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Describe why clap rejected a value, without the command line usage
+fn invalid_value(arg: &Arg, value: &str, error: &clap::Error) -> String {
+    if let Some(source) = std::error::Error::source(error) {
+        return format!("invalid value '{}': {}", value, source);
+    }
+
+    let possible: Vec<_> = arg
+        .get_possible_values()
+        .iter()
+        .map(|v| v.get_name().to_string())
+        .collect();
+
+    if possible.is_empty() {
+        format!("invalid value '{}'", value)
+    } else {
+        format!("invalid value '{}', expected one of {}", value, possible.join(", "))
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Configuration parsed from the given options, with a state directory
+    /// of its own
+    pub fn for_tests(args: &[&str]) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+
+        let state_directory = std::env::temp_dir().join(format!(
+            "ember-network-connect-test-{}-{}",
+            std::process::id(),
+            DIRECTORIES.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let args = std::iter::once("ember-network-connect").chain(args.iter().copied());
+        let mut config = Config::parse_from(args);
+        config.state_directory = state_directory;
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Configuration file of a test, removed when dropped
+    struct ConfigFile {
+        path: String,
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Write a configuration file of its own for a test
+    fn config_file(name: &str, contents: &str) -> ConfigFile {
+        let path = std::env::temp_dir().join(format!(
+            "ember-network-connect-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        ConfigFile {
+            path: path.display().to_string(),
+        }
+    }
+
+    fn file_args(args: &[&str], env: &[(&str, &str)]) -> Result<Vec<OsString>> {
+        let args = std::iter::once("ember-network-connect").chain(args.iter().copied());
+        with_config_file_args(args.map(OsString::from).collect(), |name| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| OsString::from(value))
+        })
+    }
+
+    fn parse(args: &[&str]) -> Result<std::result::Result<Config, clap::Error>> {
+        Ok(Config::try_parse_from(file_args(args, &[])?))
+    }
+
+    #[test]
+    fn file_settings_become_options() {
+        let file = config_file(
+            "settings",
+            r#"
+            portal_ssid = "Setup"
+            portal_listening_port = 8080
+            ethernet_interface = ["eth0", "eth1"]
+            online_after_reset = true
+            metrics = false
+            "#,
+        );
+
+        let config = parse(&["--config", &file.path]).unwrap().unwrap();
+
+        assert_eq!(config.ssid, "Setup");
+        assert_eq!(config.listening_port, 8080);
+        assert_eq!(config.ethernet_interfaces, ["eth0", "eth1"]);
+        assert!(config.online_after_reset);
+        assert!(!config.metrics);
+    }
+
+    #[test]
+    fn command_line_takes_precedence_over_file() {
+        let file = config_file(
+            "precedence",
+            r#"
+            portal_ssid = "File"
+            portal_passphrase = "from-file"
+            "#,
+        );
+
+        let config = parse(&["--config", &file.path, "--portal-ssid", "Cli"]).unwrap().unwrap();
+
+        assert_eq!(config.ssid, "Cli");
+        assert_eq!(config.passphrase.as_deref(), Some("from-file"));
+    }
+
+    #[test]
+    fn environment_takes_precedence_over_file() {
+        let file = config_file(
+            "environment",
+            r#"
+            dhcp_timeout = 30
+            portal_ssid = "File"
+            "#,
+        );
+        let env = [("DHCP_TIMEOUT", "90")];
+
+        let args = file_args(&["--config", &file.path], &env).unwrap();
+
+        assert!(!args.contains(&OsString::from("--dhcp-timeout=30")));
+        assert!(args.contains(&OsString::from("--portal-ssid=File")));
+    }
+
+    #[test]
+    fn options_required_together_may_come_from_file_and_command_line() {
+        let file = config_file("requires", r#"tls_key = "/etc/portal/key.pem""#);
+
+        let args = ["--config", &file.path, "--tls-certificate", "/etc/portal/cert.pem"];
+        let config = parse(&args).unwrap().unwrap();
+
+        assert_eq!(config.tls_certificate, Some(PathBuf::from("/etc/portal/cert.pem")));
+        assert_eq!(config.tls_key, Some(PathBuf::from("/etc/portal/key.pem")));
+    }
+
+    #[test]
+    fn options_required_together_are_checked_after_merging() {
+        let file = config_file("requires-missing", r#"tls_certificate = "/etc/portal/cert.pem""#);
+
+        let error = parse(&["--config", &file.path]).unwrap().unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn subcommand_stays_last() {
+        let file = config_file("subcommand", r#"state_directory = "/tmp/state""#);
+
+        let config = parse(&["--config", &file.path, "backups", "list"]).unwrap().unwrap();
+
+        assert_eq!(config.state_directory, PathBuf::from("/tmp/state"));
+        assert!(matches!(config.command, Some(Command::Backups(BackupCommand::List))));
+    }
+
+    #[test]
+    fn unknown_setting_is_rejected() {
+        let file = config_file("unknown", r#"portal_colour = "blue""#);
+
+        let error = parse(&["--config", &file.path]).unwrap_err();
+        assert!(matches!(error, AppError::ConfigSetting { ref key, .. } if key == "portal_colour"));
+    }
+
+    #[test]
+    fn invalid_value_names_the_setting() {
+        let file = config_file("invalid", r#"portal_gateway = "gateway""#);
+
+        let error = parse(&["--config", &file.path]).unwrap_err();
+        assert!(
+            matches!(error, AppError::ConfigSetting { ref key, .. } if key == "portal_gateway")
+        );
+
+        let file = config_file("invalid-boolean", r#"metrics = "yes""#);
+        assert!(parse(&["--config", &file.path]).is_err());
+    }
 }
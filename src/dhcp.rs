@@ -0,0 +1,598 @@
+mod packet;
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::ValueEnum;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::captive::captive_api_url;
+use crate::clients::{self, Clients};
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+
+use packet::{option, Message, MessageType, CLIENT_PORT, SERVER_PORT};
+
+/// Lease time handed to portal clients
+const LEASE_TIME: Duration = Duration::from_secs(3600);
+
+/// How long an offered address is reserved for the client
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Prefix length of the portal network, which the hotspot creates as a /24
+const DEFAULT_PORTAL_PREFIX: u32 = 24;
+
+/// Which DHCP server hands out addresses on the portal network
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DhcpBackend {
+    /// In-process DHCPv4 server
+    Builtin,
+    /// External dnsmasq process
+    Dnsmasq,
+}
+
+/// Address handed to a client
+#[derive(Debug, Clone)]
+struct Lease {
+    address: Ipv4Addr,
+    expires: Instant,
+    /// Offered but not yet requested
+    offered: bool,
+}
+
+/// In-process DHCPv4 server for the portal network
+pub struct DhcpServer {
+    socket: UdpSocket,
+    interface: String,
+    server: Ipv4Addr,
+    netmask: Ipv4Addr,
+    range: (Ipv4Addr, Ipv4Addr),
+    /// RFC 8908 API URL sent with option 114, only when served over HTTPS
+    captive_url: Option<String>,
+    /// Leases by client hardware address
+    leases: HashMap<String, Lease>,
+    /// Addresses clients found in use, with the time they can be offered again
+    declined: HashMap<Ipv4Addr, Instant>,
+    clients: Arc<Clients>,
+}
+
+impl DhcpServer {
+    /// Bind the DHCP port on the portal interface
+    pub fn bind(config: &Config, interface: &str, clients: Arc<Clients>) -> Result<Self> {
+        let range = parse_range(&config.dhcp_range)?;
+        let netmask = portal_netmask(&config.dhcp_range, config.gateway, range)?;
+
+        let socket = bind_socket(interface).map_err(|e| {
+            AppError::Dhcp(format!("binding port {} on {}: {}", SERVER_PORT, interface, e))
+        })?;
+
+        info!(
+            "Serving DHCP on {} for {} - {} (netmask {})",
+            interface, range.0, range.1, netmask
+        );
+
+        Ok(Self {
+            socket,
+            interface: interface.to_string(),
+            server: config.gateway,
+            netmask,
+            range,
+            captive_url: captive_api_url(config),
+            leases: HashMap::new(),
+            declined: HashMap::new(),
+            clients,
+        })
+    }
+
+    /// Serve requests in a background task
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(mut self) {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, peer) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("Receiving DHCP request on {} failed: {}", self.interface, e);
+                    continue;
+                },
+            };
+
+            let Some(request) = Message::parse(&buf[..len]) else {
+                debug!("Ignoring malformed DHCP packet from {}", peer);
+                continue;
+            };
+
+            let Some(reply) = self.handle(&request) else {
+                continue;
+            };
+
+            let destination = reply_destination(&request, &reply);
+            if let Err(e) = self.socket.send_to(&reply.encode(), destination).await {
+                warn!("Sending DHCP reply to {} failed: {}", destination, e);
+            }
+        }
+    }
+
+    /// Build the reply to a request, if it needs one
+    fn handle(&mut self, request: &Message) -> Option<Message> {
+        let message_type = request.message_type()?;
+        let client = request.hw_address();
+        debug!("DHCP {:?} from {}", message_type, client);
+
+        let now = Instant::now();
+        self.declined.retain(|_, until| *until > now);
+
+        match message_type {
+            MessageType::Discover => {
+                let Some(address) = self.select_address(&client, request.requested_ip(), now)
+                else {
+                    warn!("DHCP range exhausted, cannot offer an address to {}", client);
+                    return None;
+                };
+
+                self.leases.insert(
+                    client,
+                    Lease {
+                        address,
+                        expires: now + OFFER_TIMEOUT,
+                        offered: true,
+                    },
+                );
+                Some(self.lease_reply(request, MessageType::Offer, address))
+            },
+            MessageType::Request => {
+                // The client accepted another server's offer
+                if request.server_id().is_some_and(|id| id != self.server) {
+                    self.leases.retain(|mac, lease| *mac != client || !lease.offered);
+                    return None;
+                }
+
+                let address = request.requested_ip().unwrap_or(request.ciaddr);
+                if !self.is_available(address, &client, now) {
+                    debug!("Refusing {} to {}", address, client);
+                    return Some(request.reply(
+                        MessageType::Nak,
+                        Ipv4Addr::UNSPECIFIED,
+                        self.server,
+                    ));
+                }
+
+                self.leases.insert(
+                    client.clone(),
+                    Lease {
+                        address,
+                        expires: now + LEASE_TIME,
+                        offered: false,
+                    },
+                );
+                self.clients.lease(clients::Lease {
+                    hw_address: client,
+                    address,
+                    hostname: request.hostname(),
+                    expires: Some(SystemTime::now() + LEASE_TIME),
+                });
+                Some(self.lease_reply(request, MessageType::Ack, address))
+            },
+            MessageType::Decline => {
+                if let Some(address) = request.requested_ip() {
+                    warn!("{} reports {} is already in use", client, address);
+                    self.declined.insert(address, now + LEASE_TIME);
+                }
+                self.leases.remove(&client);
+                self.clients.release(&client);
+                None
+            },
+            MessageType::Release => {
+                self.leases.remove(&client);
+                self.clients.release(&client);
+                None
+            },
+            MessageType::Inform => {
+                let mut reply = request.reply(MessageType::Ack, Ipv4Addr::UNSPECIFIED, self.server);
+                self.add_network_options(&mut reply);
+                Some(reply)
+            },
+            MessageType::Offer | MessageType::Ack | MessageType::Nak => None,
+        }
+    }
+
+    /// Reply carrying an address with its lease time and network options
+    fn lease_reply(&self, request: &Message, message_type: MessageType, address: Ipv4Addr) -> Message {
+        let lease_secs = LEASE_TIME.as_secs() as u32;
+
+        let mut reply = request.reply(message_type, address, self.server);
+        reply.add_option(option::LEASE_TIME, lease_secs.to_be_bytes());
+        reply.add_option(option::RENEWAL_TIME, (lease_secs / 2).to_be_bytes());
+        reply.add_option(option::REBINDING_TIME, (lease_secs / 8 * 7).to_be_bytes());
+        self.add_network_options(&mut reply);
+        reply
+    }
+
+    fn add_network_options(&self, reply: &mut Message) {
+        reply.add_option(option::SUBNET_MASK, self.netmask.octets());
+        reply.add_option(option::ROUTER, self.server.octets());
+        reply.add_option(option::DNS_SERVERS, self.server.octets());
+        if let Some(ref url) = self.captive_url {
+            reply.add_option(option::CAPTIVE_PORTAL, url.as_bytes());
+        }
+    }
+
+    /// Pick the address to offer: the client's current one, the one it asks
+    /// for, or the first free address in the range
+    fn select_address(
+        &self,
+        client: &str,
+        requested: Option<Ipv4Addr>,
+        now: Instant,
+    ) -> Option<Ipv4Addr> {
+        if let Some(lease) = self.leases.get(client) {
+            if self.is_available(lease.address, client, now) {
+                return Some(lease.address);
+            }
+        }
+
+        if let Some(address) = requested {
+            if self.is_available(address, client, now) {
+                return Some(address);
+            }
+        }
+
+        (u32::from(self.range.0)..=u32::from(self.range.1))
+            .map(Ipv4Addr::from)
+            .find(|address| self.is_available(*address, client, now))
+    }
+
+    /// Whether an address in the range can be handed to the client
+    fn is_available(&self, address: Ipv4Addr, client: &str, now: Instant) -> bool {
+        let in_range = (self.range.0..=self.range.1).contains(&address);
+
+        in_range
+            && address != self.server
+            && !self.declined.contains_key(&address)
+            && !self.leases.iter().any(|(mac, lease)| {
+                mac != client && lease.address == address && lease.expires > now
+            })
+    }
+}
+
+/// Parse the first two fields of a dnsmasq style `start,end[,...]` range
+fn parse_range(range: &str) -> Result<(Ipv4Addr, Ipv4Addr)> {
+    let invalid = || AppError::Dhcp(format!("invalid DHCP range '{}'", range));
+
+    let mut fields = range.split(',').map(str::trim);
+    let start: Ipv4Addr = fields.next().and_then(|f| f.parse().ok()).ok_or_else(invalid)?;
+    let end: Ipv4Addr = fields.next().and_then(|f| f.parse().ok()).ok_or_else(invalid)?;
+
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok((start, end))
+}
+
+/// Netmask of the portal network
+///
+/// A netmask given as the third field of the range is used, as dnsmasq does.
+/// Otherwise the network is the /24 the hotspot creates, widened when the
+/// range reaches beyond it.
+fn portal_netmask(
+    dhcp_range: &str,
+    gateway: Ipv4Addr,
+    range: (Ipv4Addr, Ipv4Addr),
+) -> Result<Ipv4Addr> {
+    let netmask = dhcp_range
+        .split(',')
+        .map(str::trim)
+        .nth(2)
+        .and_then(|field| field.parse::<Ipv4Addr>().ok());
+
+    let prefix = match netmask {
+        Some(netmask) => {
+            let bits = u32::from(netmask);
+            if bits.leading_ones() + bits.trailing_zeros() != 32 {
+                return Err(AppError::Dhcp(format!(
+                    "invalid netmask {} in DHCP range '{}'",
+                    netmask, dhcp_range
+                )));
+            }
+            bits.leading_ones()
+        },
+        None => {
+            // Bits in which the range differs from the gateway
+            let differing = [range.0, range.1]
+                .iter()
+                .fold(0, |bits, address| bits | (u32::from(*address) ^ u32::from(gateway)));
+            differing.leading_zeros().min(DEFAULT_PORTAL_PREFIX)
+        },
+    };
+
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let network = u32::from(gateway) & mask;
+    if [range.0, range.1].iter().any(|address| u32::from(*address) & mask != network) {
+        return Err(AppError::Dhcp(format!(
+            "DHCP range '{}' is outside the network of the gateway {}",
+            dhcp_range, gateway
+        )));
+    }
+
+    Ok(Ipv4Addr::from(mask))
+}
+
+/// UDP socket on the DHCP server port that only sees the portal interface
+fn bind_socket(interface: &str) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.bind_device(Some(interface.as_bytes()))?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SERVER_PORT)).into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Where to send a reply (RFC 2131 section 4.1)
+///
+/// Configured clients get unicast replies; everyone else gets a broadcast on
+/// the portal interface, since clients without an address cannot be reached
+/// without an ARP entry.
+fn reply_destination(request: &Message, reply: &Message) -> SocketAddr {
+    let is_nak = reply.message_type() == Some(MessageType::Nak);
+
+    let address = if !request.ciaddr.is_unspecified() && !is_nak {
+        request.ciaddr
+    } else {
+        Ipv4Addr::BROADCAST
+    };
+
+    SocketAddrV4::new(address, CLIENT_PORT).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::state::PortalState;
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 42, 1);
+
+    async fn server() -> DhcpServer {
+        let config = Config::for_tests(&[]);
+        let state = Arc::new(watch::Sender::new(PortalState::new(&config, vec![])));
+
+        DhcpServer {
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            interface: "wlan0".to_string(),
+            server: GATEWAY,
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            range: (Ipv4Addr::new(192, 168, 42, 2), Ipv4Addr::new(192, 168, 42, 4)),
+            captive_url: captive_api_url(&config),
+            leases: HashMap::new(),
+            declined: HashMap::new(),
+            clients: Arc::new(Clients::new(state)),
+        }
+    }
+
+    /// Request from a client, encoded and parsed as if it came off the wire
+    fn request(mac: u8, message_type: MessageType, options: &[(u8, Vec<u8>)]) -> Message {
+        let mut chaddr = [0; 16];
+        chaddr[..6].copy_from_slice(&[0x02, 0, 0, 0, 0, mac]);
+
+        let mut message = Message {
+            op: 1,
+            htype: 1,
+            hlen: 6,
+            xid: u32::from(mac),
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            options: vec![(option::MESSAGE_TYPE, vec![message_type as u8])],
+        };
+        message.options.extend_from_slice(options);
+        Message::parse(&message.encode()).unwrap()
+    }
+
+    fn requesting(address: Ipv4Addr, server: Ipv4Addr) -> [(u8, Vec<u8>); 2] {
+        [
+            (option::REQUESTED_IP, address.octets().to_vec()),
+            (option::SERVER_ID, server.octets().to_vec()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn discover_is_offered_the_first_free_address() {
+        let mut server = server().await;
+
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+        assert_eq!(offer.message_type(), Some(MessageType::Offer));
+        assert_eq!(offer.xid, 1);
+        assert_eq!(offer.yiaddr, Ipv4Addr::new(192, 168, 42, 2));
+        assert_eq!(offer.server_id(), Some(GATEWAY));
+        assert_eq!(offer.option(option::SUBNET_MASK), Some(&[255, 255, 255, 0][..]));
+        assert_eq!(offer.option(option::ROUTER), Some(&GATEWAY.octets()[..]));
+        assert_eq!(offer.option(option::DNS_SERVERS), Some(&GATEWAY.octets()[..]));
+        assert_eq!(
+            offer.option(option::LEASE_TIME),
+            Some(&(LEASE_TIME.as_secs() as u32).to_be_bytes()[..])
+        );
+        assert_eq!(offer.option(option::CAPTIVE_PORTAL), None);
+
+        // The offered address is reserved for the client
+        let other = server.handle(&request(2, MessageType::Discover, &[])).unwrap();
+        assert_eq!(other.yiaddr, Ipv4Addr::new(192, 168, 42, 3));
+    }
+
+    #[tokio::test]
+    async fn captive_portal_api_is_advertised_when_https_serves() {
+        let mut server = server().await;
+        let config = Config::for_tests(&["--tls-port", "443", "--tls-mode", "serve"]);
+        server.captive_url = captive_api_url(&config);
+
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+        assert_eq!(
+            offer.option(option::CAPTIVE_PORTAL),
+            Some(&b"https://192.168.42.1/captive-portal/api"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn discover_gets_the_requested_address() {
+        let mut server = server().await;
+        let requested = [(option::REQUESTED_IP, vec![192, 168, 42, 4])];
+
+        let offer = server.handle(&request(1, MessageType::Discover, &requested)).unwrap();
+        assert_eq!(offer.yiaddr, Ipv4Addr::new(192, 168, 42, 4));
+    }
+
+    #[tokio::test]
+    async fn exhausted_range_gets_no_offer() {
+        let mut server = server().await;
+        for mac in 1..=3 {
+            server.handle(&request(mac, MessageType::Discover, &[])).unwrap();
+        }
+
+        assert!(server.handle(&request(4, MessageType::Discover, &[])).is_none());
+    }
+
+    #[tokio::test]
+    async fn request_for_the_offer_is_acknowledged() {
+        let mut server = server().await;
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+
+        let ack = server
+            .handle(&request(1, MessageType::Request, &requesting(offer.yiaddr, GATEWAY)))
+            .unwrap();
+        assert_eq!(ack.message_type(), Some(MessageType::Ack));
+        assert_eq!(ack.yiaddr, offer.yiaddr);
+        assert_eq!(ack.option(option::SUBNET_MASK), Some(&[255, 255, 255, 0][..]));
+
+        let clients = server.clients.list();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].hw_address, "02:00:00:00:00:01");
+        assert_eq!(clients[0].address, offer.yiaddr);
+    }
+
+    #[tokio::test]
+    async fn request_for_a_leased_address_is_refused() {
+        let mut server = server().await;
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+        server.handle(&request(1, MessageType::Request, &requesting(offer.yiaddr, GATEWAY)));
+
+        let nak = server
+            .handle(&request(2, MessageType::Request, &requesting(offer.yiaddr, GATEWAY)))
+            .unwrap();
+        assert_eq!(nak.message_type(), Some(MessageType::Nak));
+        assert_eq!(nak.yiaddr, Ipv4Addr::UNSPECIFIED);
+        assert!(nak.option(option::SUBNET_MASK).is_none());
+    }
+
+    #[tokio::test]
+    async fn request_outside_the_range_is_refused() {
+        let mut server = server().await;
+        let outside = Ipv4Addr::new(10, 0, 0, 5);
+
+        let nak = server
+            .handle(&request(1, MessageType::Request, &requesting(outside, GATEWAY)))
+            .unwrap();
+        assert_eq!(nak.message_type(), Some(MessageType::Nak));
+        assert!(server.clients.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_to_another_server_drops_the_offer() {
+        let mut server = server().await;
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+
+        let other = Ipv4Addr::new(192, 168, 42, 254);
+        let accepted = request(1, MessageType::Request, &requesting(offer.yiaddr, other));
+        assert!(server.handle(&accepted).is_none());
+        assert!(server.leases.is_empty());
+    }
+
+    #[tokio::test]
+    async fn declined_address_is_not_offered_again() {
+        let mut server = server().await;
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+        let declined = [(option::REQUESTED_IP, offer.yiaddr.octets().to_vec())];
+
+        assert!(server.handle(&request(1, MessageType::Decline, &declined)).is_none());
+
+        let offer = server.handle(&request(1, MessageType::Discover, &[])).unwrap();
+        assert_eq!(offer.yiaddr, Ipv4Addr::new(192, 168, 42, 3));
+    }
+
+    #[tokio::test]
+    async fn packets_without_a_message_type_are_ignored() {
+        let mut server = server().await;
+        let mut message = request(1, MessageType::Discover, &[]);
+        message.options.clear();
+
+        assert!(server.handle(&message).is_none());
+    }
+
+    #[test]
+    fn range_is_parsed() {
+        let range = parse_range("192.168.42.2, 192.168.42.254,1h").unwrap();
+        assert_eq!(range, (Ipv4Addr::new(192, 168, 42, 2), Ipv4Addr::new(192, 168, 42, 254)));
+
+        assert!(parse_range("192.168.42.2").is_err());
+        assert!(parse_range("192.168.42.9,192.168.42.2").is_err());
+        assert!(parse_range("start,end").is_err());
+    }
+
+    fn netmask(range: &str, gateway: [u8; 4]) -> Result<Ipv4Addr> {
+        portal_netmask(range, gateway.into(), parse_range(range).unwrap())
+    }
+
+    #[test]
+    fn netmask_defaults_to_the_hotspot_network() {
+        assert_eq!(
+            netmask("192.168.42.2,192.168.42.254", [192, 168, 42, 1]).unwrap(),
+            Ipv4Addr::new(255, 255, 255, 0)
+        );
+        assert_eq!(
+            netmask("192.168.42.2,192.168.42.254,1h", [192, 168, 42, 1]).unwrap(),
+            Ipv4Addr::new(255, 255, 255, 0)
+        );
+    }
+
+    #[test]
+    fn netmask_covers_the_range() {
+        assert_eq!(
+            netmask("10.0.0.10,10.0.3.200", [10, 0, 0, 1]).unwrap(),
+            Ipv4Addr::new(255, 255, 252, 0)
+        );
+    }
+
+    #[test]
+    fn netmask_is_taken_from_the_range() {
+        assert_eq!(
+            netmask("10.0.0.10,10.0.0.20,255.255.0.0,12h", [10, 0, 0, 1]).unwrap(),
+            Ipv4Addr::new(255, 255, 0, 0)
+        );
+        assert_eq!(
+            netmask("192.168.42.2,192.168.42.6,255.255.255.248", [192, 168, 42, 1]).unwrap(),
+            Ipv4Addr::new(255, 255, 255, 248)
+        );
+    }
+
+    #[test]
+    fn invalid_netmask_is_rejected() {
+        assert!(netmask("10.0.0.10,10.0.0.20,255.0.255.0", [10, 0, 0, 1]).is_err());
+        // The range reaches past the given network
+        assert!(netmask("10.0.0.10,10.0.1.20,255.255.255.0", [10, 0, 0, 1]).is_err());
+        assert!(netmask("10.0.0.10,10.0.0.20,255.255.255.0", [10, 0, 1, 1]).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use std::net::Ipv4Addr;
+
+/// UDP port DHCP servers listen on
+pub const SERVER_PORT: u16 = 67;
+
+/// UDP port DHCP clients listen on
+pub const CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+
+/// Fixed BOOTP header before the magic cookie
+const HEADER_LEN: usize = 236;
+
+/// Marks the start of DHCP options (RFC 2131)
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Replies are padded to the minimum BOOTP message size
+const MIN_MESSAGE_LEN: usize = 300;
+
+/// Option codes used by the server (RFC 2132, RFC 8910)
+pub mod option {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const DNS_SERVERS: u8 = 6;
+    pub const HOSTNAME: u8 = 12;
+    pub const REQUESTED_IP: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_ID: u8 = 54;
+    pub const RENEWAL_TIME: u8 = 58;
+    pub const REBINDING_TIME: u8 = 59;
+    pub const CAPTIVE_PORTAL: u8 = 114;
+    pub const END: u8 = 255;
+}
+
+/// DHCP message type (option 53)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            8 => Self::Inform,
+            _ => return None,
+        })
+    }
+}
+
+/// DHCPv4 message
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub xid: u32,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+    pub options: Vec<(u8, Vec<u8>)>,
+}
+
+impl Message {
+    /// Parse a client request, ignoring anything that is not a DHCP request
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        Self::decode(buf).filter(|message| message.op == BOOTREQUEST)
+    }
+
+    /// Decode a request or a reply
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN + MAGIC_COOKIE.len()
+            || buf[HEADER_LEN..HEADER_LEN + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let addr = |at: usize| Ipv4Addr::new(buf[at], buf[at + 1], buf[at + 2], buf[at + 3]);
+
+        let mut chaddr = [0; 16];
+        chaddr.copy_from_slice(&buf[28..44]);
+
+        let mut options = Vec::new();
+        let mut rest = &buf[HEADER_LEN + 4..];
+        while let Some((&code, tail)) = rest.split_first() {
+            match code {
+                option::PAD => rest = tail,
+                option::END => break,
+                _ => {
+                    let (&len, tail) = tail.split_first()?;
+                    let value = tail.get(..usize::from(len))?;
+                    options.push((code, value.to_vec()));
+                    rest = &tail[usize::from(len)..];
+                },
+            }
+        }
+
+        Some(Self {
+            op: buf[0],
+            htype: buf[1],
+            hlen: buf[2],
+            xid: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            flags: u16::from_be_bytes([buf[10], buf[11]]),
+            ciaddr: addr(12),
+            yiaddr: addr(16),
+            siaddr: addr(20),
+            giaddr: addr(24),
+            chaddr,
+            options,
+        })
+    }
+
+    /// Encode the message for sending
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(MIN_MESSAGE_LEN);
+        buf.extend_from_slice(&[self.op, self.htype, self.hlen, 0]);
+        buf.extend_from_slice(&self.xid.to_be_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        for addr in [self.ciaddr, self.yiaddr, self.siaddr, self.giaddr] {
+            buf.extend_from_slice(&addr.octets());
+        }
+        buf.extend_from_slice(&self.chaddr);
+        // sname and file are unused
+        buf.resize(HEADER_LEN, 0);
+        buf.extend_from_slice(&MAGIC_COOKIE);
+
+        for (code, value) in &self.options {
+            // Longer values are split into several options (RFC 3396)
+            for chunk in value.chunks(usize::from(u8::MAX)) {
+                buf.push(*code);
+                buf.push(chunk.len() as u8);
+                buf.extend_from_slice(chunk);
+            }
+        }
+        buf.push(option::END);
+
+        if buf.len() < MIN_MESSAGE_LEN {
+            buf.resize(MIN_MESSAGE_LEN, option::PAD);
+        }
+        buf
+    }
+
+    /// Start a reply to this message
+    pub fn reply(&self, message_type: MessageType, yiaddr: Ipv4Addr, server: Ipv4Addr) -> Self {
+        Self {
+            op: BOOTREPLY,
+            htype: self.htype,
+            hlen: self.hlen,
+            xid: self.xid,
+            flags: self.flags,
+            ciaddr: self.ciaddr,
+            yiaddr,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: self.giaddr,
+            chaddr: self.chaddr,
+            options: vec![
+                (option::MESSAGE_TYPE, vec![message_type as u8]),
+                (option::SERVER_ID, server.octets().to_vec()),
+            ],
+        }
+    }
+
+    pub fn add_option(&mut self, code: u8, value: impl Into<Vec<u8>>) {
+        self.options.push((code, value.into()));
+    }
+
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        match self.option(option::MESSAGE_TYPE)? {
+            [value] => MessageType::from_u8(*value),
+            _ => None,
+        }
+    }
+
+    fn address_option(&self, code: u8) -> Option<Ipv4Addr> {
+        let octets: [u8; 4] = self.option(code)?.try_into().ok()?;
+        Some(Ipv4Addr::from(octets))
+    }
+
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.address_option(option::REQUESTED_IP)
+    }
+
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.address_option(option::SERVER_ID)
+    }
+
+    pub fn hostname(&self) -> Option<String> {
+        let value = self.option(option::HOSTNAME)?;
+        Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string())
+    }
+
+    /// Client hardware address formatted like `aa:bb:cc:dd:ee:ff`
+    pub fn hw_address(&self) -> String {
+        let len = usize::from(self.hlen).min(self.chaddr.len());
+        self.chaddr[..len]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0xaa, 0xbb, 0xcc];
+
+    fn request(message_type: MessageType) -> Message {
+        let mut chaddr = [0; 16];
+        chaddr[..6].copy_from_slice(&MAC);
+
+        Message {
+            op: BOOTREQUEST,
+            htype: 1,
+            hlen: 6,
+            xid: 0x1234_5678,
+            flags: 0x8000,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            options: vec![(option::MESSAGE_TYPE, vec![message_type as u8])],
+        }
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let mut discover = request(MessageType::Discover);
+        discover.add_option(option::REQUESTED_IP, [192, 168, 42, 7]);
+        discover.add_option(option::HOSTNAME, *b"laptop\0");
+
+        let encoded = discover.encode();
+        assert_eq!(encoded.len(), MIN_MESSAGE_LEN);
+
+        let parsed = Message::parse(&encoded).unwrap();
+        assert_eq!(parsed.xid, 0x1234_5678);
+        assert_eq!(parsed.flags, 0x8000);
+        assert_eq!(parsed.message_type(), Some(MessageType::Discover));
+        assert_eq!(parsed.requested_ip(), Some(Ipv4Addr::new(192, 168, 42, 7)));
+        assert_eq!(parsed.hostname().as_deref(), Some("laptop"));
+        assert_eq!(parsed.hw_address(), "02:00:00:aa:bb:cc");
+    }
+
+    #[test]
+    fn reply_keeps_transaction_and_round_trips() {
+        let request = request(MessageType::Request);
+        let server = Ipv4Addr::new(192, 168, 42, 1);
+        let mut ack = request.reply(MessageType::Ack, Ipv4Addr::new(192, 168, 42, 2), server);
+        ack.add_option(option::LEASE_TIME, 3600u32.to_be_bytes());
+
+        let decoded = Message::decode(&ack.encode()).unwrap();
+        assert_eq!(decoded.op, BOOTREPLY);
+        assert_eq!(decoded.xid, request.xid);
+        assert_eq!(decoded.chaddr, request.chaddr);
+        assert_eq!(decoded.yiaddr, Ipv4Addr::new(192, 168, 42, 2));
+        assert_eq!(decoded.message_type(), Some(MessageType::Ack));
+        assert_eq!(decoded.server_id(), Some(server));
+        assert_eq!(decoded.option(option::LEASE_TIME), Some(&3600u32.to_be_bytes()[..]));
+
+        // Replies are not taken for requests
+        assert!(Message::parse(&ack.encode()).is_none());
+    }
+
+    #[test]
+    fn long_options_are_split() {
+        let mut reply = request(MessageType::Offer);
+        reply.add_option(option::CAPTIVE_PORTAL, vec![b'a'; 300]);
+
+        let decoded = Message::decode(&reply.encode()).unwrap();
+        let parts: Vec<_> = decoded
+            .options
+            .iter()
+            .filter(|(code, _)| *code == option::CAPTIVE_PORTAL)
+            .map(|(_, value)| value.len())
+            .collect();
+        assert_eq!(parts, [255, 45]);
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let encoded = request(MessageType::Discover).encode();
+
+        assert!(Message::parse(&[]).is_none());
+        assert!(Message::parse(&encoded[..HEADER_LEN]).is_none());
+        assert!(Message::parse(&encoded[..HEADER_LEN + 2]).is_none());
+    }
+
+    #[test]
+    fn missing_magic_cookie_is_rejected() {
+        let mut encoded = request(MessageType::Discover).encode();
+        encoded[HEADER_LEN] = 0;
+        assert!(Message::parse(&encoded).is_none());
+    }
+
+    #[test]
+    fn option_past_the_end_is_rejected() {
+        let mut encoded = request(MessageType::Discover).encode();
+        encoded.truncate(HEADER_LEN + MAGIC_COOKIE.len());
+        encoded.extend_from_slice(&[option::HOSTNAME, 10, b'a', b'b']);
+        assert!(Message::parse(&encoded).is_none());
+
+        // An option code without its length
+        encoded.truncate(HEADER_LEN + MAGIC_COOKIE.len());
+        encoded.push(option::HOSTNAME);
+        assert!(Message::parse(&encoded).is_none());
+    }
+
+    #[test]
+    fn options_without_end_and_with_padding_are_accepted() {
+        let mut encoded = request(MessageType::Discover).encode();
+        encoded.truncate(HEADER_LEN + MAGIC_COOKIE.len());
+        encoded.extend_from_slice(&[option::PAD, option::MESSAGE_TYPE, 1, 3, option::PAD]);
+
+        let parsed = Message::parse(&encoded).unwrap();
+        assert_eq!(parsed.message_type(), Some(MessageType::Request));
+    }
+
+    #[test]
+    fn invalid_message_type_is_ignored() {
+        let mut message = request(MessageType::Discover);
+        message.options = vec![(option::MESSAGE_TYPE, vec![42])];
+        assert_eq!(Message::parse(&message.encode()).unwrap().message_type(), None);
+
+        message.options = vec![(option::MESSAGE_TYPE, vec![1, 2])];
+        assert_eq!(Message::parse(&message.encode()).unwrap().message_type(), None);
+    }
+}
@@ -0,0 +1,489 @@
+mod packet;
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+
+use packet::{
+    encode_name, error_response, rcode, rtype, Query, QueryError, Response, Section, CLASS_IN,
+    PORT,
+};
+
+/// TTL of local answers, so clients do not keep the portal address once
+/// they are back on a real network
+const LOCAL_TTL: u32 = 0;
+
+/// How long to wait for each upstream resolver
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a client may keep a TCP connection open
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queries answered at the same time over UDP, and TCP connections served
+/// at the same time
+const MAX_CONCURRENT: usize = 64;
+
+/// Resolvers used for the allowlist when none are configured
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Which DNS server answers on the portal network
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsBackend {
+    /// In-process DNS responder
+    Builtin,
+    /// External dnsmasq process
+    Dnsmasq,
+}
+
+/// Answers queries for the portal network
+struct Resolver {
+    gateway: Ipv4Addr,
+    hostname: String,
+    /// Names forwarded upstream, with their subdomains
+    allow: Vec<String>,
+    upstreams: Vec<SocketAddr>,
+}
+
+/// In-process wildcard DNS responder for the portal network
+///
+/// Every name resolves to the gateway, except names on the allowlist, which
+/// are forwarded to the upstream resolvers.
+pub struct DnsServer {
+    udp: UdpSocket,
+    tcp: TcpListener,
+    resolver: Resolver,
+}
+
+impl DnsServer {
+    /// Bind the DNS port on the gateway address of the portal interface
+    pub fn bind(config: &Config, interface: &str) -> Result<Self> {
+        let allow: Vec<String> = config
+            .dns_allow
+            .iter()
+            .map(|name| normalize_name(name))
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        let upstreams = if config.dns_upstream.is_empty() {
+            system_resolvers()
+        } else {
+            config.dns_upstream.clone()
+        };
+
+        if !allow.is_empty() && upstreams.is_empty() {
+            return Err(AppError::Dns(format!(
+                "no upstream resolvers for the allowlist, none configured or in {}",
+                RESOLV_CONF
+            )));
+        }
+
+        let address = SocketAddr::from((config.gateway, PORT));
+        let bind_error =
+            |e: std::io::Error| AppError::Dns(format!("binding port {} on {}: {}", PORT, interface, e));
+        let udp = bind_udp(interface, address).map_err(bind_error)?;
+        let tcp = bind_tcp(interface, address).map_err(bind_error)?;
+
+        info!("Serving DNS on {}", interface);
+        if !allow.is_empty() {
+            let upstream_list: Vec<String> = upstreams.iter().map(ToString::to_string).collect();
+            info!(
+                "Forwarding {} to {}",
+                allow.join(", "),
+                upstream_list.join(", ")
+            );
+        }
+
+        Ok(Self {
+            udp,
+            tcp,
+            resolver: Resolver {
+                gateway: config.gateway,
+                hostname: config.portal_hostname.clone(),
+                allow,
+                upstreams,
+            },
+        })
+    }
+
+    /// Serve queries in a background task
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        let resolver = Arc::new(self.resolver);
+
+        tokio::join!(
+            serve_udp(Arc::new(self.udp), Arc::clone(&resolver)),
+            serve_tcp(self.tcp, resolver),
+        );
+    }
+}
+
+impl Resolver {
+    /// Reply to a request, if it needs one
+    async fn answer(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let query = match Query::parse(request) {
+            Ok(query) => query,
+            Err(QueryError::Ignored) => return None,
+            Err(QueryError::Reply(code)) => return error_response(request, code),
+        };
+
+        if self.is_allowed(&query.name) {
+            debug!("Forwarding DNS query for {} (type {})", query.name, query.qtype);
+            let reply = match self.forward(request, query.id).await {
+                Some(reply) => reply,
+                None => Response::new(&query, rcode::SERVFAIL).encode(),
+            };
+            return Some(reply);
+        }
+
+        debug!("DNS query for {} (type {})", query.name, query.qtype);
+        Some(self.local_answer(&query))
+    }
+
+    /// Answer as the authoritative server of every name
+    fn local_answer(&self, query: &Query) -> Vec<u8> {
+        if query.qclass != CLASS_IN {
+            return Response::new(query, rcode::REFUSED).encode();
+        }
+
+        let gateway = self.gateway.octets();
+        let mut response = Response::new(query, rcode::NOERROR);
+
+        match query.qtype {
+            rtype::A | rtype::ANY => {
+                response.add(Section::Answer, rtype::A, LOCAL_TTL, &gateway);
+            },
+            rtype::NS => {
+                response.add(Section::Answer, rtype::NS, LOCAL_TTL, &encode_name(&self.hostname));
+                response.add_for(Section::Additional, &self.hostname, rtype::A, LOCAL_TTL, &gateway);
+            },
+            rtype::SOA => {
+                response.add(Section::Answer, rtype::SOA, LOCAL_TTL, &self.soa());
+            },
+            // AAAA, HTTPS and everything else get a NODATA answer with the
+            // SOA for negative caching (RFC 2308), so clients fall back to
+            // the A record instead of waiting for a timeout
+            _ => {
+                response.add(Section::Authority, rtype::SOA, LOCAL_TTL, &self.soa());
+            },
+        }
+
+        response.encode()
+    }
+
+    /// SOA record data naming the portal as the primary server
+    fn soa(&self) -> Vec<u8> {
+        let mut rdata = encode_name(&self.hostname);
+        rdata.extend(encode_name(&format!("hostmaster.{}", self.hostname)));
+        // Serial, refresh, retry, expire and the negative caching TTL
+        for value in [1, 3600, 600, 86400, LOCAL_TTL] {
+            rdata.extend_from_slice(&value.to_be_bytes());
+        }
+        rdata
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allow.iter().any(|allowed| {
+            name.strip_suffix(allowed.as_str())
+                .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+        })
+    }
+
+    /// Forward a request to the first upstream resolver that answers
+    async fn forward(&self, request: &[u8], id: u16) -> Option<Vec<u8>> {
+        for upstream in &self.upstreams {
+            match timeout(UPSTREAM_TIMEOUT, exchange(*upstream, request, id)).await {
+                Ok(Ok(reply)) => return Some(reply),
+                Ok(Err(e)) => debug!("Upstream resolver {} failed: {}", upstream, e),
+                Err(_) => debug!("Upstream resolver {} timed out", upstream),
+            }
+        }
+
+        warn!("No upstream resolver answered");
+        None
+    }
+}
+
+async fn serve_udp(socket: Arc<UdpSocket>, resolver: Arc<Resolver>) {
+    let limit = Arc::new(Semaphore::new(MAX_CONCURRENT));
+    let mut buf = [0; 4096];
+
+    loop {
+        // Queries wait in the socket buffer while all permits are taken
+        let Ok(permit) = Arc::clone(&limit).acquire_owned().await else {
+            return;
+        };
+
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Receiving DNS query failed: {}", e);
+                continue;
+            },
+        };
+
+        // Forwarded queries wait for upstream, so every query gets a task
+        let request = buf[..len].to_vec();
+        let socket = Arc::clone(&socket);
+        let resolver = Arc::clone(&resolver);
+        tokio::spawn(async move {
+            if let Some(reply) = resolver.answer(&request).await {
+                if let Err(e) = socket.send_to(&reply, peer).await {
+                    debug!("Sending DNS reply to {} failed: {}", peer, e);
+                }
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, resolver: Arc<Resolver>) {
+    let limit = Arc::new(Semaphore::new(MAX_CONCURRENT));
+
+    loop {
+        let Ok(permit) = Arc::clone(&limit).acquire_owned().await else {
+            return;
+        };
+
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Accepting DNS connection failed: {}", e);
+                continue;
+            },
+        };
+
+        let resolver = Arc::clone(&resolver);
+        tokio::spawn(async move {
+            if let Ok(Err(e)) = timeout(TCP_TIMEOUT, serve_connection(stream, &resolver)).await {
+                debug!("DNS connection from {} failed: {}", peer, e);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Answer length-prefixed queries until the client closes the connection
+async fn serve_connection(mut stream: TcpStream, resolver: &Resolver) -> std::io::Result<()> {
+    loop {
+        let len = match stream.read_u16().await {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let mut request = vec![0; usize::from(len)];
+        stream.read_exact(&mut request).await?;
+
+        if let Some(reply) = resolver.answer(&request).await {
+            stream.write_u16(reply.len() as u16).await?;
+            stream.write_all(&reply).await?;
+        }
+    }
+}
+
+/// Send a request to a resolver and wait for the reply with the same ID
+async fn exchange(upstream: SocketAddr, request: &[u8], id: u16) -> std::io::Result<Vec<u8>> {
+    let local: SocketAddr = match upstream {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(upstream).await?;
+    socket.send(request).await?;
+
+    let mut buf = vec![0; 4096];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        if len >= 2 && buf[..2] == id.to_be_bytes() {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+
+/// Parse an upstream resolver given as an address with an optional port
+pub fn parse_upstream(value: &str) -> std::result::Result<SocketAddr, String> {
+    value
+        .parse::<SocketAddr>()
+        .or_else(|_| value.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, PORT)))
+        .map_err(|_| format!("'{}' is not an IP address with an optional port", value))
+}
+
+/// Name servers of the host
+fn system_resolvers() -> Vec<SocketAddr> {
+    let Ok(contents) = std::fs::read_to_string(RESOLV_CONF) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, PORT))
+        .collect()
+}
+
+/// Lower case name without wildcard prefix or trailing dot
+fn normalize_name(name: &str) -> String {
+    let name = name.trim().trim_end_matches('.');
+    name.strip_prefix("*.").unwrap_or(name).to_ascii_lowercase()
+}
+
+/// UDP socket on the gateway address, which may not be assigned yet
+fn bind_udp(interface: &str, address: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind_device(Some(interface.as_bytes()))?;
+    socket.set_freebind_v4(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+fn bind_tcp(interface: &str, address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind_device(Some(interface.as_bytes()))?;
+    socket.set_freebind_v4(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 42, 1);
+
+    fn resolver(allow: &[&str]) -> Resolver {
+        Resolver {
+            gateway: GATEWAY,
+            hostname: "portal.local".to_string(),
+            allow: allow.iter().map(|name| normalize_name(name)).collect(),
+            upstreams: Vec::new(),
+        }
+    }
+
+    fn query(name: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+        let mut buf = vec![0xab, 0xcd, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        buf.extend(encode_name(name));
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&qclass.to_be_bytes());
+        buf
+    }
+
+    fn field(buf: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([buf[at], buf[at + 1]])
+    }
+
+    /// Response code and the answer, authority and additional counts
+    fn summary(reply: &[u8]) -> (u8, [u16; 3]) {
+        let code = (field(reply, 2) & 0x000f) as u8;
+        (code, [field(reply, 6), field(reply, 8), field(reply, 10)])
+    }
+
+    #[tokio::test]
+    async fn every_name_resolves_to_the_gateway() {
+        let request = query("connectivitycheck.gstatic.com", 1, CLASS_IN);
+        let reply = resolver(&[]).answer(&request).await.unwrap();
+
+        assert_eq!(field(&reply, 0), 0xabcd);
+        assert_eq!(summary(&reply), (rcode::NOERROR, [1, 0, 0]));
+        assert_eq!(reply[reply.len() - 4..], GATEWAY.octets());
+        // TTL of the answer
+        assert_eq!(reply[reply.len() - 10..reply.len() - 6], LOCAL_TTL.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn other_types_get_nodata_with_the_soa() {
+        // AAAA
+        let request = query("example.com", 28, CLASS_IN);
+        let reply = resolver(&[]).answer(&request).await.unwrap();
+
+        assert_eq!(summary(&reply), (rcode::NOERROR, [0, 1, 0]));
+        assert_ne!(field(&reply, 2) & 0x0400, 0, "authoritative");
+
+        let record = request.len();
+        assert_eq!(reply[record..record + 2], [0xc0, 12]);
+        assert_eq!(field(&reply, record + 2), rtype::SOA);
+        assert_eq!(reply[record + 12..], resolver(&[]).soa());
+    }
+
+    #[test]
+    fn soa_names_the_portal() {
+        let soa = resolver(&[]).soa();
+        let primary = encode_name("portal.local");
+
+        assert_eq!(soa[..primary.len()], primary);
+        assert_eq!(soa[soa.len() - 4..], LOCAL_TTL.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn ns_query_names_the_portal_with_its_address() {
+        let reply = resolver(&[]).answer(&query("", rtype::NS, CLASS_IN)).await.unwrap();
+        assert_eq!(summary(&reply), (rcode::NOERROR, [1, 0, 1]));
+    }
+
+    #[tokio::test]
+    async fn other_classes_are_refused() {
+        let reply = resolver(&[]).answer(&query("example.com", 1, 3)).await.unwrap();
+        assert_eq!(summary(&reply), (rcode::REFUSED, [0, 0, 0]));
+    }
+
+    #[tokio::test]
+    async fn truncated_queries_get_formerr() {
+        let request = query("example.com", 1, CLASS_IN);
+
+        let reply = resolver(&[]).answer(&request[..request.len() - 2]).await.unwrap();
+        assert_eq!(reply.len(), 12);
+        assert_eq!(summary(&reply), (rcode::FORMERR, [0, 0, 0]));
+
+        assert!(resolver(&[]).answer(&request[..8]).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn allowed_names_without_upstream_fail() {
+        let request = query("api.example.com", 1, CLASS_IN);
+        let reply = resolver(&["*.example.com."]).answer(&request).await.unwrap();
+        assert_eq!(summary(&reply), (rcode::SERVFAIL, [0, 0, 0]));
+    }
+
+    #[test]
+    fn allowlist_matches_subdomains() {
+        let resolver = resolver(&["*.Example.com."]);
+
+        assert!(resolver.is_allowed("example.com"));
+        assert!(resolver.is_allowed("api.example.com"));
+        assert!(!resolver.is_allowed("badexample.com"));
+        assert!(!resolver.is_allowed("example.com.evil"));
+    }
+
+    #[test]
+    fn upstream_port_defaults_to_dns() {
+        assert_eq!(parse_upstream("1.1.1.1").unwrap(), "1.1.1.1:53".parse().unwrap());
+        assert_eq!(parse_upstream("1.1.1.1:5353").unwrap(), "1.1.1.1:5353".parse().unwrap());
+        assert_eq!(parse_upstream("::1").unwrap(), "[::1]:53".parse().unwrap());
+        assert!(parse_upstream("resolver").is_err());
+    }
+}
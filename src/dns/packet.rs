@@ -0,0 +1,351 @@
+/// UDP and TCP port DNS servers listen on
+pub const PORT: u16 = 53;
+
+const HEADER_LEN: usize = 12;
+
+/// Longest name allowed in a message (RFC 1035)
+const MAX_NAME_LEN: usize = 255;
+
+/// Record types answered with data (RFC 1035)
+pub mod rtype {
+    pub const A: u16 = 1;
+    pub const NS: u16 = 2;
+    pub const SOA: u16 = 6;
+    pub const ANY: u16 = 255;
+}
+
+/// The Internet class
+pub const CLASS_IN: u16 = 1;
+
+/// Response codes (RFC 1035)
+pub mod rcode {
+    pub const NOERROR: u8 = 0;
+    pub const FORMERR: u8 = 1;
+    pub const SERVFAIL: u8 = 2;
+    pub const NOTIMP: u8 = 4;
+    pub const REFUSED: u8 = 5;
+}
+
+const FLAG_QR: u16 = 0x8000;
+const FLAG_AA: u16 = 0x0400;
+const FLAG_RD: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+
+/// Why a message could not be answered as a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryError {
+    /// Too short to carry a header, nothing to reply to
+    Ignored,
+    /// Reply with the given response code
+    Reply(u8),
+}
+
+/// Standard query with a single question
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub id: u16,
+    flags: u16,
+    /// Lower case name without the trailing dot, empty for the root
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    /// The question section as received
+    question: Vec<u8>,
+}
+
+impl Query {
+    /// Parse a query, ignoring anything that is not one
+    pub fn parse(buf: &[u8]) -> Result<Self, QueryError> {
+        if buf.len() < HEADER_LEN {
+            return Err(QueryError::Ignored);
+        }
+
+        let flags = u16::from_be_bytes([buf[2], buf[3]]);
+        if flags & FLAG_QR != 0 {
+            return Err(QueryError::Ignored);
+        }
+        if flags & OPCODE_MASK != 0 {
+            return Err(QueryError::Reply(rcode::NOTIMP));
+        }
+        if u16::from_be_bytes([buf[4], buf[5]]) != 1 {
+            return Err(QueryError::Reply(rcode::FORMERR));
+        }
+
+        let malformed = QueryError::Reply(rcode::FORMERR);
+        let (name, len) = parse_name(&buf[HEADER_LEN..]).ok_or(malformed)?;
+        let end = HEADER_LEN + len;
+        let fixed = buf.get(end..end + 4).ok_or(malformed)?;
+
+        Ok(Self {
+            id: u16::from_be_bytes([buf[0], buf[1]]),
+            flags,
+            name,
+            qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+            qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            question: buf[HEADER_LEN..end + 4].to_vec(),
+        })
+    }
+}
+
+/// Header-only reply to a message that could not be parsed as a query
+pub fn error_response(buf: &[u8], code: u8) -> Option<Vec<u8>> {
+    let header = buf.get(..HEADER_LEN)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    let flags = FLAG_QR | (flags & (OPCODE_MASK | FLAG_RD)) | u16::from(code);
+
+    let mut reply = vec![0; HEADER_LEN];
+    reply[..2].copy_from_slice(&header[..2]);
+    reply[2..4].copy_from_slice(&flags.to_be_bytes());
+    Some(reply)
+}
+
+/// Section a record is added to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// Authoritative response to a query
+///
+/// Records must be added in section order.
+pub struct Response {
+    buf: Vec<u8>,
+    counts: [u16; 3],
+}
+
+impl Response {
+    pub fn new(query: &Query, code: u8) -> Self {
+        let flags = FLAG_QR | FLAG_AA | (query.flags & FLAG_RD) | u16::from(code);
+
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&query.id.to_be_bytes());
+        buf.extend_from_slice(&flags.to_be_bytes());
+        buf.extend_from_slice(&1u16.to_be_bytes());
+        buf.extend_from_slice(&[0; 6]);
+        buf.extend_from_slice(&query.question);
+
+        Self { buf, counts: [0; 3] }
+    }
+
+    /// Add a record owned by the queried name
+    pub fn add(&mut self, section: Section, rtype: u16, ttl: u32, rdata: &[u8]) {
+        // Compression pointer to the name in the question
+        self.add_record(section, &[0xc0, HEADER_LEN as u8], rtype, ttl, rdata);
+    }
+
+    /// Add a record owned by another name
+    pub fn add_for(&mut self, section: Section, name: &str, rtype: u16, ttl: u32, rdata: &[u8]) {
+        self.add_record(section, &encode_name(name), rtype, ttl, rdata);
+    }
+
+    fn add_record(&mut self, section: Section, owner: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) {
+        self.buf.extend_from_slice(owner);
+        self.buf.extend_from_slice(&rtype.to_be_bytes());
+        self.buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        self.buf.extend_from_slice(&ttl.to_be_bytes());
+        self.buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(rdata);
+        self.counts[section as usize] += 1;
+    }
+
+    pub fn encode(mut self) -> Vec<u8> {
+        for (i, count) in self.counts.iter().enumerate() {
+            let at = 6 + i * 2;
+            self.buf[at..at + 2].copy_from_slice(&count.to_be_bytes());
+        }
+        self.buf
+    }
+}
+
+/// Encode a dotted name as uncompressed labels
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(name.len() + 2);
+    for label in name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()) {
+        buf.push(label.len().min(63) as u8);
+        buf.extend_from_slice(&label.as_bytes()[..label.len().min(63)]);
+    }
+    buf.push(0);
+    buf
+}
+
+/// Parse an uncompressed name, returning it with the length it took
+///
+/// Queries never need compression since the question is the first name in
+/// the message.
+fn parse_name(buf: &[u8]) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut at = 0;
+
+    loop {
+        let len = usize::from(*buf.get(at)?);
+        at += 1;
+        if len == 0 {
+            break;
+        }
+        if len > 63 || at + len > MAX_NAME_LEN {
+            return None;
+        }
+        let label = buf.get(at..at + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        at += len;
+    }
+
+    Some((labels.join("."), at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        buf.extend(encode_name(name));
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        buf
+    }
+
+    fn count(buf: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([buf[at], buf[at + 1]])
+    }
+
+    /// Read a possibly compressed name, returning it with the position after it
+    fn read_name(buf: &[u8], mut at: usize) -> (String, usize) {
+        let mut labels = Vec::new();
+        let mut end = None;
+
+        loop {
+            let len = usize::from(buf[at]);
+            if len & 0xc0 == 0xc0 {
+                end.get_or_insert(at + 2);
+                at = (len & 0x3f) << 8 | usize::from(buf[at + 1]);
+                continue;
+            }
+            at += 1;
+            if len == 0 {
+                break;
+            }
+            labels.push(String::from_utf8_lossy(&buf[at..at + len]).into_owned());
+            at += len;
+        }
+
+        (labels.join("."), end.unwrap_or(at))
+    }
+
+    #[test]
+    fn query_is_parsed() {
+        let query = Query::parse(&query("Example.COM.", rtype::A)).unwrap();
+        assert_eq!(query.id, 0x1234);
+        assert_eq!(query.name, "example.com");
+        assert_eq!(query.qtype, rtype::A);
+        assert_eq!(query.qclass, CLASS_IN);
+    }
+
+    #[test]
+    fn root_query_is_parsed() {
+        assert_eq!(Query::parse(&query("", rtype::NS)).unwrap().name, "");
+    }
+
+    #[test]
+    fn answers_point_to_the_question_name() {
+        let request = query("portal.example", rtype::A);
+        let parsed = Query::parse(&request).unwrap();
+
+        let mut response = Response::new(&parsed, rcode::NOERROR);
+        response.add(Section::Answer, rtype::A, 0, &[192, 168, 42, 1]);
+        response.add_for(Section::Additional, "ns.example", rtype::A, 0, &[192, 168, 42, 1]);
+        let buf = response.encode();
+
+        let flags = count(&buf, 2);
+        assert_eq!(flags & (FLAG_QR | FLAG_AA | FLAG_RD), FLAG_QR | FLAG_AA | FLAG_RD);
+        assert_eq!([count(&buf, 4), count(&buf, 6), count(&buf, 8), count(&buf, 10)], [1, 1, 0, 1]);
+        // The question is copied as received
+        assert_eq!(buf[HEADER_LEN..request.len()], request[HEADER_LEN..]);
+
+        // The answer owner is a compression pointer to the question
+        let answer = request.len();
+        assert_eq!(buf[answer..answer + 2], [0xc0, HEADER_LEN as u8]);
+        let (owner, at) = read_name(&buf, answer);
+        assert_eq!(owner, "portal.example");
+        assert_eq!(count(&buf, at), rtype::A);
+        assert_eq!(count(&buf, at + 8), 4);
+        assert_eq!(buf[at + 10..at + 14], [192, 168, 42, 1]);
+
+        // Records owned by other names are written out in full
+        let (owner, _) = read_name(&buf, at + 14);
+        assert_eq!(owner, "ns.example");
+    }
+
+    #[test]
+    fn compressed_question_is_rejected() {
+        let mut request = query("", rtype::A);
+        request.splice(HEADER_LEN..HEADER_LEN + 1, [0xc0, HEADER_LEN as u8]);
+        assert_eq!(Query::parse(&request).unwrap_err(), QueryError::Reply(rcode::FORMERR));
+    }
+
+    #[test]
+    fn truncated_header_is_ignored() {
+        let request = query("example.com", rtype::A);
+        assert_eq!(Query::parse(&request[..HEADER_LEN - 1]).unwrap_err(), QueryError::Ignored);
+        assert!(error_response(&request[..HEADER_LEN - 1], rcode::FORMERR).is_none());
+    }
+
+    #[test]
+    fn truncated_question_is_malformed() {
+        let request = query("example.com", rtype::A);
+
+        for len in [HEADER_LEN, HEADER_LEN + 5, request.len() - 4, request.len() - 1] {
+            assert_eq!(
+                Query::parse(&request[..len]).unwrap_err(),
+                QueryError::Reply(rcode::FORMERR),
+                "truncated to {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn overlong_names_are_malformed() {
+        let mut request = query("", rtype::A);
+        request.splice(HEADER_LEN..HEADER_LEN, [64].into_iter().chain([b'a'; 64]));
+        assert_eq!(Query::parse(&request).unwrap_err(), QueryError::Reply(rcode::FORMERR));
+
+        let name = vec!["a".repeat(63); 5].join(".");
+        assert_eq!(
+            Query::parse(&query(&name, rtype::A)).unwrap_err(),
+            QueryError::Reply(rcode::FORMERR)
+        );
+    }
+
+    #[test]
+    fn responses_and_other_opcodes_are_not_queries() {
+        let mut request = query("example.com", rtype::A);
+        request[2] |= 0x80;
+        assert_eq!(Query::parse(&request).unwrap_err(), QueryError::Ignored);
+
+        let mut request = query("example.com", rtype::A);
+        // Status request
+        request[2] = 0x10;
+        assert_eq!(Query::parse(&request).unwrap_err(), QueryError::Reply(rcode::NOTIMP));
+
+        let reply = error_response(&request, rcode::NOTIMP).unwrap();
+        assert_eq!(reply.len(), HEADER_LEN);
+        assert_eq!(reply[..2], [0x12, 0x34]);
+        assert_eq!(count(&reply, 2), FLAG_QR | 0x1000 | u16::from(rcode::NOTIMP));
+    }
+
+    #[test]
+    fn multiple_questions_are_malformed() {
+        let mut request = query("example.com", rtype::A);
+        request[5] = 2;
+        assert_eq!(Query::parse(&request).unwrap_err(), QueryError::Reply(rcode::FORMERR));
+    }
+
+    #[test]
+    fn names_are_encoded_as_labels() {
+        assert_eq!(encode_name("portal.local."), b"\x06portal\x05local\x00");
+        assert_eq!(encode_name(""), [0]);
+    }
+}
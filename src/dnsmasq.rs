@@ -1,36 +1,395 @@
-use std::process::{Child, Command};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::Pid;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, timeout, timeout_at, Instant};
+use tracing::{info, warn};
 
-use network_manager::Device;
-
+use crate::captive::captive_api_url;
+use crate::clients::DNSMASQ_LEASE_FILE;
 use crate::config::Config;
+use crate::dhcp::DhcpBackend;
+use crate::dns::DnsBackend;
 use crate::errors::{AppError, Result};
 
-/// Start dnsmasq for DHCP and DNS on the portal interface
-pub fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
-    let args = [
-        &format!("--address=/#/{}", config.gateway),
-        &format!("--dhcp-range={}", config.dhcp_range),
-        &format!("--dhcp-option=option:router,{}", config.gateway),
-        &format!("--interface={}", device.interface()),
-        "--keep-in-foreground",
-        "--bind-interfaces",
-        "--except-interface=lo",
-        "--conf-file",
-        "--no-hosts",
+/// dnsmasq exiting within this time of its first start is a startup failure
+const STARTUP_PERIOD: Duration = Duration::from_secs(3);
+
+/// Delay before the first restart, doubled after each crash
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// How long dnsmasq gets to exit on SIGTERM before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether either backend of the portal network needs dnsmasq
+pub fn dnsmasq_required(config: &Config) -> bool {
+    config.dhcp_backend == DhcpBackend::Dnsmasq || config.dns_backend == DnsBackend::Dnsmasq
+}
+
+/// Supervised dnsmasq process
+///
+/// Output goes to the log with the `dnsmasq` target, and the process is
+/// restarted with backoff when it exits.
+pub struct Dnsmasq {
+    args: Vec<String>,
+    /// None while waiting to restart
+    child: Option<Child>,
+    started: Instant,
+    /// Whether the process ever ran past the startup period
+    established: bool,
+    restart_delay: Duration,
+    restart_at: Option<Instant>,
+    /// Exit during the first start, kept until it is reported
+    failed_start: Option<ExitStatus>,
+    /// Tasks logging the output of the current process
+    output: Vec<JoinHandle<()>>,
+    /// Last line written to stderr, to explain a failed start
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl Dnsmasq {
+    /// Start dnsmasq on the portal interface
+    pub fn start(config: &Config, interface: &str) -> Result<Self> {
+        // The lease file lives in the state directory
+        fs::create_dir_all(&config.state_directory)?;
+
+        let mut dnsmasq = Self {
+            args: dnsmasq_args(config, interface),
+            child: None,
+            started: Instant::now(),
+            established: false,
+            restart_delay: MIN_RESTART_DELAY,
+            restart_at: None,
+            failed_start: None,
+            output: Vec::new(),
+            last_error: Arc::new(Mutex::new(None)),
+        };
+
+        dnsmasq.child = Some(dnsmasq.spawn()?);
+        Ok(dnsmasq)
+    }
+
+    fn spawn(&mut self) -> Result<Child> {
+        let mut child = Command::new("dnsmasq")
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| AppError::Dnsmasq(format!("spawning failed: {}", e)))?;
+
+        self.output.clear();
+        if let Some(stdout) = child.stdout.take() {
+            let last_error = Arc::clone(&self.last_error);
+            self.output.push(tokio::spawn(forward_output(stdout, false, last_error)));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let last_error = Arc::clone(&self.last_error);
+            self.output.push(tokio::spawn(forward_output(stderr, true, last_error)));
+        }
+
+        self.started = Instant::now();
+        Ok(child)
+    }
+
+    /// Wait out the startup period, failing if dnsmasq exits during it
+    pub async fn wait_started(&mut self) -> Result<()> {
+        match timeout_at(self.started + STARTUP_PERIOD, self.supervise()).await {
+            Ok(result) => result,
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Keep dnsmasq running, restarting it whenever it exits
+    ///
+    /// Only returns if dnsmasq exits during its first start. Safe to cancel,
+    /// so it can be polled alongside other events: everything awaited on is
+    /// kept in `self` and picked up by the next call.
+    pub async fn supervise(&mut self) -> Result<()> {
+        loop {
+            if let Some(status) = self.failed_start {
+                return Err(self.startup_failure(status).await);
+            }
+
+            if let Some(ref mut child) = self.child {
+                let status = child.wait().await?;
+                self.child = None;
+                self.exited(status);
+                continue;
+            }
+
+            if let Some(restart_at) = self.restart_at {
+                sleep_until(restart_at).await;
+            }
+
+            match self.spawn() {
+                Ok(child) => {
+                    info!("Restarted dnsmasq");
+                    self.child = Some(child);
+                    self.restart_at = None;
+                },
+                Err(e) => {
+                    warn!("{}", e);
+                    self.schedule_restart();
+                },
+            }
+        }
+    }
+
+    /// Handle an exit, scheduling a restart unless it was a failed start
+    fn exited(&mut self, status: ExitStatus) {
+        let uptime = self.started.elapsed();
+
+        if !self.established && uptime < STARTUP_PERIOD {
+            self.failed_start = Some(status);
+            return;
+        }
+
+        self.established = true;
+        if uptime >= STARTUP_PERIOD {
+            self.restart_delay = MIN_RESTART_DELAY;
+        }
+
+        warn!(
+            "dnsmasq exited ({}), restarting in {}s",
+            status,
+            self.restart_delay.as_secs()
+        );
+        self.schedule_restart();
+    }
+
+    /// Error explaining an exit during the first start
+    async fn startup_failure(&mut self, status: ExitStatus) -> AppError {
+        // Let the output tasks log what dnsmasq wrote before exiting. Each
+        // task stays in the list until awaited, in case this is cancelled.
+        while let Some(task) = self.output.first_mut() {
+            let _ = timeout(Duration::from_secs(1), task).await;
+            self.output.remove(0);
+        }
+
+        self.failed_start = None;
+        let reason = self.last_error.lock().unwrap_or_else(|e| e.into_inner()).take();
+        AppError::Dnsmasq(match reason {
+            Some(reason) => format!("exited during startup ({}): {}", status, reason),
+            None => format!("exited during startup ({})", status),
+        })
+    }
+
+    fn schedule_restart(&mut self) {
+        self.restart_at = Some(Instant::now() + self.restart_delay);
+        self.restart_delay = (self.restart_delay * 2).min(MAX_RESTART_DELAY);
+    }
+
+    /// Stop dnsmasq, killing it if it does not exit on SIGTERM
+    pub async fn stop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+
+        if let Some(pid) = child.id() {
+            let _ = kill(Pid::from_raw(pid as i32), Signal::SIGTERM);
+        }
+
+        if timeout(STOP_TIMEOUT, child.wait()).await.is_err() {
+            warn!("dnsmasq did not exit on SIGTERM, killing it");
+            let _ = child.kill().await;
+        }
+    }
+}
+
+/// Log each line of dnsmasq output, stderr as warnings
+async fn forward_output(
+    output: impl AsyncRead + Unpin,
+    is_stderr: bool,
+    last_error: Arc<Mutex<Option<String>>>,
+) {
+    let mut lines = BufReader::new(output).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if is_stderr {
+            warn!(target: "dnsmasq", "{}", line);
+            *last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(line);
+        } else {
+            info!(target: "dnsmasq", "{}", line);
+        }
+    }
+}
+
+/// Arguments running dnsmasq for DHCP and DNS on the portal interface,
+/// leaving out whatever the built-in servers handle
+fn dnsmasq_args(config: &Config, interface: &str) -> Vec<String> {
+    let mut args = vec![
+        format!("--interface={}", interface),
+        "--keep-in-foreground".to_string(),
+        "--bind-interfaces".to_string(),
+        "--except-interface=lo".to_string(),
+        "--conf-file".to_string(),
+        "--no-hosts".to_string(),
     ];
 
-    Command::new("dnsmasq")
-        .args(&args)
-        .spawn()
-        .map_err(|e| AppError::Dnsmasq(e.to_string()))
+    if config.dns_backend == DnsBackend::Dnsmasq {
+        args.push(format!("--address=/#/{}", config.gateway));
+        for name in &config.dns_allow {
+            args.extend(allowed_name_servers(name, &config.dns_upstream));
+        }
+    } else {
+        args.push("--port=0".to_string());
+    }
+
+    if config.dhcp_backend == DhcpBackend::Dnsmasq {
+        args.extend([
+            format!("--dhcp-range={}", config.dhcp_range),
+            format!("--dhcp-leasefile={}", dnsmasq_lease_file(config).display()),
+            format!("--dhcp-option=option:router,{}", config.gateway),
+        ]);
+        // Without its own DNS server dnsmasq does not send one, so point
+        // clients at the built-in server on the gateway
+        if config.dns_backend != DnsBackend::Dnsmasq {
+            args.push(format!("--dhcp-option=option:dns-server,{}", config.gateway));
+        }
+        // RFC 8910 captive portal API
+        if let Some(url) = captive_api_url(config) {
+            args.push(format!("--dhcp-option=114,\"{}\"", url));
+        }
+    }
+
+    args
 }
 
-/// Stop the dnsmasq process
-pub fn stop_dnsmasq(dnsmasq: &mut Child) -> Result<()> {
-    kill(Pid::from_raw(dnsmasq.id() as i32), Signal::SIGTERM)?;
-    dnsmasq.wait()?;
-    Ok(())
+/// Where dnsmasq records the leases it hands out
+pub fn dnsmasq_lease_file(config: &Config) -> PathBuf {
+    config.state_directory.join(DNSMASQ_LEASE_FILE)
+}
+
+/// Server options forwarding a name to the upstream resolvers, or to the
+/// resolvers in resolv.conf if none are given
+fn allowed_name_servers(name: &str, upstreams: &[SocketAddr]) -> Vec<String> {
+    let name = name.trim().trim_start_matches("*.").trim_end_matches('.');
+
+    if upstreams.is_empty() {
+        return vec![format!("--server=/{}/#", name)];
+    }
+
+    upstreams
+        .iter()
+        .map(|upstream| format!("--server=/{}/{}#{}", name, upstream.ip(), upstream.port()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Supervise a shell command in place of dnsmasq
+    fn supervised(script: &str) -> Dnsmasq {
+        let child = Command::new("sh")
+            .args(["-c", script])
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+
+        Dnsmasq {
+            args: Vec::new(),
+            child: Some(child),
+            started: Instant::now(),
+            established: false,
+            restart_delay: MIN_RESTART_DELAY,
+            restart_at: None,
+            failed_start: None,
+            output: Vec::new(),
+            last_error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    #[tokio::test]
+    async fn startup_failure_survives_cancellation() {
+        let mut dnsmasq = supervised("exit 3");
+
+        // Output that takes a while to drain after the exit
+        let writer = Arc::clone(&dnsmasq.last_error);
+        dnsmasq.output.push(tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            *writer.lock().unwrap() = Some("bad option".to_string());
+        }));
+
+        while dnsmasq.failed_start.is_none() {
+            let polled = timeout(Duration::from_millis(20), dnsmasq.supervise()).await;
+            assert!(polled.is_err(), "supervise returned before the exit was seen");
+        }
+
+        let error = dnsmasq.supervise().await.unwrap_err().to_string();
+        assert!(error.contains("exited during startup"), "{}", error);
+        assert!(error.contains("bad option"), "{}", error);
+        assert!(dnsmasq.failed_start.is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_started_reports_exit_during_startup() {
+        let mut dnsmasq = supervised("exit 3");
+
+        let error = dnsmasq.wait_started().await.unwrap_err().to_string();
+        assert!(error.contains("exited during startup"), "{}", error);
+        assert!(dnsmasq.started.elapsed() < STARTUP_PERIOD);
+    }
+
+    #[test]
+    fn args_cover_each_backend_combination() {
+        let args = |dhcp: &str, dns: &str| {
+            let config = Config::for_tests(&["--dhcp-backend", dhcp, "--dns-backend", dns]);
+            dnsmasq_args(&config, "wlan0")
+        };
+        let has = |args: &[String], prefix: &str| args.iter().any(|arg| arg.starts_with(prefix));
+
+        let both = args("dnsmasq", "dnsmasq");
+        assert!(has(&both, "--address=/#/192.168.42.1"));
+        assert!(!has(&both, "--port=0"));
+        assert!(has(&both, "--dhcp-range="));
+        assert!(!has(&both, "--dhcp-option=option:dns-server,"));
+
+        let dhcp_only = args("dnsmasq", "builtin");
+        assert!(has(&dhcp_only, "--port=0"));
+        assert!(!has(&dhcp_only, "--address="));
+        assert!(has(&dhcp_only, "--dhcp-range="));
+        assert!(has(&dhcp_only, "--dhcp-option=option:router,192.168.42.1"));
+        assert!(has(&dhcp_only, "--dhcp-option=option:dns-server,192.168.42.1"));
+
+        let dns_only = args("builtin", "dnsmasq");
+        assert!(has(&dns_only, "--address=/#/192.168.42.1"));
+        assert!(!has(&dns_only, "--dhcp-"));
+
+        let neither = args("builtin", "builtin");
+        assert!(has(&neither, "--port=0"));
+        assert!(!has(&neither, "--dhcp-"));
+    }
+
+    #[test]
+    fn captive_portal_option_needs_https() {
+        let has_option = |args: &[&str]| {
+            let config = Config::for_tests(args);
+            dnsmasq_args(&config, "wlan0")
+                .iter()
+                .any(|arg| arg.starts_with("--dhcp-option=114,"))
+        };
+
+        assert!(!has_option(&["--dhcp-backend", "dnsmasq"]));
+        assert!(!has_option(&["--dhcp-backend", "dnsmasq", "--tls-port", "443"]));
+        assert!(has_option(&[
+            "--dhcp-backend",
+            "dnsmasq",
+            "--tls-port",
+            "443",
+            "--tls-mode",
+            "serve",
+        ]));
+    }
 }
@@ -8,12 +8,24 @@ pub enum AppError {
     #[error("Nix error: {0}")]
     Nix(#[from] nix::Error),
 
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
     #[error("NetworkManager error: {0}")]
     NetworkManager(#[from] network_manager::errors::Error),
 
+    #[error("NetworkManager D-Bus call failed: {0}")]
+    DBus(#[from] zbus::Error),
+
+    #[error("Invalid connection profile setting {0}")]
+    ProfileSetting(String),
+
     #[error("Cannot find network device '{0}'")]
     DeviceNotFound(String),
 
+    #[error("Cannot find connection profile '{0}'")]
+    ConnectionNotFound(String),
+
     #[error("Device '{0}' is not a WiFi device")]
     NotAWiFiDevice(String),
 
@@ -32,7 +44,7 @@ pub enum AppError {
     #[error("Starting NetworkManager service failed: {0}")]
     StartNetworkManager(String),
 
-    #[error("Spawning dnsmasq failed: {0}")]
+    #[error("dnsmasq failed: {0}")]
     Dnsmasq(String),
 
     #[error("Blocking exit signals failed: {0}")]
@@ -49,6 +61,52 @@ pub enum AppError {
 
     #[error("Setting DHCP failed: {0}")]
     SetDhcp(String),
+
+    #[error("Setting static IP failed: {0}")]
+    SetStatic(String),
+
+    #[error("No deleted connection profiles to restore")]
+    NothingToRestore,
+
+    #[error("Cannot find backup '{0}'")]
+    BackupNotFound(String),
+
+    #[error("Invalid backup name '{0}'")]
+    InvalidBackupName(String),
+
+    #[error("Interface '{0}' is not managed by the portal")]
+    UnknownInterface(String),
+
+    #[error("Several ethernet interfaces are managed, name the interface to configure")]
+    InterfaceRequired,
+
+    #[error("Cannot load configuration file {0}: {1}")]
+    ConfigFile(String, String),
+
+    #[error("DHCP server error: {0}")]
+    Dhcp(String),
+
+    #[error("DNS server error: {0}")]
+    Dns(String),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[error("Reset credential error: {0}")]
+    Auth(String),
+
+    #[error("Logging error: {0}")]
+    Logging(String),
+
+    #[error("Webhook error: {0}")]
+    Webhook(String),
+
+    #[error("Invalid setting '{key}' in {path}: {message}")]
+    ConfigSetting {
+        path: String,
+        key: String,
+        message: String,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
@@ -69,6 +127,14 @@ pub fn exit_code(e: &AppError) -> i32 {
         AppError::RootPrivilegesRequired(_) => 23,
         AppError::NotAnEthernetDevice(_) => 24,
         AppError::SetDhcp(_) => 25,
+        AppError::SetStatic(_) => 26,
+        AppError::ConfigFile(..) | AppError::ConfigSetting { .. } => 27,
+        AppError::Auth(_) => 28,
+        AppError::Tls(_) => 29,
+        AppError::Dhcp(_) => 30,
+        AppError::Dns(_) => 31,
+        AppError::Logging(_) => 32,
+        AppError::Webhook(_) => 33,
         _ => 1,
     }
 }
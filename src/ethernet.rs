@@ -0,0 +1,677 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{debug, error, info, warn};
+
+use crate::backend::{DeviceState, Ip4Config, NetworkBackend, ProfileSettings};
+#[cfg(test)]
+use crate::backend::SettingValue;
+use crate::backup::save_backup;
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use crate::network::StaticConfig;
+
+/// Current state and configuration of an ethernet device
+#[derive(Debug, Serialize)]
+pub struct EthernetStatus {
+    pub interface: String,
+    pub state: DeviceState,
+    pub carrier: bool,
+    pub ipv4: Ip4Config,
+    /// Wired profiles that a reset would delete
+    pub profiles: Vec<ProfileSettings>,
+}
+
+/// Progress of the last DHCP reset
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ResetStatus {
+    /// No reset requested yet
+    Idle,
+    /// DHCP applied, waiting for the device to obtain a lease
+    WaitingForLease,
+    /// The device obtained a lease
+    LeaseObtained(DhcpLease),
+    /// No lease within the DHCP timeout
+    TimedOut,
+    /// Applying DHCP failed
+    Failed { error: String },
+    /// No lease within the rollback timeout, previous profiles restored
+    RolledBack,
+    /// Previous profiles restored on request
+    Restored,
+    /// Restoring previous profiles failed
+    RestoreFailed { error: String },
+}
+
+/// Lease obtained by an ethernet device
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DhcpLease {
+    pub addresses: Vec<String>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+    /// Lease time in seconds
+    pub lease_time: Option<u64>,
+}
+
+/// Ethernet device managed by the portal and the state of its last reset
+pub struct EthernetTarget {
+    pub interface: String,
+    reset_status: ResetStatus,
+    lease_deadline: Option<Instant>,
+    rollback_deadline: Option<Instant>,
+    /// Wired profiles deleted by the first reset or static configuration
+    /// since the last restore
+    snapshot: Vec<ProfileSettings>,
+}
+
+impl EthernetTarget {
+    pub fn new(interface: String) -> Self {
+        Self {
+            interface,
+            reset_status: ResetStatus::Idle,
+            lease_deadline: None,
+            rollback_deadline: None,
+            snapshot: Vec::new(),
+        }
+    }
+
+    pub fn reset_status(&self) -> &ResetStatus {
+        &self.reset_status
+    }
+
+    pub fn is_watching_lease(&self) -> bool {
+        self.lease_deadline.is_some() || self.rollback_deadline.is_some()
+    }
+
+    /// Reset the device to DHCP and start waiting for a lease
+    pub fn reset_to_dhcp(&mut self, backend: &mut dyn NetworkBackend, config: &Config) -> Result<()> {
+        info!("Resetting {} to DHCP", self.interface);
+
+        self.replace_wired_profiles(backend, config);
+
+        // Set DHCP on the ethernet device, putting the deleted profiles back
+        // if that fails
+        if let Err(e) = backend.set_dhcp(&self.interface) {
+            self.reset_status = match self.restore_after_failure(backend, config) {
+                Ok(()) => ResetStatus::Failed {
+                    error: e.to_string(),
+                },
+                Err(restore) => ResetStatus::RestoreFailed {
+                    error: format!("{}, then restoring failed: {}", e, restore),
+                },
+            };
+            return Err(e);
+        }
+
+        info!(
+            "DHCP applied on {}, waiting up to {}s for a lease",
+            self.interface, config.dhcp_timeout
+        );
+        let now = Instant::now();
+        self.reset_status = ResetStatus::WaitingForLease;
+        self.lease_deadline = Some(now + Duration::from_secs(config.dhcp_timeout));
+        self.rollback_deadline = (config.rollback_timeout > 0 && !self.snapshot.is_empty())
+            .then(|| now + Duration::from_secs(config.rollback_timeout));
+        Ok(())
+    }
+
+    /// Replace the device configuration with a static IPv4 address
+    pub fn configure_static(
+        &mut self,
+        backend: &mut dyn NetworkBackend,
+        config: &Config,
+        static_config: &StaticConfig,
+    ) -> Result<()> {
+        info!(
+            "Configuring {} with static address {}/{}",
+            self.interface, static_config.address, static_config.prefix
+        );
+
+        self.replace_wired_profiles(backend, config);
+
+        if let Err(e) = backend.set_static(&self.interface, static_config) {
+            let _ = self.restore_after_failure(backend, config);
+            return Err(e);
+        }
+
+        // A static address needs no lease, and rolling back would undo it
+        self.lease_deadline = None;
+        self.rollback_deadline = None;
+
+        info!("Static configuration of {} complete", self.interface);
+        Ok(())
+    }
+
+    /// Restore the profiles deleted by the last change on request
+    pub fn undo(&mut self, backend: &mut dyn NetworkBackend, config: &Config) -> Result<()> {
+        let result = self.restore_snapshot(backend, config);
+        match result {
+            Ok(()) => self.reset_status = ResetStatus::Restored,
+            Err(AppError::NothingToRestore) => {},
+            Err(ref e) => {
+                self.reset_status = ResetStatus::RestoreFailed {
+                    error: e.to_string(),
+                }
+            },
+        }
+        result
+    }
+
+    /// Record the lease once the device is activated with an address
+    pub fn check_lease(&mut self, backend: &mut dyn NetworkBackend, config: &Config) {
+        match self.read_lease(backend) {
+            Ok(Some(lease)) => {
+                info!(
+                    "DHCP lease obtained on {}: {} via {}",
+                    self.interface,
+                    lease.addresses.join(", "),
+                    lease
+                        .gateway
+                        .map_or_else(|| "no gateway".to_string(), |g| g.to_string())
+                );
+                self.reset_status = ResetStatus::LeaseObtained(lease);
+                self.lease_deadline = None;
+                self.rollback_deadline = None;
+                return;
+            },
+            Ok(None) => {},
+            Err(e) => debug!("Reading DHCP lease of {} failed: {}", self.interface, e),
+        }
+
+        let now = Instant::now();
+
+        if self.rollback_deadline.is_some_and(|deadline| now >= deadline) {
+            warn!(
+                "No DHCP lease on {} after {}s, rolling back",
+                self.interface, config.rollback_timeout
+            );
+            self.lease_deadline = None;
+            self.rollback_deadline = None;
+            self.reset_status = match self.restore_snapshot(backend, config) {
+                Ok(()) => ResetStatus::RolledBack,
+                Err(e) => {
+                    error!("Rollback of {} failed: {}", self.interface, e);
+                    ResetStatus::RestoreFailed {
+                        error: e.to_string(),
+                    }
+                },
+            };
+            return;
+        }
+
+        if self.lease_deadline.is_some_and(|deadline| now >= deadline) {
+            warn!(
+                "No DHCP lease on {} after {}s",
+                self.interface, config.dhcp_timeout
+            );
+            self.reset_status = ResetStatus::TimedOut;
+            self.lease_deadline = None;
+        }
+    }
+
+    /// Read the lease if the device is activated with an IPv4 address
+    fn read_lease(&self, backend: &dyn NetworkBackend) -> Result<Option<DhcpLease>> {
+        let device = backend.get_device(&self.interface)?;
+        if device.state != DeviceState::Activated {
+            return Ok(None);
+        }
+
+        let ip4 = backend.get_ip4_config(&self.interface)?;
+        if ip4.addresses.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(DhcpLease {
+            addresses: ip4.addresses,
+            gateway: ip4.gateway,
+            dns: ip4.dns,
+            lease_time: backend.get_dhcp_lease_time(&self.interface)?,
+        }))
+    }
+
+    /// Describe the device and the profiles a reset would delete
+    pub fn status(&self, backend: &dyn NetworkBackend, config: &Config) -> Result<EthernetStatus> {
+        let device = backend.get_device(&self.interface)?;
+
+        let (profiles, _) = self.partition_wired_profiles(backend, config)?;
+
+        Ok(EthernetStatus {
+            interface: device.interface,
+            state: device.state,
+            carrier: backend.get_carrier(&self.interface)?,
+            ipv4: backend.get_ip4_config(&self.interface)?,
+            profiles,
+        })
+    }
+
+    /// Wired profiles a reset applies to, and the wired profiles it leaves alone
+    fn partition_wired_profiles(
+        &self,
+        backend: &dyn NetworkBackend,
+        config: &Config,
+    ) -> Result<(Vec<ProfileSettings>, Vec<ProfileSettings>)> {
+        let hw_address = backend.get_hw_address(&self.interface)?;
+
+        let mut targeted = Vec::new();
+        let mut kept = Vec::new();
+
+        for conn in backend.get_connections()? {
+            if conn.kind != "802-3-ethernet" {
+                continue;
+            }
+
+            let profile = match backend.get_profile(&conn.uuid) {
+                Ok(profile) => profile,
+                Err(e) => {
+                    warn!("Cannot read wired connection '{}': {}", conn.id, e);
+                    continue;
+                },
+            };
+
+            let active_on = backend.get_active_device(&conn.uuid).unwrap_or_else(|e| {
+                debug!("Cannot read the device of '{}': {}", conn.id, e);
+                None
+            });
+
+            if config.reset_all_wired
+                || applies_to(
+                    &profile,
+                    &self.interface,
+                    hw_address.as_deref(),
+                    active_on.as_deref(),
+                )
+            {
+                targeted.push(profile);
+            } else {
+                kept.push(profile);
+            }
+        }
+
+        Ok((targeted, kept))
+    }
+
+    /// Delete the wired profiles that apply to the device
+    ///
+    /// Returns the settings of the deleted profiles. When `backup` is set each
+    /// profile is written to the backup archive before it is deleted.
+    fn remove_wired_profiles(
+        &self,
+        backend: &mut dyn NetworkBackend,
+        config: &Config,
+        backup: bool,
+    ) -> Vec<ProfileSettings> {
+        let (targeted, kept) = match self.partition_wired_profiles(backend, config) {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                warn!("Cannot list wired connections: {}", e);
+                return Vec::new();
+            },
+        };
+
+        for profile in &kept {
+            info!(
+                "Keeping wired connection '{}' ({})",
+                profile.id,
+                binding(profile)
+            );
+        }
+
+        for profile in &targeted {
+            if backup {
+                self.save_backup(config, profile);
+            }
+
+            info!(
+                "Removing wired connection '{}' ({})",
+                profile.id,
+                binding(profile)
+            );
+            if let Err(e) = backend.delete_connection(&profile.uuid) {
+                warn!("Deleting wired connection '{}' failed: {}", profile.id, e);
+            }
+        }
+
+        targeted
+    }
+
+    /// Delete the wired profiles that apply to the device before a change
+    ///
+    /// The profiles deleted by an earlier change are kept in the snapshot, as
+    /// the ones deleted now are the portal's own, so that undo returns to the
+    /// configuration the device had before the portal changed it.
+    fn replace_wired_profiles(&mut self, backend: &mut dyn NetworkBackend, config: &Config) {
+        let removed = self.remove_wired_profiles(backend, config, true);
+        if self.snapshot.is_empty() {
+            self.snapshot = removed;
+        }
+    }
+
+    /// Write a profile to the on-disk backup archive
+    fn save_backup(&self, config: &Config, profile: &ProfileSettings) {
+        match save_backup(&config.state_directory, &self.interface, profile) {
+            Ok(path) => info!("Saved '{}' to {}", profile.id, path.display()),
+            Err(e) => warn!("Cannot back up wired connection '{}': {}", profile.id, e),
+        }
+    }
+
+    /// Recreate the wired profiles deleted by a change that could not be
+    /// applied
+    fn restore_after_failure(
+        &mut self,
+        backend: &mut dyn NetworkBackend,
+        config: &Config,
+    ) -> Result<()> {
+        match self.restore_snapshot(backend, config) {
+            Ok(()) | Err(AppError::NothingToRestore) => Ok(()),
+            Err(e) => {
+                error!("Restoring wired connections on {} failed: {}", self.interface, e);
+                Err(e)
+            },
+        }
+    }
+
+    /// Recreate and activate the wired profiles deleted by the last change
+    fn restore_snapshot(&mut self, backend: &mut dyn NetworkBackend, config: &Config) -> Result<()> {
+        if self.snapshot.is_empty() {
+            return Err(AppError::NothingToRestore);
+        }
+
+        info!(
+            "Restoring {} wired connection(s) on {}",
+            self.snapshot.len(),
+            self.interface
+        );
+        self.lease_deadline = None;
+        self.rollback_deadline = None;
+
+        self.remove_wired_profiles(backend, config, false);
+
+        for profile in &self.snapshot {
+            debug!("Recreating wired connection '{}'", profile.id);
+            backend.add_profile(profile)?;
+        }
+
+        // Activate the first profile that comes up
+        let activated = self.snapshot.iter().find(|profile| {
+            match backend.activate_connection(&profile.uuid) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Activating wired connection '{}' failed: {}", profile.id, e);
+                    false
+                },
+            }
+        });
+
+        if let Some(profile) = activated {
+            info!("Restored wired connection '{}'", profile.id);
+        }
+
+        self.snapshot.clear();
+        Ok(())
+    }
+}
+
+/// Whether a wired profile would activate on the given device
+///
+/// Profiles bound to another interface name or MAC address, or active on
+/// another device, are left alone; other unbound profiles can activate on
+/// any wired device.
+fn applies_to(
+    profile: &ProfileSettings,
+    interface: &str,
+    hw_address: Option<&str>,
+    active_on: Option<&str>,
+) -> bool {
+    if profile.interface_name().is_some_and(|name| name != interface) {
+        return false;
+    }
+
+    if active_on.is_some_and(|device| device != interface) {
+        return false;
+    }
+
+    match profile.mac_address() {
+        Some(mac) => hw_address.is_some_and(|hw| hw.eq_ignore_ascii_case(&mac)),
+        None => true,
+    }
+}
+
+/// Describe what a wired profile is bound to, for logging
+fn binding(profile: &ProfileSettings) -> String {
+    match (profile.interface_name(), profile.mac_address()) {
+        (Some(name), Some(mac)) => format!("bound to {} and {}", name, mac),
+        (Some(name), None) => format!("bound to {}", name),
+        (None, Some(mac)) => format!("bound to {}", mac),
+        (None, None) => "unbound".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeBackend;
+
+    fn backend(ethernet_interfaces: &[&str]) -> FakeBackend {
+        let interfaces: Vec<String> = ethernet_interfaces.iter().map(|i| i.to_string()).collect();
+        FakeBackend::with_defaults("wlan0", &interfaces)
+    }
+
+    fn static_config() -> StaticConfig {
+        StaticConfig {
+            interface: None,
+            address: Ipv4Addr::new(192, 168, 5, 20),
+            prefix: 24,
+            gateway: Some(Ipv4Addr::new(192, 168, 5, 1)),
+            dns: Vec::new(),
+        }
+    }
+
+    fn connection_ids(backend: &dyn NetworkBackend) -> Vec<String> {
+        let connections = backend.get_connections().unwrap();
+        connections.into_iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn reset_replaces_wired_profiles_with_dhcp() {
+        let mut backend = backend(&["eth0", "eth1"]);
+        let config = Config::for_tests(&["-e", "eth0,eth1"]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+
+        assert_eq!(*target.reset_status(), ResetStatus::WaitingForLease);
+        assert!(target.is_watching_lease());
+        assert_eq!(connection_ids(&backend), ["Wired static eth1", "Wired connection 1"]);
+        assert_eq!(target.snapshot.len(), 1);
+        assert_eq!(target.snapshot[0].id, "Wired static eth0");
+    }
+
+    #[test]
+    fn reset_keeps_unbound_profiles_active_elsewhere() {
+        let mut backend = backend(&["eth0", "eth1"])
+            .with_unbound_profile("Wired unbound eth1", Some("eth1"))
+            .with_unbound_profile("Wired unbound", None);
+        let config = Config::for_tests(&["-e", "eth0,eth1"]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        let status = target.status(&backend, &config).unwrap();
+        let profiles: Vec<_> = status.profiles.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(profiles, ["Wired static eth0", "Wired unbound"]);
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        assert_eq!(
+            connection_ids(&backend),
+            ["Wired static eth1", "Wired unbound eth1", "Wired connection 1"]
+        );
+    }
+
+    #[test]
+    fn applies_to_matches_binding_and_active_device() {
+        let mut profile = ProfileSettings {
+            id: "Wired".into(),
+            uuid: "uuid".into(),
+            method: "auto".into(),
+            settings: Default::default(),
+        };
+        let mac = Some("02:00:00:00:00:0A");
+
+        assert!(applies_to(&profile, "eth0", mac, None));
+        assert!(applies_to(&profile, "eth0", mac, Some("eth0")));
+        assert!(!applies_to(&profile, "eth0", mac, Some("eth1")));
+
+        let bind = |profile: &mut ProfileSettings, octet: u8| {
+            let mac_address = SettingValue {
+                signature: "ay".into(),
+                value: serde_json::json!([2, 0, 0, 0, 0, octet]),
+            };
+            let ethernet = profile.settings.entry("802-3-ethernet".into()).or_default();
+            ethernet.insert("mac-address".into(), mac_address);
+        };
+        bind(&mut profile, 10);
+        assert!(applies_to(&profile, "eth0", mac, None));
+        bind(&mut profile, 11);
+        assert!(!applies_to(&profile, "eth0", mac, None));
+
+        profile.settings.clear();
+        let connection = profile.settings.entry("connection".into()).or_default();
+        connection.insert("interface-name".into(), SettingValue::string("eth1"));
+        assert!(!applies_to(&profile, "eth0", mac, None));
+    }
+
+    #[test]
+    fn reset_restores_profiles_when_dhcp_fails() {
+        let mut backend = backend(&["eth0"]).with_failing_set_dhcp();
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        let result = target.reset_to_dhcp(&mut backend, &config);
+
+        assert!(matches!(result, Err(AppError::SetDhcp(_))));
+        assert!(matches!(target.reset_status(), ResetStatus::Failed { .. }));
+        assert!(!target.is_watching_lease());
+        assert!(target.snapshot.is_empty());
+        assert_eq!(connection_ids(&backend), ["Wired static eth0"]);
+        let ip4 = backend.get_ip4_config("eth0").unwrap();
+        assert_eq!(ip4.addresses, ["192.168.1.50/24"]);
+    }
+
+    #[test]
+    fn check_lease_records_lease() {
+        let mut backend = backend(&["eth0"]);
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        target.check_lease(&mut backend, &config);
+
+        assert_eq!(
+            *target.reset_status(),
+            ResetStatus::LeaseObtained(DhcpLease {
+                addresses: vec!["10.0.0.50/24".into()],
+                gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+                dns: vec![Ipv4Addr::new(10, 0, 0, 1)],
+                lease_time: Some(3600),
+            })
+        );
+        assert!(!target.is_watching_lease());
+    }
+
+    #[test]
+    fn check_lease_waits_until_deadline() {
+        let mut backend = backend(&["eth0"]).without_carrier("eth0");
+        let config = Config::for_tests(&["--rollback-timeout", "0"]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        target.check_lease(&mut backend, &config);
+        assert_eq!(*target.reset_status(), ResetStatus::WaitingForLease);
+
+        target.lease_deadline = Some(Instant::now());
+        target.check_lease(&mut backend, &config);
+        assert_eq!(*target.reset_status(), ResetStatus::TimedOut);
+        assert!(!target.is_watching_lease());
+    }
+
+    #[test]
+    fn check_lease_rolls_back_without_lease() {
+        let mut backend = backend(&["eth0"]).without_carrier("eth0");
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        target.rollback_deadline = Some(Instant::now());
+        target.check_lease(&mut backend, &config);
+
+        assert_eq!(*target.reset_status(), ResetStatus::RolledBack);
+        assert!(!target.is_watching_lease());
+        assert_eq!(connection_ids(&backend), ["Wired static eth0"]);
+        assert!(target.snapshot.is_empty());
+    }
+
+    #[test]
+    fn undo_restores_deleted_profiles() {
+        let mut backend = backend(&["eth0"]);
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        target.check_lease(&mut backend, &config);
+        target.undo(&mut backend, &config).unwrap();
+
+        assert_eq!(*target.reset_status(), ResetStatus::Restored);
+        assert_eq!(connection_ids(&backend), ["Wired static eth0"]);
+        let ip4 = backend.get_ip4_config("eth0").unwrap();
+        assert_eq!(ip4.addresses, ["192.168.1.50/24"]);
+    }
+
+    #[test]
+    fn configure_static_stops_waiting_for_lease() {
+        let mut backend = backend(&["eth0"]).without_carrier("eth0");
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        assert!(target.is_watching_lease());
+
+        target
+            .configure_static(&mut backend, &config, &static_config())
+            .unwrap();
+        assert!(!target.is_watching_lease());
+    }
+
+    #[test]
+    fn undo_after_several_changes_restores_original_profiles() {
+        let mut backend = backend(&["eth0"]);
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+        target
+            .configure_static(&mut backend, &config, &static_config())
+            .unwrap();
+        target.reset_to_dhcp(&mut backend, &config).unwrap();
+
+        assert_eq!(target.snapshot.len(), 1);
+        assert_eq!(target.snapshot[0].id, "Wired static eth0");
+
+        target.undo(&mut backend, &config).unwrap();
+        assert_eq!(connection_ids(&backend), ["Wired static eth0"]);
+        let ip4 = backend.get_ip4_config("eth0").unwrap();
+        assert_eq!(ip4.addresses, ["192.168.1.50/24"]);
+    }
+
+    #[test]
+    fn undo_without_change_restores_nothing() {
+        let mut backend = backend(&["eth0"]);
+        let config = Config::for_tests(&[]);
+        let mut target = EthernetTarget::new("eth0".into());
+
+        let result = target.undo(&mut backend, &config);
+
+        assert!(matches!(result, Err(AppError::NothingToRestore)));
+        assert_eq!(*target.reset_status(), ResetStatus::Idle);
+        assert_eq!(connection_ids(&backend), ["Wired static eth0"]);
+    }
+}
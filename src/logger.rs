@@ -1,15 +1,141 @@
-use tracing_subscriber::{fmt, EnvFilter};
+mod syslog;
+
+use std::path::Path;
+
+use clap::ValueEnum;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::fmt::format::{debug_fn, FormatFields};
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+
+pub use syslog::{parse_syslog_target, SyslogTarget};
+
+use syslog::{Rfc5424, SyslogWriter};
+
+/// Format of the log lines on standard output and in the log file
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable text
+    Text,
+    /// One JSON object per line with a timestamp and the request spans
+    Json,
+}
+
+/// How often the log file is rotated
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
 
 /// Initialize the tracing subscriber for logging
-pub fn init() {
+///
+/// Without a configuration, which could not be read, text is logged to
+/// standard output so the error can be reported.
+pub fn init(config: Option<&Config>) -> Result<()> {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-        // Default: show info for our crate, warn for others
-        EnvFilter::new("ember_network_connect=info,tower_http=warn,warn")
+        // Default: show info for our crate, HTTP requests and dnsmasq, warn for others
+        EnvFilter::new("ember_network_connect=info,http=info,dnsmasq=info,tower_http=warn,warn")
     });
 
-    fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .without_time()
-        .init();
+    let (layers, result) = match config.map(layers).transpose() {
+        Ok(Some(layers)) => (layers, Ok(())),
+        Ok(None) => (vec![stdout_layer(LogFormat::Text)], Ok(())),
+        Err(e) => (vec![stdout_layer(LogFormat::Text)], Err(e)),
+    };
+
+    tracing_subscriber::registry().with(layers).with(filter).init();
+    result
+}
+
+fn layers(config: &Config) -> Result<Vec<BoxedLayer>> {
+    let mut layers = vec![stdout_layer(config.log_format)];
+
+    if let Some(ref path) = config.log_file {
+        layers.push(file_layer(config, path)?);
+    }
+
+    if let Some(ref target) = config.syslog {
+        layers.push(
+            fmt::layer()
+                .event_format(Rfc5424::new())
+                .fmt_fields(plain_fields())
+                .with_ansi(false)
+                .with_writer(SyslogWriter::new(target.clone()))
+                .boxed(),
+        );
+    }
+
+    Ok(layers)
+}
+
+fn stdout_layer(format: LogFormat) -> BoxedLayer {
+    match format {
+        LogFormat::Text => fmt::layer().with_target(false).without_time().boxed(),
+        LogFormat::Json => fmt::layer().json().with_span_list(true).boxed(),
+    }
+}
+
+/// Log file rotated into `<name>.<date>` files, of which the newest are kept
+fn file_layer(config: &Config, path: &Path) -> Result<BoxedLayer> {
+    let Some(name) = path.file_name() else {
+        return Err(AppError::Logging(format!("invalid log file {}", path.display())));
+    };
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let rotation = match config.log_rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name.to_string_lossy());
+    if config.log_max_files > 0 {
+        builder = builder.max_log_files(config.log_max_files);
+    }
+
+    std::fs::create_dir_all(directory)
+        .map_err(|e| AppError::Logging(format!("creating {}: {}", directory.display(), e)))?;
+    let appender = builder
+        .build(directory)
+        .map_err(|e| AppError::Logging(format!("opening {}: {}", path.display(), e)))?;
+
+    let layer = match config.log_format {
+        LogFormat::Text => fmt::layer()
+            .fmt_fields(plain_fields())
+            .with_ansi(false)
+            .with_writer(appender)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_span_list(true)
+            .with_writer(appender)
+            .boxed(),
+    };
+    Ok(layer)
+}
+
+/// Fields without colors
+///
+/// Span fields are formatted once per field formatter type, so the outputs
+/// without colors need another type than the colored standard output.
+fn plain_fields() -> impl for<'writer> FormatFields<'writer> + Send + Sync + 'static {
+    debug_fn(|writer, field, value| match field.name() {
+        "message" => write!(writer, "{:?}", value),
+        name => write!(writer, "{}={:?}", name, value),
+    })
+    .delimited(" ")
 }
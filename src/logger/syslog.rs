@@ -0,0 +1,388 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Syslog port for both transports
+const DEFAULT_PORT: u16 = 514;
+
+/// Facility of the messages (daemon)
+const FACILITY: u8 = 3;
+
+const APP_NAME: &str = "ember-network-connect";
+
+/// How long connecting and sending over TCP may block logging
+const TCP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long messages are dropped after the collector could not be reached
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Transport to the syslog collector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogProtocol {
+    /// One message per datagram (RFC 5426)
+    Udp,
+    /// Octet-counted messages on a stream (RFC 6587)
+    Tcp,
+}
+
+/// Remote syslog collector
+#[derive(Debug, Clone)]
+pub struct SyslogTarget {
+    pub protocol: SyslogProtocol,
+    /// Host and port, resolved when connecting
+    pub address: String,
+}
+
+impl fmt::Display for SyslogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.protocol {
+            SyslogProtocol::Udp => "udp",
+            SyslogProtocol::Tcp => "tcp",
+        };
+        write!(f, "{}://{}", scheme, self.address)
+    }
+}
+
+/// Parse a collector given as `[udp://|tcp://]host[:port]`
+pub fn parse_syslog_target(value: &str) -> std::result::Result<SyslogTarget, String> {
+    let (protocol, address) = if let Some(address) = value.strip_prefix("udp://") {
+        (SyslogProtocol::Udp, address)
+    } else if let Some(address) = value.strip_prefix("tcp://") {
+        (SyslogProtocol::Tcp, address)
+    } else if value.contains("://") {
+        return Err(format!("'{}' is not a udp:// or tcp:// address", value));
+    } else {
+        (SyslogProtocol::Udp, value)
+    };
+
+    let address = if address.parse::<SocketAddr>().is_ok() {
+        address.to_string()
+    } else if let Ok(ip) = address.parse::<IpAddr>() {
+        SocketAddr::new(ip, DEFAULT_PORT).to_string()
+    } else {
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                address.to_string()
+            },
+            Some(_) => return Err(format!("'{}' is not a host with an optional port", value)),
+            None if !address.is_empty() => format!("{}:{}", address, DEFAULT_PORT),
+            None => return Err(format!("'{}' has no host", value)),
+        }
+    };
+
+    Ok(SyslogTarget { protocol, address })
+}
+
+/// Event formatter producing RFC 5424 messages
+pub struct Rfc5424 {
+    hostname: String,
+    pid: u32,
+}
+
+impl Rfc5424 {
+    pub fn new() -> Self {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|name| name.trim().to_string())
+            .ok()
+            .filter(|name| !name.is_empty() && !name.contains(' '))
+            .unwrap_or_else(|| "-".to_string());
+
+        Self {
+            hostname,
+            pid: std::process::id(),
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for Rfc5424
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let severity = match *event.metadata().level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+
+        // No message ID or structured data
+        write!(
+            writer,
+            "<{}>1 {} {} {} {} - - ",
+            FACILITY * 8 + severity,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.hostname,
+            APP_NAME,
+            self.pid
+        )?;
+
+        // Spans the way the text format shows them
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                write!(writer, "{}", span.name())?;
+                if let Some(fields) = span.extensions().get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, "{{{}}}", fields)?;
+                    }
+                }
+                write!(writer, ": ")?;
+            }
+        }
+
+        ctx.field_format().format_fields(writer.by_ref(), event)
+    }
+}
+
+enum Socket {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+#[derive(Default)]
+struct Connection {
+    socket: Option<Socket>,
+    /// Messages are dropped until then after a failure
+    retry_at: Option<Instant>,
+}
+
+/// Sends each log event to the collector
+///
+/// Messages are dropped while the collector cannot be reached, since
+/// logging must never hold up the portal.
+pub struct SyslogWriter {
+    target: SyslogTarget,
+    connection: Mutex<Connection>,
+}
+
+impl SyslogWriter {
+    pub fn new(target: SyslogTarget) -> Self {
+        Self {
+            target,
+            connection: Mutex::new(Connection::default()),
+        }
+    }
+
+    fn send(&self, message: &[u8]) {
+        let mut connection = self.connection();
+
+        if connection.retry_at.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+
+        let result = match connection.socket {
+            Some(ref mut socket) => send_message(socket, message),
+            None => connect(&self.target).and_then(|mut socket| {
+                send_message(&mut socket, message)?;
+                connection.socket = Some(socket);
+                Ok(())
+            }),
+        };
+
+        match result {
+            Ok(()) => connection.retry_at = None,
+            Err(_) => {
+                connection.socket = None;
+                connection.retry_at = Some(Instant::now() + RETRY_DELAY);
+            },
+        }
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogWriter {
+    type Writer = Message<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Message {
+            writer: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// One log event, sent when dropped
+pub struct Message<'a> {
+    writer: &'a SyslogWriter,
+    buf: Vec<u8>,
+}
+
+impl Write for Message<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.writer.send(&self.buf);
+        }
+    }
+}
+
+fn connect(target: &SyslogTarget) -> io::Result<Socket> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address");
+
+    for address in target.address.to_socket_addrs()? {
+        let socket = match target.protocol {
+            SyslogProtocol::Udp => {
+                let local: SocketAddr = match address {
+                    SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                    SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+                };
+                UdpSocket::bind(local)
+                    .and_then(|socket| socket.connect(address).map(|()| Socket::Udp(socket)))
+            },
+            SyslogProtocol::Tcp => TcpStream::connect_timeout(&address, TCP_TIMEOUT)
+                .and_then(|stream| stream.set_write_timeout(Some(TCP_TIMEOUT)).map(|()| stream))
+                .map(Socket::Tcp),
+        };
+
+        match socket {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn send_message(socket: &mut Socket, message: &[u8]) -> io::Result<()> {
+    match socket {
+        Socket::Udp(socket) => socket.send(message).map(|_| ()),
+        Socket::Tcp(stream) => {
+            let mut frame = format!("{} ", message.len()).into_bytes();
+            frame.extend_from_slice(message);
+            stream.write_all(&frame)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Log one event inside a span through a syslog layer sending to `target`
+    fn log_event(target: SyslogTarget) {
+        let layer = tracing_subscriber::fmt::layer()
+            .event_format(Rfc5424::new())
+            .fmt_fields(crate::logger::plain_fields())
+            .with_ansi(false)
+            .with_writer(SyslogWriter::new(target));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("reset", interface = "eth0");
+            let _entered = span.enter();
+            tracing::warn!(attempt = 2, "lease timed out");
+        });
+    }
+
+    fn assert_rfc5424(message: &str) {
+        let fields: Vec<&str> = message.splitn(8, ' ').collect();
+        // daemon facility (3) at warning severity (4)
+        assert_eq!(fields[0], "<28>1");
+        assert!(fields[1].ends_with('Z'), "timestamp {}", fields[1]);
+        assert!(!fields[2].is_empty());
+        assert_eq!(fields[3], APP_NAME);
+        assert_eq!(fields[4], std::process::id().to_string());
+        assert_eq!(fields[5..7], ["-", "-"]);
+        assert_eq!(
+            fields[7],
+            "reset{interface=\"eth0\"}: lease timed out attempt=2"
+        );
+    }
+
+    #[test]
+    fn targets_default_to_udp_and_the_syslog_port() {
+        let target = parse_syslog_target("logs.example.com").unwrap();
+        assert_eq!(target.protocol, SyslogProtocol::Udp);
+        assert_eq!(target.address, "logs.example.com:514");
+
+        let target = parse_syslog_target("tcp://10.0.0.1").unwrap();
+        assert_eq!(target.protocol, SyslogProtocol::Tcp);
+        assert_eq!(target.address, "10.0.0.1:514");
+
+        let target = parse_syslog_target("udp://[fd00::1]:1514").unwrap();
+        assert_eq!(target.address, "[fd00::1]:1514");
+        assert_eq!(
+            parse_syslog_target("fd00::1").unwrap().address,
+            "[fd00::1]:514"
+        );
+        assert_eq!(target.to_string(), "udp://[fd00::1]:1514");
+    }
+
+    #[test]
+    fn invalid_targets_are_rejected() {
+        for value in [
+            "",
+            "tcp://",
+            "http://logs",
+            "logs:syslog",
+            ":514",
+            "logs:70000",
+        ] {
+            assert!(parse_syslog_target(value).is_err(), "{value} accepted");
+        }
+    }
+
+    #[test]
+    fn udp_messages_are_sent_one_per_datagram() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+
+        log_event(parse_syslog_target(&format!("udp://{address}")).unwrap());
+
+        let mut buf = [0; 1024];
+        let len = collector.recv(&mut buf).unwrap();
+        assert_rfc5424(std::str::from_utf8(&buf[..len]).unwrap());
+    }
+
+    #[test]
+    fn tcp_messages_are_octet_counted() {
+        let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+
+        log_event(parse_syslog_target(&format!("tcp://{address}")).unwrap());
+
+        let (mut stream, _) = collector.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut frame = String::new();
+        stream.read_to_string(&mut frame).unwrap();
+
+        let (len, message) = frame.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), message.len());
+        assert_rfc5424(message);
+    }
+}
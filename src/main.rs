@@ -1,17 +1,35 @@
+mod audit;
+mod auth;
+mod backend;
+mod backup;
+mod captive;
+mod clients;
 mod config;
+mod dhcp;
+mod dns;
 mod dnsmasq;
 mod errors;
+mod ethernet;
 mod exit;
 mod logger;
+mod metrics;
+mod mqtt;
 mod network;
 mod privileges;
 mod server;
+mod state;
+mod tls;
+mod webhook;
 
 use std::process;
 
 use tracing::error;
 
-use config::get_config;
+use audit::run_audit_command;
+use auth::run_auth_command;
+use backend::create_backend;
+use backup::run_backup_command;
+use config::{get_config, Command};
 use errors::exit_code;
 use exit::block_exit_signals;
 use network::{init_networking, process_network_commands};
@@ -28,13 +46,30 @@ async fn main() {
 async fn run() -> errors::Result<()> {
     block_exit_signals()?;
 
-    logger::init();
-
+    // Errors reading the configuration are logged with the default options
     let config = get_config();
+    logger::init(config.as_ref().ok())?;
+    let config = config?;
+
+    // Read-only subcommands work without root
+    let requires_root = match config.command {
+        Some(ref command) => command.requires_root(),
+        None => true,
+    };
+    if requires_root {
+        require_root()?;
+    }
+
+    match config.command {
+        Some(Command::Backups(ref command)) => return run_backup_command(command, &config),
+        Some(Command::Auth(ref command)) => return run_auth_command(command, &config),
+        Some(Command::Audit(ref command)) => return run_audit_command(command, &config),
+        None => {},
+    }
 
-    require_root()?;
+    let mut backend = create_backend(&config);
 
-    init_networking(&config)?;
+    init_networking(backend.as_mut(), &config)?;
 
-    process_network_commands(&config).await
+    process_network_commands(backend, &config).await
 }
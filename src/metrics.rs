@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+
+use crate::state::PortalState;
+
+/// Content type of the Prometheus text format
+pub const METRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    route: String,
+    status: u16,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ProbeLabels {
+    probe: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct InterfaceLabels {
+    interface: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct TimerLabels {
+    timer: &'static str,
+}
+
+/// Prometheus metrics of the portal
+///
+/// Counters are updated as requests and network commands are handled,
+/// gauges are read from the portal state when the metrics are scraped.
+pub struct Metrics {
+    registry: Registry,
+    started: Instant,
+    uptime: Gauge,
+    http_requests: Family<RequestLabels, Counter>,
+    probe_redirects: Family<ProbeLabels, Counter>,
+    dhcp_clients: Gauge,
+    reset_attempts: Family<InterfaceLabels, Counter>,
+    reset_successes: Family<InterfaceLabels, Counter>,
+    reset_failures: Family<InterfaceLabels, Counter>,
+    reset_duration: Histogram,
+    timeout_remaining: Family<TimerLabels, Gauge>,
+    /// Start of the resets still waiting for an outcome, by interface
+    pending_resets: Mutex<HashMap<String, Instant>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("portal");
+
+        let uptime = Gauge::default();
+        registry.register("uptime_seconds", "Seconds since the portal started", uptime.clone());
+
+        let http_requests = Family::default();
+        registry.register(
+            "http_requests",
+            "HTTP requests by route and status",
+            http_requests.clone(),
+        );
+
+        let probe_redirects = Family::default();
+        registry.register(
+            "captive_probe_redirects",
+            "Connectivity checks and foreign hosts redirected to the portal",
+            probe_redirects.clone(),
+        );
+
+        let dhcp_clients = Gauge::default();
+        registry.register(
+            "dhcp_clients",
+            "Clients with a DHCP lease on the portal network",
+            dhcp_clients.clone(),
+        );
+
+        let reset_attempts = Family::default();
+        registry.register("reset_attempts", "DHCP resets requested", reset_attempts.clone());
+
+        let reset_successes = Family::default();
+        registry.register(
+            "reset_successes",
+            "DHCP resets that obtained a lease",
+            reset_successes.clone(),
+        );
+
+        let reset_failures = Family::default();
+        registry.register(
+            "reset_failures",
+            "DHCP resets that failed, timed out or were rolled back",
+            reset_failures.clone(),
+        );
+
+        let reset_duration = Histogram::new(exponential_buckets(1.0, 2.0, 10));
+        registry.register(
+            "reset_duration_seconds",
+            "Time from a DHCP reset to its outcome",
+            reset_duration.clone(),
+        );
+
+        let timeout_remaining = Family::default();
+        registry.register(
+            "timeout_remaining_seconds",
+            "Seconds left on the overall and activity timeouts",
+            timeout_remaining.clone(),
+        );
+
+        Self {
+            registry,
+            started: Instant::now(),
+            uptime,
+            http_requests,
+            probe_redirects,
+            dhcp_clients,
+            reset_attempts,
+            reset_successes,
+            reset_failures,
+            reset_duration,
+            timeout_remaining,
+            pending_resets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count an HTTP request, `route` being the matched route pattern
+    pub fn http_request(&self, route: &str, status: u16) {
+        let labels = RequestLabels {
+            route: route.to_string(),
+            status,
+        };
+        self.http_requests.get_or_create(&labels).inc();
+    }
+
+    /// Count a request redirected to the portal
+    pub fn probe_redirect(&self, probe: &str) {
+        let labels = ProbeLabels {
+            probe: probe.to_string(),
+        };
+        self.probe_redirects.get_or_create(&labels).inc();
+    }
+
+    /// Count a DHCP reset and start timing it
+    pub fn reset_started(&self, interface: &str) {
+        self.reset_attempts.get_or_create(&interface_labels(interface)).inc();
+        self.pending_resets().insert(interface.to_string(), Instant::now());
+    }
+
+    /// Record the outcome of a pending DHCP reset
+    ///
+    /// Later outcomes of the same reset, such as a lease obtained after the
+    /// DHCP timeout, are ignored.
+    pub fn reset_finished(&self, interface: &str, success: bool) {
+        let Some(started) = self.pending_resets().remove(interface) else {
+            return;
+        };
+
+        self.reset_duration.observe(started.elapsed().as_secs_f64());
+        let outcomes = if success {
+            &self.reset_successes
+        } else {
+            &self.reset_failures
+        };
+        outcomes.get_or_create(&interface_labels(interface)).inc();
+    }
+
+    /// Encode the metrics in the Prometheus text format
+    pub fn render(&self, portal: &PortalState, dhcp_clients: usize) -> String {
+        self.uptime.set(self.started.elapsed().as_secs() as i64);
+        self.dhcp_clients.set(dhcp_clients as i64);
+
+        // Timers that are disabled or stopped are left out
+        self.timeout_remaining.clear();
+        let timers = [
+            ("overall", portal.seconds_remaining),
+            ("activity", portal.activity_seconds_remaining),
+        ];
+        for (timer, remaining) in timers {
+            if let Some(remaining) = remaining {
+                self.timeout_remaining
+                    .get_or_create(&TimerLabels { timer })
+                    .set(remaining as i64);
+            }
+        }
+
+        let mut body = String::new();
+        // Writing to a String cannot fail
+        let _ = encode(&mut body, &self.registry);
+        body
+    }
+
+    fn pending_resets(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        self.pending_resets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn interface_labels(interface: &str) -> InterfaceLabels {
+    InterfaceLabels {
+        interface: interface.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn portal() -> PortalState {
+        PortalState::new(&Config::for_tests(&[]), vec![])
+    }
+
+    /// Value of a series in the rendered metrics
+    fn sample<'a>(body: &'a str, series: &str) -> Option<&'a str> {
+        body.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+    }
+
+    fn resets<'a>(body: &'a str, outcome: &str, interface: &str) -> Option<&'a str> {
+        sample(
+            body,
+            &format!("portal_reset_{outcome}_total{{interface=\"{interface}\"}}"),
+        )
+    }
+
+    fn timer<'a>(body: &'a str, timer: &str) -> Option<&'a str> {
+        sample(
+            body,
+            &format!("portal_timeout_remaining_seconds{{timer=\"{timer}\"}}"),
+        )
+    }
+
+    #[test]
+    fn reset_outcomes_are_counted_by_interface() {
+        let metrics = Metrics::new();
+        metrics.reset_started("eth0");
+        metrics.reset_started("eth1");
+        metrics.reset_finished("eth0", true);
+        metrics.reset_finished("eth1", false);
+
+        let body = metrics.render(&portal(), 0);
+        assert_eq!(resets(&body, "attempts", "eth0"), Some("1"));
+        assert_eq!(resets(&body, "attempts", "eth1"), Some("1"));
+        assert_eq!(resets(&body, "successes", "eth0"), Some("1"));
+        assert_eq!(resets(&body, "successes", "eth1"), None);
+        assert_eq!(resets(&body, "failures", "eth1"), Some("1"));
+        assert_eq!(
+            sample(&body, "portal_reset_duration_seconds_count"),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn only_the_first_outcome_of_a_reset_counts() {
+        let metrics = Metrics::new();
+        metrics.reset_finished("eth0", true);
+        metrics.reset_started("eth0");
+        metrics.reset_finished("eth0", false);
+        metrics.reset_finished("eth0", true);
+
+        let body = metrics.render(&portal(), 0);
+        assert_eq!(resets(&body, "failures", "eth0"), Some("1"));
+        assert_eq!(resets(&body, "successes", "eth0"), None);
+        assert_eq!(
+            sample(&body, "portal_reset_duration_seconds_count"),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn render_reports_running_timers_and_clients() {
+        let metrics = Metrics::new();
+        let mut state = portal();
+        state.seconds_remaining = Some(120);
+        state.activity_seconds_remaining = Some(30);
+
+        let body = metrics.render(&state, 3);
+        assert_eq!(sample(&body, "portal_dhcp_clients"), Some("3"));
+        assert_eq!(sample(&body, "portal_uptime_seconds"), Some("0"));
+        assert_eq!(timer(&body, "overall"), Some("120"));
+        assert_eq!(timer(&body, "activity"), Some("30"));
+        assert!(body.ends_with("# EOF\n"));
+
+        // A timer that stopped since the last scrape is no longer reported
+        state.activity_seconds_remaining = None;
+        let body = metrics.render(&state, 0);
+        assert_eq!(sample(&body, "portal_dhcp_clients"), Some("0"));
+        assert_eq!(timer(&body, "overall"), Some("120"));
+        assert_eq!(timer(&body, "activity"), None);
+    }
+}
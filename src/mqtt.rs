@@ -0,0 +1,571 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use rumqttc::{
+    AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, Publish, QoS,
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::ethernet::ResetStatus;
+use crate::network::NetworkCommand;
+use crate::state::{extend_overall, PortalState, StateSender};
+
+/// MQTT port without TLS
+const DEFAULT_PORT: u16 = 1883;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Delay before reconnecting after the connection to the broker failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How long the final state may take to reach the broker when exiting
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Requests queued for the broker while it cannot be reached
+const REQUEST_CAPACITY: usize = 32;
+
+/// Received commands waiting to run; further ones are dropped
+const COMMAND_CAPACITY: usize = 8;
+
+/// MQTT broker
+#[derive(Debug, Clone)]
+pub struct MqttBroker {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for MqttBroker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// Parse a broker given as `[mqtt://]host[:port]`
+pub fn parse_mqtt_broker(value: &str) -> std::result::Result<MqttBroker, String> {
+    let address = match value.strip_prefix("mqtt://") {
+        Some(address) => address,
+        None if value.contains("://") => {
+            return Err(format!("'{}' is not an mqtt:// address", value));
+        },
+        None => value,
+    };
+
+    // A bare IPv6 address has no port
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(MqttBroker {
+            host: ip.to_string(),
+            port: DEFAULT_PORT,
+        });
+    }
+
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() => match port.parse() {
+            Ok(port) => Ok(MqttBroker {
+                host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+                port,
+            }),
+            Err(_) => Err(format!("'{}' has an invalid port", value)),
+        },
+        None if !address.is_empty() => Ok(MqttBroker {
+            host: address.to_string(),
+            port: DEFAULT_PORT,
+        }),
+        _ => Err(format!("'{}' has no host", value)),
+    }
+}
+
+/// Command received on the command topic
+///
+/// Either a JSON object such as `{"command": "extend", "seconds": 600}` or
+/// just the command name.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum MqttCommand {
+    /// Add time to the overall timeout, `--overall-timeout` seconds by default
+    Extend { seconds: Option<u64> },
+    /// Exit the portal
+    Stop,
+    /// DHCP reset of the named interfaces, all if empty
+    Reset {
+        #[serde(default)]
+        interfaces: Vec<String>,
+    },
+}
+
+fn parse_command(payload: &[u8]) -> std::result::Result<MqttCommand, String> {
+    let text = std::str::from_utf8(payload)
+        .map_err(|_| "command is not UTF-8".to_string())?
+        .trim();
+
+    let command = if text.starts_with('{') {
+        serde_json::from_str(text)
+    } else {
+        serde_json::from_value(serde_json::json!({ "command": text }))
+    };
+    command.map_err(|e| format!("invalid command '{}': {}", text, e))
+}
+
+/// Topics under the configured prefix
+#[derive(Clone)]
+struct Topics {
+    /// Retained portal status
+    state: String,
+    /// Retained seconds until the overall timeout, empty if disabled
+    seconds_remaining: String,
+    command: String,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        Self {
+            state: format!("{}/state", prefix),
+            seconds_remaining: format!("{}/seconds_remaining", prefix),
+            command: format!("{}/command", prefix),
+        }
+    }
+}
+
+/// Status published on the state topic
+fn portal_status(state: &PortalState) -> &'static str {
+    if state.shutdown.is_some() {
+        return "exited";
+    }
+
+    let statuses = || state.reset.iter().map(|reset| &reset.status);
+    if statuses().any(|status| *status == ResetStatus::WaitingForLease) {
+        "resetting"
+    } else if statuses().any(|status| {
+        matches!(
+            status,
+            ResetStatus::Failed { .. }
+                | ResetStatus::TimedOut
+                | ResetStatus::RolledBack
+                | ResetStatus::RestoreFailed { .. }
+        )
+    }) {
+        "reset_failed"
+    } else if statuses().any(|status| matches!(status, ResetStatus::LeaseObtained(_))) {
+        "reset_ok"
+    } else if state.visited {
+        "user_connected"
+    } else {
+        "portal_up"
+    }
+}
+
+/// Client publishing the portal state to an MQTT broker and taking commands
+///
+/// The state is published as `idle` from the start and followed once the
+/// portal is up. A last will marks the portal as `exited` if the connection
+/// is lost without a goodbye.
+pub struct Mqtt {
+    client: AsyncClient,
+    topics: Topics,
+    /// Payloads received on the command topic until the portal is followed
+    commands: Option<mpsc::Receiver<Vec<u8>>>,
+    connection: JoinHandle<()>,
+    portal: Option<JoinHandle<()>>,
+}
+
+impl Mqtt {
+    /// Connect to the broker, if one is configured
+    pub fn start(config: &Config) -> Option<Self> {
+        let broker = config.mqtt_broker.as_ref()?;
+        let topics = Topics::new(&config.mqtt_topic_prefix);
+
+        let client_id = format!("ember-{}", &Uuid::new_v4().simple().to_string()[..16]);
+        let mut options = MqttOptions::new(client_id, &broker.host, broker.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(&topics.state, "exited", QoS::AtLeastOnce, true));
+        if let (Some(username), Some(password)) = (&config.mqtt_username, &config.mqtt_password) {
+            options.set_credentials(username, password);
+        }
+
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        if let Err(e) = client.try_publish(&topics.state, QoS::AtLeastOnce, true, "idle") {
+            error!("Publishing MQTT state failed: {}", e);
+        }
+
+        info!(
+            "Publishing portal state to MQTT broker {} under {}",
+            broker, config.mqtt_topic_prefix
+        );
+        let (commands_tx, commands) = mpsc::channel(COMMAND_CAPACITY);
+        let connection = tokio::spawn(run_connection(
+            eventloop,
+            client.clone(),
+            broker.clone(),
+            topics.command.clone(),
+            commands_tx,
+        ));
+
+        Some(Self {
+            client,
+            topics,
+            commands: Some(commands),
+            connection,
+            portal: None,
+        })
+    }
+
+    /// Publish the portal state and pass commands to the network handler
+    ///
+    /// Commands received before the portal was up are dropped rather than
+    /// run against a portal their sender never saw.
+    pub fn follow(
+        &mut self,
+        state: StateSender,
+        network_tx: mpsc::Sender<NetworkCommand>,
+        config: &Config,
+    ) {
+        let Some(mut commands) = self.commands.take() else {
+            return;
+        };
+
+        let mut dropped = 0;
+        while commands.try_recv().is_ok() {
+            dropped += 1;
+        }
+        if dropped > 0 {
+            warn!("Dropped {} MQTT commands received before the portal was up", dropped);
+        }
+
+        self.portal = Some(tokio::spawn(follow_portal(
+            self.client.clone(),
+            self.topics.clone(),
+            state,
+            network_tx,
+            commands,
+            config.overall_timeout,
+        )));
+    }
+
+    /// Publish `exited` and disconnect
+    pub async fn stop(self) {
+        if let Some(portal) = self.portal {
+            portal.abort();
+        }
+
+        let goodbye = async {
+            self.client
+                .publish(&self.topics.state, QoS::AtLeastOnce, true, "exited")
+                .await?;
+            self.client.disconnect().await
+        };
+        let sent = tokio::time::timeout(DISCONNECT_TIMEOUT, async {
+            goodbye.await.ok()?;
+            self.connection.await.ok()
+        })
+        .await;
+
+        if !matches!(sent, Ok(Some(()))) {
+            warn!("Could not tell the MQTT broker that the portal exited");
+        }
+    }
+}
+
+/// Keep the connection to the broker, subscribing to the command topic on
+/// every connect
+async fn run_connection(
+    mut eventloop: EventLoop,
+    client: AsyncClient,
+    broker: MqttBroker,
+    command_topic: String,
+    commands: mpsc::Sender<Vec<u8>>,
+) {
+    // Only the first failure after a connection is worth a warning
+    let mut connected = true;
+
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker {}", broker);
+                connected = true;
+                if let Err(e) = client.try_subscribe(&command_topic, QoS::AtLeastOnce) {
+                    error!("Subscribing to {} failed: {}", command_topic, e);
+                }
+            },
+            Ok(Event::Incoming(Packet::Publish(publish)))
+                if is_command(&publish, &command_topic) =>
+            {
+                if commands.try_send(publish.payload.to_vec()).is_err() {
+                    warn!("Dropping MQTT command, {} are already waiting", COMMAND_CAPACITY);
+                }
+            },
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+            Ok(_) => {},
+            Err(e) => {
+                if connected {
+                    warn!(
+                        "MQTT connection to {} failed: {}, retrying every {}s",
+                        broker,
+                        e,
+                        RECONNECT_DELAY.as_secs()
+                    );
+                } else {
+                    debug!("MQTT connection to {} failed: {}", broker, e);
+                }
+                connected = false;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            },
+        }
+    }
+}
+
+/// Whether a message is a command to run
+///
+/// Retained messages on the command topic are left over from earlier
+/// publishers and would replay on every connect.
+fn is_command(publish: &Publish, command_topic: &str) -> bool {
+    publish.topic == command_topic && !publish.retain
+}
+
+/// Publish the status and remaining time whenever they change, and run the
+/// received commands
+async fn follow_portal(
+    client: AsyncClient,
+    topics: Topics,
+    state: StateSender,
+    network_tx: mpsc::Sender<NetworkCommand>,
+    mut commands: mpsc::Receiver<Vec<u8>>,
+    default_extension: u64,
+) {
+    let mut portal = state.subscribe();
+    let mut published_status = None;
+    let mut published_remaining = None;
+
+    loop {
+        let (status, remaining) = {
+            let current = portal.borrow_and_update();
+            (portal_status(&current), current.seconds_remaining)
+        };
+
+        if published_status != Some(status) {
+            if let Err(e) = client.publish(&topics.state, QoS::AtLeastOnce, true, status).await {
+                error!("Publishing MQTT state failed: {}", e);
+            }
+            published_status = Some(status);
+        }
+
+        if published_remaining != Some(remaining) {
+            // An empty retained message clears the topic
+            let payload = remaining.map(|secs| secs.to_string()).unwrap_or_default();
+            let topic = &topics.seconds_remaining;
+            if let Err(e) = client.publish(topic, QoS::AtMostOnce, true, payload).await {
+                error!("Publishing MQTT remaining time failed: {}", e);
+            }
+            published_remaining = Some(remaining);
+        }
+
+        tokio::select! {
+            changed = portal.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            payload = commands.recv() => {
+                let Some(payload) = payload else {
+                    return;
+                };
+                run_command(&payload, &state, &network_tx, default_extension).await;
+            }
+        }
+    }
+}
+
+async fn run_command(
+    payload: &[u8],
+    state: &StateSender,
+    network_tx: &mpsc::Sender<NetworkCommand>,
+    default_extension: u64,
+) {
+    let command = match parse_command(payload) {
+        Ok(command) => command,
+        Err(e) => {
+            warn!("Ignoring MQTT command: {}", e);
+            return;
+        },
+    };
+    info!("Received MQTT command: {:?}", command);
+
+    match command {
+        MqttCommand::Extend { seconds } => {
+            match extend_overall(state, seconds.unwrap_or(default_extension)) {
+                Ok(extension) => info!(
+                    "Extended the overall timeout by {}s, exiting in {}s",
+                    extension.added, extension.seconds_remaining
+                ),
+                Err(e) => warn!("Refused to extend the overall timeout: {}", e),
+            }
+        },
+        MqttCommand::Stop => {
+            if let Err(e) = network_tx.send(NetworkCommand::Stop).await {
+                error!("Sending NetworkCommand::Stop failed: {}", e);
+            }
+        },
+        MqttCommand::Reset { interfaces } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let cmd = NetworkCommand::Reset {
+                interfaces,
+                reply: reply_tx,
+            };
+
+            if let Err(e) = network_tx.send(cmd).await {
+                error!("Sending NetworkCommand::Reset failed: {}", e);
+                return;
+            }
+
+            // Failures are logged by the network handler
+            if let Err(e) = reply_rx.await {
+                error!("Receiving reset result failed: {}", e);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::DhcpLease;
+    use crate::network::InterfaceResetStatus;
+    use crate::state::ShutdownReason;
+
+    fn portal(statuses: &[ResetStatus]) -> PortalState {
+        let reset = statuses
+            .iter()
+            .enumerate()
+            .map(|(index, status)| InterfaceResetStatus {
+                interface: format!("eth{}", index),
+                status: status.clone(),
+            })
+            .collect();
+        PortalState::new(&Config::for_tests(&[]), reset)
+    }
+
+    fn broker(value: &str) -> (String, u16) {
+        let broker = parse_mqtt_broker(value).unwrap();
+        (broker.host, broker.port)
+    }
+
+    #[test]
+    fn commands_are_json_or_a_bare_name() {
+        assert!(matches!(
+            parse_command(br#"{"command": "extend", "seconds": 600}"#),
+            Ok(MqttCommand::Extend { seconds: Some(600) })
+        ));
+        assert!(matches!(
+            parse_command(b" extend\n"),
+            Ok(MqttCommand::Extend { seconds: None })
+        ));
+        assert!(matches!(parse_command(b"stop"), Ok(MqttCommand::Stop)));
+
+        let Ok(MqttCommand::Reset { interfaces }) = parse_command(b"reset") else {
+            panic!("reset not parsed");
+        };
+        assert!(interfaces.is_empty());
+        let Ok(MqttCommand::Reset { interfaces }) =
+            parse_command(br#"{"command": "reset", "interfaces": ["eth1"]}"#)
+        else {
+            panic!("reset not parsed");
+        };
+        assert_eq!(interfaces, ["eth1"]);
+    }
+
+    #[test]
+    fn invalid_commands_are_rejected() {
+        for payload in [
+            &b"reboot"[..],
+            b"",
+            b"{\"command\": \"extend\", \"seconds\": -1}",
+            b"{\"seconds\": 60}",
+            b"{not json",
+            b"\xff\xfe",
+        ] {
+            assert!(parse_command(payload).is_err(), "{:?} accepted", payload);
+        }
+    }
+
+    #[test]
+    fn retained_commands_are_not_run() {
+        let topic = Topics::new("ember/portal/").command;
+        assert_eq!(topic, "ember/portal/command");
+
+        let mut publish = Publish::new(&topic, QoS::AtLeastOnce, "stop");
+        assert!(is_command(&publish, &topic));
+        assert!(!is_command(&publish, "ember/other/command"));
+
+        publish.retain = true;
+        assert!(!is_command(&publish, &topic));
+    }
+
+    #[test]
+    fn brokers_default_to_the_mqtt_port() {
+        assert_eq!(broker("broker.local"), ("broker.local".to_string(), 1883));
+        assert_eq!(
+            broker("mqtt://broker.local:8883"),
+            ("broker.local".to_string(), 8883)
+        );
+        assert_eq!(broker("10.0.0.2"), ("10.0.0.2".to_string(), 1883));
+        assert_eq!(broker("fd00::2"), ("fd00::2".to_string(), 1883));
+        assert_eq!(
+            broker("mqtt://[fd00::2]:1884"),
+            ("fd00::2".to_string(), 1884)
+        );
+
+        for value in [
+            "",
+            "mqtt://",
+            ":1883",
+            "broker.local:mqtt",
+            "mqtts://broker.local",
+        ] {
+            assert!(parse_mqtt_broker(value).is_err(), "{} accepted", value);
+        }
+    }
+
+    #[test]
+    fn status_follows_the_portal() {
+        let mut state = portal(&[]);
+        assert_eq!(portal_status(&state), "portal_up");
+
+        state.visited = true;
+        assert_eq!(portal_status(&state), "user_connected");
+
+        let lease = ResetStatus::LeaseObtained(DhcpLease {
+            addresses: vec!["10.0.0.20/24".to_string()],
+            gateway: None,
+            dns: Vec::new(),
+            lease_time: None,
+        });
+        assert_eq!(
+            portal_status(&portal(std::slice::from_ref(&lease))),
+            "reset_ok"
+        );
+        assert_eq!(
+            portal_status(&portal(&[lease.clone(), ResetStatus::TimedOut])),
+            "reset_failed"
+        );
+        assert_eq!(
+            portal_status(&portal(&[
+                ResetStatus::RolledBack,
+                ResetStatus::WaitingForLease
+            ])),
+            "resetting"
+        );
+        assert_eq!(
+            portal_status(&portal(&[ResetStatus::Restored])),
+            "portal_up"
+        );
+
+        let mut state = portal(&[ResetStatus::WaitingForLease]);
+        state.shutdown = Some(ShutdownReason::Stopped);
+        assert_eq!(portal_status(&state), "exited");
+    }
+}
@@ -1,17 +1,28 @@
 use std::net::Ipv4Addr;
-use std::process::Child;
 use std::sync::Arc;
 use std::time::Duration;
 
-use network_manager::{Connection, Device, DeviceState, DeviceType, NetworkManager, ServiceState};
-use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
 
+use crate::auth::Authenticator;
+use crate::backend::{ConnectionInfo, DeviceState, DeviceType, NetworkBackend};
+use crate::clients::{spawn_lease_watch, Clients};
 use crate::config::Config;
-use crate::dnsmasq::{start_dnsmasq, stop_dnsmasq};
+use crate::dhcp::{DhcpBackend, DhcpServer};
+use crate::dns::{DnsBackend, DnsServer};
+use crate::dnsmasq::{dnsmasq_lease_file, dnsmasq_required, Dnsmasq};
 use crate::errors::{AppError, Result};
+use crate::ethernet::{EthernetStatus, EthernetTarget, ResetStatus};
 use crate::exit::trap_exit_signals;
-use crate::server::{start_server, start_timer};
+use crate::metrics::Metrics;
+use crate::mqtt::Mqtt;
+use crate::server::start_server;
+use crate::state::{spawn_countdown, ActivityState, PortalState, ShutdownReason, StateSender};
+use crate::tls::{load_tls_listener, TlsListener};
+use crate::webhook::{WebhookEvent, Webhooks};
 
 /// Commands sent to the network handler
 #[derive(Debug)]
@@ -22,170 +33,555 @@ pub enum NetworkCommand {
     OverallTimeout,
     /// Exit signal received
     Exit,
-    /// User requested DHCP reset
-    Reset,
+    /// Stop requested over MQTT
+    Stop,
+    /// User requested DHCP reset of the named interfaces (all if empty)
+    Reset {
+        interfaces: Vec<String>,
+        reply: oneshot::Sender<InterfaceResults>,
+    },
+    /// User requested a static IPv4 configuration
+    ConfigureStatic {
+        static_config: StaticConfig,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// User requested the current status of the ethernet devices
+    Status(oneshot::Sender<Result<Vec<EthernetStatus>>>),
+    /// User requested the outcome of the last DHCP reset on each interface
+    ResetStatus(oneshot::Sender<Vec<InterfaceResetStatus>>),
+    /// User requested to restore the profiles deleted by the last change on
+    /// the named interfaces (all if empty)
+    Undo {
+        interfaces: Vec<String>,
+        reply: oneshot::Sender<InterfaceResults>,
+    },
     /// User accessed the portal (resets activity timeout)
     Activate,
 }
 
+/// Static IPv4 configuration for the ethernet device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticConfig {
+    /// Ethernet interface to configure, required when several are managed
+    #[serde(default)]
+    pub interface: Option<String>,
+    pub address: Ipv4Addr,
+    pub prefix: u8,
+    pub gateway: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub dns: Vec<Ipv4Addr>,
+}
+
+impl StaticConfig {
+    /// Check that the configuration describes a usable host address
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if !(1..=32).contains(&self.prefix) {
+            return Err(format!("Invalid prefix length {}", self.prefix));
+        }
+
+        if !is_unicast(self.address) {
+            return Err(format!("Invalid address {}", self.address));
+        }
+
+        let mask = u32::MAX << (32 - u32::from(self.prefix));
+        let network = u32::from(self.address) & mask;
+        let broadcast = network | !mask;
+
+        // /31 and /32 have no network or broadcast address
+        let is_host = |addr: Ipv4Addr| {
+            self.prefix >= 31 || (u32::from(addr) != network && u32::from(addr) != broadcast)
+        };
+
+        if !is_host(self.address) {
+            return Err(format!(
+                "Address {} is not a host address in /{}",
+                self.address, self.prefix
+            ));
+        }
+
+        if let Some(gateway) = self.gateway {
+            if !is_unicast(gateway) || gateway == self.address {
+                return Err(format!("Invalid gateway {}", gateway));
+            }
+            if u32::from(gateway) & mask != network || !is_host(gateway) {
+                return Err(format!(
+                    "Gateway {} is not in {}/{}",
+                    gateway,
+                    Ipv4Addr::from(network),
+                    self.prefix
+                ));
+            }
+        }
+
+        if let Some(dns) = self.dns.iter().find(|dns| !is_unicast(**dns)) {
+            return Err(format!("Invalid DNS server {}", dns));
+        }
+
+        Ok(())
+    }
+}
+
+fn is_unicast(addr: Ipv4Addr) -> bool {
+    !(addr.is_unspecified() || addr.is_loopback() || addr.is_multicast() || addr.is_broadcast())
+}
+
+/// How often the ethernet devices are checked while waiting for a lease
+const LEASE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Result of a request on each selected ethernet interface
+pub type InterfaceResults = Vec<(String, Result<()>)>;
+
+/// Progress of the last DHCP reset on one ethernet interface
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterfaceResetStatus {
+    pub interface: String,
+    pub status: ResetStatus,
+}
+
+impl InterfaceResetStatus {
+    fn of(target: &EthernetTarget) -> Self {
+        Self {
+            interface: target.interface.clone(),
+            status: target.reset_status().clone(),
+        }
+    }
+}
+
+/// Keep dnsmasq running if it is used, never completing otherwise
+async fn supervise_dnsmasq(dnsmasq: &mut Option<Dnsmasq>) -> Result<()> {
+    match dnsmasq {
+        Some(dnsmasq) => dnsmasq.supervise().await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Main network command handler
 struct NetworkHandler {
-    manager: NetworkManager,
-    eth_device: Device,
-    portal_connection: Option<Connection>,
+    backend: Box<dyn NetworkBackend>,
+    targets: Vec<EthernetTarget>,
+    wifi_interface: String,
+    portal_connection: Option<ConnectionInfo>,
     config: Arc<Config>,
-    dnsmasq: Child,
+    dnsmasq: Option<Dnsmasq>,
+    dhcp_server: Option<JoinHandle<()>>,
+    dns_server: Option<JoinHandle<()>>,
     rx: mpsc::Receiver<NetworkCommand>,
-    user_connected: bool,
+    state: StateSender,
+    metrics: Arc<Metrics>,
+    webhooks: Webhooks,
+    mqtt: Option<Mqtt>,
 }
 
 impl NetworkHandler {
-    /// Create handler and spawn background tasks
-    async fn new(config: Arc<Config>) -> Result<Self> {
-        let manager = NetworkManager::new();
-        debug!("NetworkManager initialized");
-
+    /// Create the handler for the configured devices
+    ///
+    /// Nothing is changed on the system until `start` is called.
+    fn new(
+        backend: Box<dyn NetworkBackend>,
+        config: Arc<Config>,
+        rx: mpsc::Receiver<NetworkCommand>,
+    ) -> Result<Self> {
         // Find WiFi device for the access point
-        let wifi_device = find_device(&manager, config.interface.as_deref())?;
-
-        // Find ethernet device to reset
-        let eth_device = manager
-            .get_device_by_interface(&config.ethernet_interface)
-            .map_err(|_| AppError::DeviceNotFound(config.ethernet_interface.clone()))?;
+        let wifi_interface = find_device(backend.as_ref(), config.interface.as_deref())?;
 
-        // Verify it's actually an ethernet device
-        if eth_device.as_ethernet_device().is_none() {
-            return Err(AppError::NotAnEthernetDevice(
-                config.ethernet_interface.clone(),
-            ));
-        }
+        // Find ethernet devices to reset
+        let mut targets = Vec::new();
+        for interface in &config.ethernet_interfaces {
+            let eth_device = backend.get_device(interface)?;
 
-        // Create WiFi access point
-        let portal_connection = Some(create_portal(&wifi_device, &config)?);
+            // Verify it's actually an ethernet device
+            if eth_device.device_type != DeviceType::Ethernet {
+                return Err(AppError::NotAnEthernetDevice(interface.clone()));
+            }
 
-        // Start dnsmasq for DHCP/DNS
-        let dnsmasq = start_dnsmasq(&config, &wifi_device)?;
+            if targets.iter().all(|t: &EthernetTarget| t.interface != eth_device.interface) {
+                targets.push(EthernetTarget::new(eth_device.interface));
+            }
+        }
 
-        // Create command channel
-        let (tx, rx) = mpsc::channel(32);
+        // State pushed to the UI
+        let initial_reset = targets.iter().map(InterfaceResetStatus::of).collect();
+        let state = Arc::new(watch::Sender::new(PortalState::new(&config, initial_reset)));
 
-        // Spawn background tasks
-        spawn_server(&config, tx.clone());
-        spawn_activity_timeout(config.activity_timeout, tx.clone());
-        spawn_overall_timeout(config.overall_timeout, tx.clone());
-        spawn_signal_handler(tx);
+        let webhooks = Webhooks::start(&config)?;
 
         Ok(Self {
-            manager,
-            eth_device,
-            portal_connection,
+            backend,
+            targets,
+            wifi_interface,
+            portal_connection: None,
             config,
-            dnsmasq,
+            dnsmasq: None,
+            dhcp_server: None,
+            dns_server: None,
             rx,
-            user_connected: false,
+            state,
+            metrics: Arc::new(Metrics::new()),
+            webhooks,
+            mqtt: None,
         })
     }
 
+    /// Create the access point, start the portal servers and spawn the
+    /// background tasks sending commands to `tx`
+    async fn start(&mut self, tx: mpsc::Sender<NetworkCommand>) -> Result<()> {
+        let config = Arc::clone(&self.config);
+        let wifi_interface = self.wifi_interface.as_str();
+
+        // The per-device reset code is derived from the first ethernet device
+        let hw_address = match config.reset_code_secret {
+            Some(_) => self.backend.get_hw_address(&self.targets[0].interface)?,
+            None => None,
+        };
+        let auth = Authenticator::new(&config, hw_address.as_deref())?;
+
+        let tls = load_tls_listener(&config).await?;
+        self.mqtt = Mqtt::start(&config);
+
+        // Create WiFi access point
+        let portal = create_portal(self.backend.as_mut(), wifi_interface, &config)?;
+        self.portal_connection = Some(portal);
+
+        // Start the DHCP and DNS servers, and dnsmasq for what they don't cover
+        let clients = Arc::new(Clients::new(Arc::clone(&self.state)));
+        self.dhcp_server = match config.dhcp_backend {
+            DhcpBackend::Builtin => {
+                let server = DhcpServer::bind(&config, wifi_interface, Arc::clone(&clients))?;
+                spawn_lease_watch(Arc::clone(&clients), None);
+                Some(server.spawn())
+            },
+            DhcpBackend::Dnsmasq => {
+                spawn_lease_watch(Arc::clone(&clients), Some(dnsmasq_lease_file(&config)));
+                None
+            },
+        };
+        self.dns_server = match config.dns_backend {
+            DnsBackend::Builtin => Some(DnsServer::bind(&config, wifi_interface)?.spawn()),
+            DnsBackend::Dnsmasq => None,
+        };
+        if dnsmasq_required(&config) {
+            // Only announce the portal once dnsmasq got past its startup
+            let dnsmasq = self.dnsmasq.insert(Dnsmasq::start(&config, wifi_interface)?);
+            dnsmasq.wait_started().await?;
+        }
+
+        // Spawn background tasks
+        spawn_server(
+            &config,
+            tx.clone(),
+            auth,
+            tls,
+            clients,
+            Arc::clone(&self.state),
+            Arc::clone(&self.metrics),
+        );
+        if let Some(ref mut mqtt) = self.mqtt {
+            mqtt.follow(Arc::clone(&self.state), tx.clone(), &config);
+        }
+        spawn_countdown(Arc::clone(&self.state), tx.clone());
+        spawn_signal_handler(tx);
+
+        Ok(())
+    }
+
     /// Run the main event loop
-    async fn run(&mut self) -> Result<()> {
+    async fn run(&mut self) -> Result<ShutdownReason> {
+        let mut lease_poll = tokio::time::interval(LEASE_POLL_INTERVAL);
+
+        self.webhooks.send(WebhookEvent::Started {
+            gateway: self.config.gateway,
+            interfaces: self.targets.iter().map(|t| t.interface.clone()).collect(),
+            deadline: self.state.borrow().deadline,
+        });
+
         loop {
-            let Some(cmd) = self.rx.recv().await else {
+            let cmd = tokio::select! {
+                cmd = self.rx.recv() => cmd,
+                _ = lease_poll.tick(), if self.is_watching_lease() => {
+                    self.check_leases();
+                    self.publish_reset_status();
+                    continue;
+                }
+                result = supervise_dnsmasq(&mut self.dnsmasq) => {
+                    result?;
+                    continue;
+                }
+            };
+
+            let Some(cmd) = cmd else {
                 return Err(AppError::ChannelClosed);
             };
 
             match cmd {
                 NetworkCommand::Activate => {
-                    if !self.user_connected {
+                    let mut first_visit = false;
+                    let mut connected = false;
+                    self.state.send_if_modified(|state| {
+                        first_visit = !state.visited;
+                        connected = state.activity == ActivityState::Waiting;
+                        state.visited = true;
+                        if connected {
+                            state.activity = ActivityState::Connected;
+                        }
+                        first_visit || connected
+                    });
+                    if connected {
                         info!("User connected to captive portal");
-                        self.user_connected = true;
+                    }
+                    if first_visit {
+                        self.webhooks.send(WebhookEvent::UserConnected);
                     }
                 }
                 NetworkCommand::OverallTimeout => {
                     info!("Overall timeout reached, exiting");
-                    return Ok(());
+                    self.webhooks.send(WebhookEvent::Timeout { timer: "overall" });
+                    return Ok(ShutdownReason::OverallTimeout);
                 }
                 NetworkCommand::ActivityTimeout => {
-                    if !self.user_connected {
+                    if self.state.borrow().activity_expired() {
                         info!("Activity timeout reached, exiting");
-                        return Ok(());
+                        self.webhooks.send(WebhookEvent::Timeout { timer: "activity" });
+                        return Ok(ShutdownReason::ActivityTimeout);
                     }
                 }
                 NetworkCommand::Exit => {
                     info!("Exit signal received");
-                    return Ok(());
+                    return Ok(ShutdownReason::Signal);
+                }
+                NetworkCommand::Stop => {
+                    info!("Stop requested, exiting");
+                    return Ok(ShutdownReason::Stopped);
+                }
+                NetworkCommand::Reset { interfaces, reply } => {
+                    let metrics = Arc::clone(&self.metrics);
+                    let webhooks = self.webhooks.clone();
+                    let outcomes = self.for_each_target(&interfaces, |target, backend, config| {
+                        metrics.reset_started(&target.interface);
+                        webhooks.send(WebhookEvent::ResetRequested {
+                            interface: target.interface.clone(),
+                        });
+                        target.reset_to_dhcp(backend, config)
+                    });
+                    for (interface, result) in &outcomes {
+                        let Err(e) = result else {
+                            continue;
+                        };
+                        if matches!(e, AppError::UnknownInterface(_)) {
+                            continue;
+                        }
+                        self.metrics.reset_finished(interface, false);
+                        self.webhooks.send(WebhookEvent::ResetFailed {
+                            interface: interface.clone(),
+                            reset: ResetStatus::Failed {
+                                error: e.to_string(),
+                            },
+                        });
+                    }
+                    let _ = reply.send(outcomes);
+                }
+                NetworkCommand::ConfigureStatic {
+                    static_config,
+                    reply,
+                } => {
+                    let _ = reply.send(self.configure_static(&static_config));
+                }
+                NetworkCommand::Status(reply) => {
+                    let _ = reply.send(self.ethernet_status());
                 }
-                NetworkCommand::Reset => {
-                    self.reset_to_dhcp()?;
+                NetworkCommand::ResetStatus(reply) => {
+                    let _ = reply.send(self.reset_status());
+                }
+                NetworkCommand::Undo { interfaces, reply } => {
+                    let outcomes = self.for_each_target(&interfaces, |target, backend, config| {
+                        target.undo(backend, config)
+                    });
+                    let _ = reply.send(outcomes);
                 }
             }
+
+            self.publish_reset_status();
         }
     }
 
-    /// Reset ethernet to DHCP
-    fn reset_to_dhcp(&self) -> Result<()> {
-        info!("Resetting {} to DHCP", self.config.ethernet_interface);
+    fn is_watching_lease(&self) -> bool {
+        self.targets.iter().any(EthernetTarget::is_watching_lease)
+    }
 
-        // Delete existing wired connections
-        if let Ok(connections) = self.manager.get_connections() {
-            for conn in connections {
-                if conn.settings().kind == "802-3-ethernet" {
-                    debug!("Deleting wired connection");
-                    let _ = conn.delete();
-                }
+    /// Check every ethernet device that is waiting for a lease
+    fn check_leases(&mut self) {
+        for target in &mut self.targets {
+            if !target.is_watching_lease() {
+                continue;
+            }
+
+            let previous = target.reset_status().clone();
+            target.check_lease(self.backend.as_mut(), &self.config);
+
+            let status = target.reset_status();
+            let success = match status {
+                ResetStatus::LeaseObtained(_) => true,
+                ResetStatus::TimedOut
+                | ResetStatus::RolledBack
+                | ResetStatus::RestoreFailed { .. } => false,
+                _ => continue,
+            };
+            self.metrics.reset_finished(&target.interface, success);
+
+            if *status == previous {
+                continue;
             }
+            let interface = target.interface.clone();
+            self.webhooks.send(match status {
+                ResetStatus::LeaseObtained(lease) => WebhookEvent::LeaseObtained {
+                    interface,
+                    lease: lease.clone(),
+                },
+                _ => WebhookEvent::ResetFailed {
+                    interface,
+                    reset: status.clone(),
+                },
+            });
         }
+    }
+
+    /// Apply an operation to the named ethernet devices, or to all of them
+    ///
+    /// Each interface gets its own result; interfaces that are not managed by
+    /// the portal are reported as failed.
+    fn for_each_target<F>(&mut self, interfaces: &[String], mut op: F) -> InterfaceResults
+    where
+        F: FnMut(&mut EthernetTarget, &mut dyn NetworkBackend, &Config) -> Result<()>,
+    {
+        let selected: Vec<String> = if interfaces.is_empty() {
+            self.targets.iter().map(|t| t.interface.clone()).collect()
+        } else {
+            interfaces.to_vec()
+        };
+
+        selected
+            .into_iter()
+            .map(|interface| {
+                let result = match self.targets.iter_mut().find(|t| t.interface == interface) {
+                    Some(target) => op(target, self.backend.as_mut(), &self.config),
+                    None => Err(AppError::UnknownInterface(interface.clone())),
+                };
+
+                if let Err(ref e) = result {
+                    warn!("Request on {} failed: {}", interface, e);
+                }
 
-        // Set DHCP on the ethernet device
-        let ethernet = self
-            .eth_device
-            .as_ethernet_device()
-            .ok_or_else(|| AppError::NotAnEthernetDevice(self.config.ethernet_interface.clone()))?;
+                (interface, result)
+            })
+            .collect()
+    }
 
-        ethernet
-            .set_dhcp()
-            .map_err(|e| AppError::SetDhcp(e.to_string()))?;
+    /// Apply a static IPv4 configuration to the named ethernet device
+    ///
+    /// The interface may only be omitted when a single device is managed.
+    fn configure_static(&mut self, static_config: &StaticConfig) -> Result<()> {
+        let target = match static_config.interface.as_deref() {
+            Some(name) => self
+                .targets
+                .iter_mut()
+                .find(|t| t.interface == name)
+                .ok_or_else(|| AppError::UnknownInterface(name.to_string()))?,
+            None if self.targets.len() == 1 => &mut self.targets[0],
+            None => return Err(AppError::InterfaceRequired),
+        };
+
+        target.configure_static(self.backend.as_mut(), &self.config, static_config)
+    }
 
-        info!("DHCP reset complete");
-        Ok(())
+    /// Describe every managed ethernet device
+    fn ethernet_status(&self) -> Result<Vec<EthernetStatus>> {
+        self.targets
+            .iter()
+            .map(|target| target.status(self.backend.as_ref(), &self.config))
+            .collect()
+    }
+
+    /// Progress of the last reset on every managed ethernet device
+    fn reset_status(&self) -> Vec<InterfaceResetStatus> {
+        self.targets.iter().map(InterfaceResetStatus::of).collect()
+    }
+
+    /// Push reset progress to the UI when it changed
+    fn publish_reset_status(&self) {
+        let reset = self.reset_status();
+        self.state.send_if_modified(|state| {
+            if state.reset == reset {
+                return false;
+            }
+            state.reset = reset;
+            true
+        });
     }
 
     /// Cleanup resources
-    fn cleanup(&mut self) {
-        let _ = stop_dnsmasq(&mut self.dnsmasq);
+    async fn cleanup(&mut self) {
+        if let Some(mut dnsmasq) = self.dnsmasq.take() {
+            dnsmasq.stop().await;
+        }
 
-        if let Some(ref conn) = self.portal_connection {
+        for server in [self.dhcp_server.take(), self.dns_server.take()].into_iter().flatten() {
+            server.abort();
+        }
+
+        if let Some(conn) = self.portal_connection.take() {
             info!("Stopping access point '{}'", self.config.ssid);
-            let _ = conn.deactivate();
-            let _ = conn.delete();
+            let _ = self.backend.deactivate_connection(&conn.uuid);
+            let _ = self.backend.delete_connection(&conn.uuid);
         }
     }
 }
 
 /// Main entry point
-pub async fn process_network_commands(config: &Config) -> Result<()> {
+pub async fn process_network_commands(
+    backend: Box<dyn NetworkBackend>,
+    config: &Config,
+) -> Result<()> {
     let config = Arc::new(config.clone());
-    let mut handler = NetworkHandler::new(config).await?;
+    let (tx, rx) = mpsc::channel(32);
+    let mut handler = NetworkHandler::new(backend, config, rx)?;
+    if let Err(e) = handler.start(tx).await {
+        // Take down whatever started before the failure
+        handler.cleanup().await;
+        if let Some(mqtt) = handler.mqtt.take() {
+            mqtt.stop().await;
+        }
+        return Err(e);
+    }
 
     let result = handler.run().await;
-    handler.cleanup();
-    result
+
+    let reason = match result {
+        Ok(reason) => reason,
+        Err(_) => ShutdownReason::Error,
+    };
+    handler.state.send_modify(|state| state.shutdown = Some(reason));
+    handler.webhooks.send(WebhookEvent::Exit { reason });
+
+    handler.cleanup().await;
+    handler.webhooks.flush().await;
+    if let Some(mqtt) = handler.mqtt.take() {
+        mqtt.stop().await;
+    }
+    result.map(|_| ())
 }
 
 /// Initialize networking before starting the handler
-pub fn init_networking(config: &Config) -> Result<()> {
-    start_network_manager_service()?;
+pub fn init_networking(backend: &mut dyn NetworkBackend, config: &Config) -> Result<()> {
+    backend.start_service()?;
 
     // Delete any existing AP profile with same SSID
-    let manager = NetworkManager::new();
-    if let Ok(connections) = manager.get_connections() {
+    if let Ok(connections) = backend.get_connections() {
         for conn in connections {
-            let settings = conn.settings();
-            if settings.kind == "802-11-wireless"
-                && settings.mode == "ap"
-                && settings.ssid.as_str().ok() == Some(&config.ssid)
+            if conn.kind == "802-11-wireless"
+                && conn.mode == "ap"
+                && conn.ssid.as_deref() == Some(config.ssid.as_str())
             {
                 info!("Deleting existing AP profile for '{}'", config.ssid);
-                let _ = conn.delete();
+                let _ = backend.delete_connection(&conn.uuid);
             }
         }
     }
@@ -194,21 +590,18 @@ pub fn init_networking(config: &Config) -> Result<()> {
 }
 
 /// Find a WiFi device by interface name or auto-detect
-fn find_device(manager: &NetworkManager, interface: Option<&str>) -> Result<Device> {
+fn find_device(backend: &dyn NetworkBackend, interface: Option<&str>) -> Result<String> {
     if let Some(name) = interface {
-        let device = manager
-            .get_device_by_interface(name)
-            .map_err(|_| AppError::DeviceNotFound(name.to_string()))?;
+        let device = backend.get_device(name)?;
         info!("Using WiFi device: {}", name);
-        return Ok(device);
+        return Ok(device.interface);
     }
 
     // Auto-detect first managed WiFi device
-    for device in manager.get_devices()? {
-        if *device.device_type() == DeviceType::WiFi && device.get_state()? != DeviceState::Unmanaged
-        {
-            info!("Auto-detected WiFi device: {}", device.interface());
-            return Ok(device);
+    for device in backend.get_devices()? {
+        if device.device_type == DeviceType::WiFi && device.state != DeviceState::Unmanaged {
+            info!("Auto-detected WiFi device: {}", device.interface);
+            return Ok(device.interface);
         }
     }
 
@@ -216,83 +609,306 @@ fn find_device(manager: &NetworkManager, interface: Option<&str>) -> Result<Devi
 }
 
 /// Create the captive portal AP
-fn create_portal(device: &Device, config: &Config) -> Result<Connection> {
+fn create_portal(
+    backend: &mut dyn NetworkBackend,
+    interface: &str,
+    config: &Config,
+) -> Result<ConnectionInfo> {
     info!("Creating access point '{}'", config.ssid);
 
-    let wifi = device
-        .as_wifi_device()
-        .ok_or_else(|| AppError::NotAWiFiDevice(device.interface().to_string()))?;
-
     let passphrase = config.passphrase.as_deref();
-    let (connection, _) = wifi
-        .create_hotspot(config.ssid.as_str(), passphrase, Some(config.gateway))
-        .map_err(|e| AppError::CreateCaptivePortal(e.to_string()))?;
+    let connection =
+        backend.create_hotspot(interface, &config.ssid, passphrase, config.gateway)?;
 
     info!("Access point '{}' created", config.ssid);
     Ok(connection)
 }
 
-/// Ensure NetworkManager service is running
-fn start_network_manager_service() -> Result<()> {
-    let Ok(state) = NetworkManager::get_service_state() else {
-        info!("Cannot get NetworkManager state, assuming it's running");
-        return Ok(());
-    };
-
-    if state == ServiceState::Active {
-        debug!("NetworkManager already running");
-        return Ok(());
-    }
-
-    info!("Starting NetworkManager service...");
-    let state =
-        NetworkManager::start_service(15).map_err(|e| AppError::StartNetworkManager(e.to_string()))?;
-
-    if state != ServiceState::Active {
-        return Err(AppError::StartActiveNetworkManager);
-    }
-
-    info!("NetworkManager started");
-    Ok(())
-}
-
 // --- Background task spawners ---
 
-fn spawn_server(config: &Config, tx: mpsc::Sender<NetworkCommand>) {
-    let gateway = config.gateway;
-    let port = config.listening_port;
-    let ui_dir = config.ui_directory();
+fn spawn_server(
+    config: &Arc<Config>,
+    tx: mpsc::Sender<NetworkCommand>,
+    auth: Authenticator,
+    tls: Option<TlsListener>,
+    clients: Arc<Clients>,
+    state: StateSender,
+    metrics: Arc<Metrics>,
+) {
+    let config = Arc::clone(config);
 
     tokio::spawn(async move {
-        if let Err(e) = start_server(gateway, port, tx, ui_dir).await {
+        if let Err(e) = start_server(config, tx, auth, tls, clients, state, metrics).await {
             error!("HTTP server error: {}", e);
         }
     });
 }
 
-fn spawn_activity_timeout(timeout: u64, tx: mpsc::Sender<NetworkCommand>) {
-    if timeout == 0 {
-        return;
-    }
-
+fn spawn_signal_handler(tx: mpsc::Sender<NetworkCommand>) {
     tokio::spawn(async move {
-        tokio::time::sleep(Duration::from_secs(timeout)).await;
-        let _ = tx.send(NetworkCommand::ActivityTimeout).await;
+        if let Err(e) = trap_exit_signals(tx).await {
+            error!("Signal handler error: {}", e);
+        }
     });
 }
 
-fn spawn_overall_timeout(timeout: u64, tx: mpsc::Sender<NetworkCommand>) {
-    if timeout == 0 {
-        return;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FakeBackend, FAKE_WIFI_INTERFACE};
+
+    fn static_config(address: &str, prefix: u8, gateway: Option<&str>) -> StaticConfig {
+        StaticConfig {
+            interface: None,
+            address: address.parse().unwrap(),
+            prefix,
+            gateway: gateway.map(|gateway| gateway.parse().unwrap()),
+            dns: Vec::new(),
+        }
     }
 
-    start_timer(timeout, tx);
-}
+    #[test]
+    fn accepts_host_address_with_gateway() {
+        let config = static_config("192.168.1.50", 24, Some("192.168.1.1"));
+        assert_eq!(config.validate(), Ok(()));
+    }
 
-fn spawn_signal_handler(tx: mpsc::Sender<NetworkCommand>) {
-    tokio::spawn(async move {
-        if let Err(e) = trap_exit_signals(tx).await {
-            error!("Signal handler error: {}", e);
+    #[test]
+    fn accepts_point_to_point_prefixes() {
+        assert_eq!(static_config("10.0.0.0", 31, Some("10.0.0.1")).validate(), Ok(()));
+        assert_eq!(static_config("10.0.0.7", 32, None).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_prefix() {
+        assert!(static_config("192.168.1.50", 0, None).validate().is_err());
+        assert!(static_config("192.168.1.50", 33, None).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_non_unicast_address() {
+        for address in ["0.0.0.0", "127.0.0.1", "224.0.0.1", "255.255.255.255"] {
+            assert!(static_config(address, 8, None).validate().is_err(), "{}", address);
         }
-    });
+    }
+
+    #[test]
+    fn rejects_network_and_broadcast_address() {
+        assert!(static_config("192.168.1.0", 24, None).validate().is_err());
+        assert!(static_config("192.168.1.255", 24, None).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_gateway_outside_subnet() {
+        let config = static_config("192.168.1.50", 24, Some("192.168.2.1"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_gateway_equal_to_address() {
+        let config = static_config("192.168.1.50", 24, Some("192.168.1.50"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_gateway_on_broadcast_address() {
+        let config = static_config("192.168.1.50", 24, Some("192.168.1.255"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_multicast_dns() {
+        let mut config = static_config("192.168.1.50", 24, None);
+        config.dns = vec!["8.8.8.8".parse().unwrap(), "239.1.1.1".parse().unwrap()];
+        assert_eq!(config.validate(), Err("Invalid DNS server 239.1.1.1".to_string()));
+    }
+
+    fn fake_backend() -> FakeBackend {
+        let ethernet = ["eth0".to_string(), "eth1".to_string()];
+        FakeBackend::with_defaults(FAKE_WIFI_INTERFACE, &ethernet)
+    }
+
+    fn handler(args: &[&str]) -> Result<(NetworkHandler, mpsc::Sender<NetworkCommand>)> {
+        handler_with(args, fake_backend())
+    }
+
+    fn handler_with(
+        args: &[&str],
+        backend: FakeBackend,
+    ) -> Result<(NetworkHandler, mpsc::Sender<NetworkCommand>)> {
+        let config = Config::for_tests(args);
+        let (tx, rx) = mpsc::channel(8);
+        let handler = NetworkHandler::new(Box::new(backend), Arc::new(config), rx)?;
+        Ok((handler, tx))
+    }
+
+    /// Send a command and wait for its reply
+    async fn request<T>(
+        tx: &mpsc::Sender<NetworkCommand>,
+        command: impl FnOnce(oneshot::Sender<T>) -> NetworkCommand,
+    ) -> T {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        tx.send(command(reply_tx)).await.unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    fn reset(
+        interfaces: &[&str],
+    ) -> impl FnOnce(oneshot::Sender<InterfaceResults>) -> NetworkCommand {
+        let interfaces = interfaces.iter().map(|i| i.to_string()).collect();
+        |reply| NetworkCommand::Reset { interfaces, reply }
+    }
+
+    fn statuses(reset: &[InterfaceResetStatus]) -> Vec<(&str, &ResetStatus)> {
+        reset.iter().map(|r| (r.interface.as_str(), &r.status)).collect()
+    }
+
+    #[test]
+    fn new_rejects_non_ethernet_interface() {
+        let result = handler(&["-e", "eth0,wlan0"]);
+        assert!(matches!(result, Err(AppError::NotAnEthernetDevice(name)) if name == "wlan0"));
+    }
+
+    #[test]
+    fn new_rejects_missing_interface() {
+        assert!(matches!(handler(&["-e", "eth2"]), Err(AppError::DeviceNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn reset_reports_each_interface() {
+        let (mut handler, tx) = handler(&["-e", "eth0,eth1"]).unwrap();
+
+        let client = async move {
+            let outcomes = request(&tx, reset(&["eth1", "eth2"])).await;
+            assert_eq!(outcomes[0].0, "eth1");
+            assert!(outcomes[0].1.is_ok());
+            assert_eq!(outcomes[1].0, "eth2");
+            assert!(matches!(outcomes[1].1, Err(AppError::UnknownInterface(_))));
+
+            // The lease may already be polled
+            let reset = request(&tx, NetworkCommand::ResetStatus).await;
+            assert_eq!(statuses(&reset)[0], ("eth0", &ResetStatus::Idle));
+            assert!(matches!(
+                reset[1].status,
+                ResetStatus::WaitingForLease | ResetStatus::LeaseObtained(_)
+            ));
+
+            tx.send(NetworkCommand::Stop).await.unwrap();
+        };
+
+        let (reason, ()) = tokio::join!(handler.run(), client);
+        assert_eq!(reason.unwrap(), ShutdownReason::Stopped);
+        assert_eq!(handler.state.borrow().reset, handler.reset_status());
+    }
+
+    #[tokio::test]
+    async fn reset_publishes_lease() {
+        let (mut handler, tx) = handler(&["-e", "eth0"]).unwrap();
+        let mut portal = handler.state.subscribe();
+
+        let client = async move {
+            let outcomes = request(&tx, reset(&[])).await;
+            assert_eq!(outcomes.len(), 1);
+
+            let leased = portal.wait_for(|state| {
+                matches!(state.reset[0].status, ResetStatus::LeaseObtained(_))
+            });
+            tokio::time::timeout(Duration::from_secs(5), leased).await.unwrap().unwrap();
+
+            tx.send(NetworkCommand::Stop).await.unwrap();
+        };
+
+        let (reason, ()) = tokio::join!(handler.run(), client);
+        assert_eq!(reason.unwrap(), ShutdownReason::Stopped);
+    }
+
+    #[tokio::test]
+    async fn failed_reset_is_counted() {
+        let backend = fake_backend().with_failing_set_dhcp();
+        let (mut handler, tx) = handler_with(&["-e", "eth0"], backend).unwrap();
+
+        let client = async move {
+            let outcomes = request(&tx, reset(&[])).await;
+            assert!(outcomes[0].1.is_err());
+
+            tx.send(NetworkCommand::Stop).await.unwrap();
+        };
+
+        let (reason, ()) = tokio::join!(handler.run(), client);
+        assert_eq!(reason.unwrap(), ShutdownReason::Stopped);
+
+        let metrics = handler.metrics.render(&handler.state.borrow(), 0);
+        assert!(metrics.contains("reset_attempts_total{interface=\"eth0\"} 1"));
+        assert!(metrics.contains("reset_failures_total{interface=\"eth0\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn undo_restores_profiles_after_reset() {
+        let (mut handler, tx) = handler(&["-e", "eth0"]).unwrap();
+
+        let client = async move {
+            let outcomes = request(&tx, reset(&["eth0"])).await;
+            assert!(outcomes[0].1.is_ok());
+
+            let outcomes = request(&tx, |reply| NetworkCommand::Undo {
+                interfaces: Vec::new(),
+                reply,
+            })
+            .await;
+            assert!(outcomes[0].1.is_ok());
+
+            let reset = request(&tx, NetworkCommand::ResetStatus).await;
+            assert_eq!(statuses(&reset), [("eth0", &ResetStatus::Restored)]);
+
+            let status = request(&tx, NetworkCommand::Status).await.unwrap();
+            assert_eq!(status[0].ipv4.addresses, ["192.168.1.50/24"]);
+            assert_eq!(status[0].profiles[0].id, "Wired static eth0");
+
+            tx.send(NetworkCommand::Stop).await.unwrap();
+        };
+
+        let (reason, ()) = tokio::join!(handler.run(), client);
+        assert_eq!(reason.unwrap(), ShutdownReason::Stopped);
+    }
+
+    #[tokio::test]
+    async fn static_config_requires_interface_with_several_devices() {
+        let (mut handler, tx) = handler(&["-e", "eth0,eth1"]).unwrap();
+
+        let client = async move {
+            let static_config = static_config("192.168.1.60", 24, None);
+            let result = request(&tx, |reply| NetworkCommand::ConfigureStatic {
+                static_config,
+                reply,
+            })
+            .await;
+            assert!(matches!(result, Err(AppError::InterfaceRequired)));
+
+            tx.send(NetworkCommand::Exit).await.unwrap();
+        };
+
+        let (reason, ()) = tokio::join!(handler.run(), client);
+        assert_eq!(reason.unwrap(), ShutdownReason::Signal);
+    }
+
+    #[tokio::test]
+    async fn activate_marks_portal_visited() {
+        let (mut handler, tx) = handler(&["-e", "eth0", "--activity-timeout", "60"]).unwrap();
+
+        let client = async move {
+            tx.send(NetworkCommand::Activate).await.unwrap();
+
+            // Ignored once a user connected
+            tx.send(NetworkCommand::ActivityTimeout).await.unwrap();
+            request(&tx, NetworkCommand::ResetStatus).await;
+
+            tx.send(NetworkCommand::Stop).await.unwrap();
+        };
+
+        let (reason, ()) = tokio::join!(handler.run(), client);
+        assert_eq!(reason.unwrap(), ShutdownReason::Stopped);
+        let state = handler.state.borrow();
+        assert!(state.visited);
+        assert_eq!(state.activity, ActivityState::Connected);
+    }
 }
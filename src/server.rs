@@ -1,53 +1,159 @@
-use std::net::{Ipv4Addr, SocketAddr};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use axum::{
-    extract::State,
-    http::{header, StatusCode, Uri},
-    response::{IntoResponse, Redirect, Response},
+    body::Bytes,
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, Query, Request, State},
+    http::{header, request::Parts, StatusCode},
+    middleware::{from_fn_with_state, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Redirect, Response,
+    },
     routing::{get, post},
-    Router,
+    Json, Router,
 };
-use tokio::sync::mpsc;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
 use tower_http::cors::CorsLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::services::ServeDir;
-use tracing::{error, info};
+use tower_http::trace::TraceLayer;
+use tracing::{debug, error, info, info_span, warn, Span};
 
-use crate::network::NetworkCommand;
+use crate::audit::{AuditAction, AuditLog, AuditRecord, ConnectionSummary};
+use crate::auth::{AuthError, Authenticator, Role};
+use crate::captive::{portal_url, Probe, CAPTIVE_API_CONTENT_TYPE, CAPTIVE_API_PATH};
+use crate::clients::{ClientInfo, Clients};
+use crate::config::Config;
+use crate::errors::AppError;
+use crate::ethernet::EthernetStatus;
+use crate::metrics::{Metrics, METRICS_CONTENT_TYPE};
+use crate::network::{InterfaceResetStatus, InterfaceResults, NetworkCommand, StaticConfig};
+use crate::state::{extend_overall, rearm_activity, PortalState, StateSender};
+use crate::tls::{TlsListener, TlsMode};
 
-/// Global timer for countdown
-static TIMER: AtomicU64 = AtomicU64::new(0);
+/// Interfaces named in a reset or undo request, all interfaces if empty
+#[derive(Debug, Default, Deserialize)]
+struct InterfaceSelection {
+    #[serde(default)]
+    interfaces: Vec<String>,
+}
+
+/// Time to add with `POST /extend`, the overall timeout if not given
+#[derive(Debug, Default, Deserialize)]
+struct ExtendRequest {
+    seconds: Option<u64>,
+}
+
+/// Credential of `GET /events` in the query, since EventSource cannot send
+/// an Authorization header
+#[derive(Debug, Default, Deserialize)]
+struct StreamCredential {
+    pin: Option<String>,
+}
+
+/// Outcome of a request on one ethernet interface
+#[derive(Debug, Serialize)]
+struct InterfaceOutcome {
+    interface: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
 
 /// Shared state passed to handlers
 #[derive(Clone)]
 pub struct AppState {
-    gateway: Ipv4Addr,
+    config: Arc<Config>,
     network_tx: mpsc::Sender<NetworkCommand>,
+    auth: Arc<Authenticator>,
+    /// Answer connectivity checks as online
+    online: Arc<AtomicBool>,
+    clients: Arc<Clients>,
+    /// Countdown, activity and reset progress published by the network handler
+    portal: StateSender,
+    metrics: Arc<Metrics>,
+    audit: Arc<AuditLog>,
 }
 
 /// Start the HTTP server
 pub async fn start_server(
-    gateway: Ipv4Addr,
-    listening_port: u16,
+    config: Arc<Config>,
     network_tx: mpsc::Sender<NetworkCommand>,
-    ui_directory: PathBuf,
+    auth: Authenticator,
+    tls: Option<TlsListener>,
+    clients: Arc<Clients>,
+    portal: StateSender,
+    metrics: Arc<Metrics>,
 ) -> Result<(), std::io::Error> {
+    let gateway = config.gateway;
+    let listening_port = config.listening_port;
+    let ui_directory = config.ui_directory();
+    let audit = Arc::new(AuditLog::new(config.audit_log()));
+
     let state = AppState {
-        gateway,
+        config,
         network_tx,
+        auth: Arc::new(auth),
+        online: Arc::new(AtomicBool::new(false)),
+        clients,
+        audit,
+        portal,
+        metrics,
     };
 
     // Static file serving for UI
     let serve_dir = ServeDir::new(&ui_directory)
         .append_index_html_on_directories(true);
 
+    // Endpoints that only read state
+    let view_routes = Router::new()
+        .route("/status", get(status))
+        .route("/reset_status", get(reset_status))
+        .route("/clients", get(list_clients))
+        .route("/state", get(portal_state))
+        .route_layer(from_fn_with_state(state.clone(), require_view))
+        .route(
+            "/events",
+            get(events).route_layer(from_fn_with_state(state.clone(), require_view_stream)),
+        );
+
+    // Endpoints that change the ethernet configuration or the countdown
+    let admin_routes = Router::new()
+        .route("/reset_dhcp", post(reset_dhcp))
+        .route("/undo_reset", post(undo_reset))
+        .route("/configure_static", post(configure_static))
+        .route("/extend", post(extend))
+        .route_layer(from_fn_with_state(state.clone(), require_admin));
+
+    // Metrics on the portal address, merged in after the layers tracking
+    // users so that scrapes neither count as activity nor as requests
+    let mut metrics_routes = Router::new();
+    match state.config.metrics_address {
+        Some(addr) => spawn_metrics_server(addr, state.clone()),
+        None if state.config.metrics => {
+            metrics_routes = metrics_routes.route(
+                "/metrics",
+                get(serve_metrics).route_layer(from_fn_with_state(state.clone(), require_view)),
+            );
+        },
+        None => {},
+    }
+
     // Build the router
     let app = Router::new()
         .route("/get_timer", get(get_timer))
-        .route("/reset_dhcp", post(reset_dhcp))
+        .route(CAPTIVE_API_PATH, get(captive_api))
+        .merge(view_routes)
+        .merge(admin_routes)
         .nest_service("/static", ServeDir::new(ui_directory.join("static")))
         .nest_service("/css", ServeDir::new(ui_directory.join("css")))
         .nest_service("/img", ServeDir::new(ui_directory.join("img")))
@@ -58,13 +164,72 @@ pub async fn start_server(
             state.clone(),
             captive_portal_redirect,
         ))
+        .layer(from_fn_with_state(state.clone(), record_activity))
+        .layer(from_fn_with_state(state.clone(), count_requests))
+        .merge(metrics_routes)
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(log_response),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .with_state(state);
 
+    if let Some(tls) = tls {
+        let tls_app = match tls.mode {
+            TlsMode::Serve => app.clone(),
+            TlsMode::Redirect => https_redirect(gateway, listening_port),
+        };
+        spawn_https_server(SocketAddr::new(gateway.into(), tls.port), tls, tls_app);
+    }
+
     let addr = SocketAddr::new(gateway.into(), listening_port);
     info!("Starting HTTP server on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+}
+
+/// Serve the HTTPS listener until it fails
+fn spawn_https_server(addr: SocketAddr, tls: TlsListener, app: Router) {
+    info!("Starting HTTPS server on {} ({:?})", addr, tls.mode);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum_server::bind_rustls(addr, tls.rustls)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+        {
+            error!("HTTPS server error: {}", e);
+        }
+    });
+}
+
+/// Serve the metrics on their own address until it fails
+fn spawn_metrics_server(addr: SocketAddr, state: AppState) {
+    info!("Serving metrics on {}", addr);
+
+    let app = Router::new().route("/metrics", get(serve_metrics)).with_state(state);
+
+    tokio::spawn(async move {
+        let result = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => axum::serve(listener, app).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Metrics server error: {}", e);
+        }
+    });
+}
+
+/// Router sending every HTTPS request to the HTTP portal
+fn https_redirect(gateway: Ipv4Addr, listening_port: u16) -> Router {
+    let url = match listening_port {
+        80 => format!("http://{}/", gateway),
+        port => format!("http://{}:{}/", gateway, port),
+    };
+
+    Router::new().fallback(move || async move { Redirect::temporary(&url) })
 }
 
 /// Middleware to redirect captive portal requests to the gateway
@@ -73,12 +238,27 @@ async fn captive_portal_redirect(
     req: axum::extract::Request,
     next: axum::middleware::Next,
 ) -> Response {
+    let gateway_str = state.config.gateway.to_string();
+
+    // Answer OS connectivity checks the way each OS expects
+    if let Some(probe) = Probe::from_path(req.uri().path()) {
+        let online = state.online.load(Ordering::Relaxed);
+        debug!("Connectivity check {:?}, answering online: {}", probe, online);
+        if online {
+            return probe.online_response();
+        }
+        state.metrics.probe_redirect(probe.name());
+        return probe.captive_response(&format!("http://{}/", gateway_str));
+    }
+
     // Check if the Host header matches our gateway
     if let Some(host) = req.headers().get(header::HOST) {
         if let Ok(host_str) = host.to_str() {
-            let gateway_str = state.gateway.to_string();
+            let hostname = state.config.portal_hostname.as_str();
+            let is_hostname = host_str.split(':').next() == Some(hostname);
             // If host doesn't match gateway (captive portal detection), redirect
-            if !host_str.starts_with(&gateway_str) {
+            if !host_str.starts_with(&gateway_str) && !is_hostname {
+                state.metrics.probe_redirect("host");
                 return Redirect::temporary(&format!("http://{}/", gateway_str)).into_response();
             }
         }
@@ -87,23 +267,131 @@ async fn captive_portal_redirect(
     next.run(req).await
 }
 
-/// Start the countdown timer
-pub fn start_timer(secs: u64, network_tx: mpsc::Sender<NetworkCommand>) {
-    TIMER.store(secs, Ordering::Relaxed);
+/// Middleware recording the last request of each client, which also
+/// re-arms the sliding activity timeout
+async fn record_activity(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        state.clients.touch(peer.ip());
+    }
+    rearm_activity(&state.portal);
 
-    tokio::spawn(async move {
-        while TIMER.load(Ordering::Relaxed) > 0 {
-            tokio::time::sleep(Duration::from_secs(1)).await;
-            let current = TIMER.load(Ordering::Relaxed);
-            if current > 0 {
-                TIMER.store(current - 1, Ordering::Relaxed);
-            }
-        }
+    next.run(req).await
+}
 
-        if let Err(e) = network_tx.send(NetworkCommand::OverallTimeout).await {
-            error!("Sending NetworkCommand::OverallTimeout failed: {}", e);
-        }
+/// Span of an HTTP request, carried by every event logged while serving it
+fn request_span(req: &Request) -> Span {
+    let id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-");
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| peer.ip().to_string())
+        .unwrap_or_default();
+
+    info_span!(
+        target: "http",
+        "request",
+        id,
+        client,
+        method = %req.method(),
+        // Without the query, which may carry the view PIN
+        uri = %req.uri().path()
+    )
+}
+
+fn log_response(response: &Response, latency: Duration, _span: &Span) {
+    info!(
+        target: "http",
+        status = response.status().as_u16(),
+        ?latency,
+        "Request served"
+    );
+}
+
+/// Middleware counting requests by route and status
+async fn count_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "fallback".to_string(), |path| path.as_str().to_string());
+
+    let response = next.run(req).await;
+    state.metrics.http_request(&route, response.status().as_u16());
+    response
+}
+
+/// Middleware refusing requests without read access
+async fn require_view(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let credential = bearer_credential(&req);
+    require_role(&state, Role::View, credential, req, next).await
+}
+
+/// Middleware refusing event streams without read access, taking the
+/// credential from the `pin` query parameter if there is no header
+async fn require_view_stream(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let credential = bearer_credential(&req).or_else(|| {
+        Query::<StreamCredential>::try_from_uri(req.uri())
+            .ok()
+            .and_then(|Query(query)| query.pin)
     });
+    require_role(&state, Role::View, credential, req, next).await
+}
+
+/// Middleware refusing requests without the reset credential
+async fn require_admin(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let credential = bearer_credential(&req);
+    require_role(&state, Role::Admin, credential, req, next).await
+}
+
+/// Credential in the `Authorization: Bearer <credential>` header
+fn bearer_credential(req: &Request) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|credential| credential.trim().to_string())
+}
+
+/// Check a credential against the required role
+async fn require_role(
+    state: &AppState,
+    role: Role,
+    credential: Option<String>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(peer)| peer.ip());
+
+    match state.auth.authorize(client, credential.as_deref(), role).await {
+        Ok(()) => next.run(req).await,
+        Err(AuthError::Unauthorized) => {
+            info!("Refused {} {}: missing or wrong credential", req.method(), req.uri().path());
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+            )
+                .into_response()
+        },
+        Err(AuthError::Forbidden) => {
+            info!("Refused {} {}: view-only credential", req.method(), req.uri().path());
+            StatusCode::FORBIDDEN.into_response()
+        },
+        Err(AuthError::LockedOut(remaining)) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, remaining.as_secs().max(1).to_string())],
+        )
+            .into_response(),
+    }
 }
 
 /// GET /get_timer - Return the current countdown value
@@ -114,18 +402,427 @@ async fn get_timer(State(state): State<AppState>) -> Result<String, StatusCode>
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
 
-    let time = TIMER.load(Ordering::Relaxed);
+    let time = state.portal.borrow().seconds_remaining.unwrap_or(0);
     Ok(time.to_string())
 }
 
-/// POST /reset_dhcp - Trigger DHCP reset
-async fn reset_dhcp(State(state): State<AppState>) -> StatusCode {
-    info!("Requested DHCP reset");
+/// RFC 8908 captive portal API state
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct CaptiveApiState {
+    captive: bool,
+    user_portal_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seconds_remaining: Option<u64>,
+}
+
+/// GET /captive-portal/api - RFC 8908 captive portal API
+async fn captive_api(State(state): State<AppState>) -> Response {
+    let remaining = state.portal.borrow().seconds_remaining.unwrap_or(0);
+
+    let body = CaptiveApiState {
+        captive: !state.online.load(Ordering::Relaxed),
+        user_portal_url: portal_url(&state.config),
+        seconds_remaining: (remaining > 0).then_some(remaining),
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, CAPTIVE_API_CONTENT_TYPE),
+            (header::CACHE_CONTROL, "private"),
+        ],
+        Json(body),
+    )
+        .into_response()
+}
+
+/// GET /status - Describe the ethernet devices and their current configuration
+async fn status(State(state): State<AppState>) -> Result<Json<Vec<EthernetStatus>>, StatusCode> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    if let Err(e) = state.network_tx.send(NetworkCommand::Status(reply_tx)).await {
+        error!("Sending NetworkCommand::Status failed: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    match reply_rx.await {
+        Ok(Ok(status)) => Ok(Json(status)),
+        Ok(Err(e)) => {
+            error!("Reading ethernet status failed: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+        Err(e) => {
+            error!("Receiving ethernet status failed: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+    }
+}
+
+/// GET /state - Countdown, activity timeout and reset progress
+async fn portal_state(State(state): State<AppState>) -> Json<PortalState> {
+    Json(state.portal.borrow().clone())
+}
+
+/// GET /metrics - Prometheus metrics
+async fn serve_metrics(State(state): State<AppState>) -> Response {
+    let portal = state.portal.borrow().clone();
+    let body = state.metrics.render(&portal, state.clients.list().len());
+
+    ([(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)], body).into_response()
+}
 
-    if let Err(e) = state.network_tx.send(NetworkCommand::Reset).await {
+/// GET /events - Stream the portal state as Server-Sent Events
+///
+/// Sends the current state on connect and again whenever it changes, which
+/// is at least once a second while a timeout counts down.
+async fn events(
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    // Watching the portal counts as opening it
+    if let Err(e) = state.network_tx.send(NetworkCommand::Activate).await {
+        error!("Sending NetworkCommand::Activate failed: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let stream = WatchStream::new(state.portal.subscribe())
+        .map(|portal| Event::default().event("state").json_data(portal));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// GET /clients - List the clients on the portal network
+async fn list_clients(State(state): State<AppState>) -> Json<Vec<ClientInfo>> {
+    Json(state.clients.list())
+}
+
+/// POST /reset_dhcp - Trigger DHCP reset of the selected interfaces
+async fn reset_dhcp(State(state): State<AppState>, requester: Requester, body: Bytes) -> Response {
+    let selection = match parse_body::<InterfaceSelection>(&body) {
+        Ok(selection) => selection,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    info!("Requested DHCP reset of {}", describe_selection(&selection));
+
+    let parameters = json!({ "interfaces": selection.interfaces });
+    let record = start_audit(&state, requester, AuditAction::Reset, parameters).await;
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let cmd = NetworkCommand::Reset {
+        interfaces: selection.interfaces,
+        reply: reply_tx,
+    };
+
+    if let Err(e) = state.network_tx.send(cmd).await {
         error!("Sending NetworkCommand::Reset failed: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR;
+        let status = StatusCode::INTERNAL_SERVER_ERROR;
+        finish_audit(&state, record, status, json!({ "error": e.to_string() })).await;
+        return status.into_response();
+    }
+
+    let (status, outcomes) = match reply_rx.await {
+        Ok(results) => {
+            if state.config.online_after_reset && results.iter().all(|(_, r)| r.is_ok()) {
+                info!("Answering connectivity checks as online");
+                state.online.store(true, Ordering::Relaxed);
+            }
+            interface_outcomes(results)
+        },
+        Err(e) => {
+            error!("Receiving reset result failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Vec::new())
+        },
+    };
+
+    finish_audit(&state, record, status, json!(outcomes)).await;
+    (status, Json(outcomes)).into_response()
+}
+
+/// GET /reset_status - Return the outcome of the last DHCP reset on each interface
+async fn reset_status(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<InterfaceResetStatus>>, StatusCode> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    if let Err(e) = state
+        .network_tx
+        .send(NetworkCommand::ResetStatus(reply_tx))
+        .await
+    {
+        error!("Sending NetworkCommand::ResetStatus failed: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    reply_rx.await.map(Json).map_err(|e| {
+        error!("Receiving reset status failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// POST /undo_reset - Restore the profiles deleted by the last change on the selected interfaces
+async fn undo_reset(State(state): State<AppState>, requester: Requester, body: Bytes) -> Response {
+    let selection = match parse_body::<InterfaceSelection>(&body) {
+        Ok(selection) => selection,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    info!("Requested undo on {}", describe_selection(&selection));
+
+    let parameters = json!({ "interfaces": selection.interfaces });
+    let record = start_audit(&state, requester, AuditAction::Restore, parameters).await;
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let cmd = NetworkCommand::Undo {
+        interfaces: selection.interfaces,
+        reply: reply_tx,
+    };
+
+    if let Err(e) = state.network_tx.send(cmd).await {
+        error!("Sending NetworkCommand::Undo failed: {}", e);
+        let status = StatusCode::INTERNAL_SERVER_ERROR;
+        finish_audit(&state, record, status, json!({ "error": e.to_string() })).await;
+        return status.into_response();
+    }
+
+    let (status, outcomes) = match reply_rx.await {
+        Ok(results) => interface_outcomes(results),
+        Err(e) => {
+            error!("Receiving undo result failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Vec::new())
+        },
+    };
+
+    finish_audit(&state, record, status, json!(outcomes)).await;
+    (status, Json(outcomes)).into_response()
+}
+
+/// Parse an optional JSON request body
+fn parse_body<T: DeserializeOwned + Default>(body: &[u8]) -> Result<T, String> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
     }
 
-    StatusCode::OK
+    serde_json::from_slice(body).map_err(|e| format!("Invalid request body: {}", e))
+}
+
+fn describe_selection(selection: &InterfaceSelection) -> String {
+    if selection.interfaces.is_empty() {
+        "all interfaces".to_string()
+    } else {
+        selection.interfaces.join(", ")
+    }
+}
+
+/// Outcome on each interface and the status reporting them
+///
+/// `200` if every interface succeeded, `409` if there was nothing to restore
+/// on any of them, `422` otherwise.
+fn interface_outcomes(results: InterfaceResults) -> (StatusCode, Vec<InterfaceOutcome>) {
+    let status = if results.iter().all(|(_, result)| result.is_ok()) {
+        StatusCode::OK
+    } else if results
+        .iter()
+        .all(|(_, result)| matches!(result, Err(AppError::NothingToRestore)))
+    {
+        StatusCode::CONFLICT
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+
+    let outcomes = results
+        .into_iter()
+        .map(|(interface, result)| InterfaceOutcome {
+            interface,
+            ok: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        })
+        .collect();
+
+    (status, outcomes)
+}
+
+/// POST /configure_static - Apply a static IPv4 configuration
+async fn configure_static(
+    State(state): State<AppState>,
+    requester: Requester,
+    Json(static_config): Json<StaticConfig>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    info!("Requested static configuration: {:?}", static_config);
+
+    static_config
+        .validate()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let parameters = json!(static_config);
+    let record = start_audit(&state, requester, AuditAction::ConfigureStatic, parameters).await;
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let cmd = NetworkCommand::ConfigureStatic {
+        static_config,
+        reply: reply_tx,
+    };
+
+    if let Err(e) = state.network_tx.send(cmd).await {
+        error!("Sending NetworkCommand::ConfigureStatic failed: {}", e);
+        let status = StatusCode::INTERNAL_SERVER_ERROR;
+        let outcome = json!({ "ok": false, "error": e.to_string() });
+        finish_audit(&state, record, status, outcome).await;
+        return Err((status, String::new()));
+    }
+
+    let result = match reply_rx.await {
+        Ok(Ok(())) => Ok(StatusCode::OK),
+        Ok(Err(e @ (AppError::UnknownInterface(_) | AppError::InterfaceRequired))) => {
+            Err((StatusCode::BAD_REQUEST, e.to_string()))
+        },
+        Ok(Err(e)) => {
+            error!("Static configuration failed: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        },
+        Err(e) => {
+            error!("Receiving static configuration result failed: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()))
+        },
+    };
+
+    let (status, outcome) = match result {
+        Ok(status) => (status, json!({ "ok": true })),
+        Err((status, ref error)) => (status, json!({ "ok": false, "error": error })),
+    };
+    finish_audit(&state, record, status, outcome).await;
+    result
+}
+
+/// POST /extend - Add time to the overall timeout, up to the maximum lifetime
+async fn extend(State(state): State<AppState>, requester: Requester, body: Bytes) -> Response {
+    let request = match parse_body::<ExtendRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let seconds = request.seconds.unwrap_or(state.config.overall_timeout);
+    if seconds == 0 {
+        return (StatusCode::BAD_REQUEST, "Extension must be at least one second").into_response();
+    }
+
+    let client = requester.client;
+    let parameters = json!({ "seconds": seconds });
+    let mut record = requester.audit_record(&state, AuditAction::Extend, parameters);
+    let previous_deadline = state.portal.borrow().deadline;
+
+    let refused = match extend_overall(&state.portal, seconds) {
+        Ok(extension) => {
+            info!(
+                "{} extended the overall timeout by {}s, exiting in {}s",
+                client, extension.added, extension.seconds_remaining
+            );
+            record.status = StatusCode::OK.as_u16();
+            record.outcome = json!({
+                "added": extension.added,
+                "previous_deadline": previous_deadline,
+                "deadline": extension.deadline,
+            });
+            state.audit.append(&record);
+            return Json(extension).into_response();
+        },
+        Err(e) => e.to_string(),
+    };
+
+    info!("Refused to extend the overall timeout for {}: {}", client, refused);
+    record.status = StatusCode::CONFLICT.as_u16();
+    record.outcome = json!({ "error": refused, "deadline": previous_deadline });
+    state.audit.append(&record);
+    (StatusCode::CONFLICT, refused).into_response()
+}
+
+/// Client requesting a change, for the audit log
+struct Requester {
+    client: IpAddr,
+    user_agent: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Requester {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            error!("Client address missing from request");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+
+        Ok(Self {
+            client: peer.ip(),
+            user_agent: parts
+                .headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
+
+impl Requester {
+    /// Audit record of a change by this client, without its outcome
+    fn audit_record(self, state: &AppState, action: AuditAction, parameters: Value) -> AuditRecord {
+        AuditRecord {
+            timestamp: Utc::now(),
+            action,
+            client: Some(self.client),
+            hw_address: state.clients.hw_address(self.client),
+            user_agent: self.user_agent,
+            parameters,
+            before: Vec::new(),
+            after: Vec::new(),
+            status: 0,
+            outcome: Value::Null,
+        }
+    }
+}
+
+/// Audit record of a network change, with the connections before it
+async fn start_audit(
+    state: &AppState,
+    requester: Requester,
+    action: AuditAction,
+    parameters: Value,
+) -> AuditRecord {
+    let mut record = requester.audit_record(state, action, parameters);
+    record.before = connection_summaries(state).await;
+    record
+}
+
+/// Complete an audit record with the connections after the change and append it
+///
+/// The record is written even if the connections cannot be read, with
+/// `after` left empty.
+async fn finish_audit(
+    state: &AppState,
+    mut record: AuditRecord,
+    status: StatusCode,
+    outcome: Value,
+) {
+    record.after = connection_summaries(state).await;
+    record.status = status.as_u16();
+    record.outcome = outcome;
+    state.audit.append(&record);
+}
+
+/// Configuration of the ethernet devices, empty if it cannot be read
+async fn connection_summaries(state: &AppState) -> Vec<ConnectionSummary> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    if let Err(e) = state.network_tx.send(NetworkCommand::Status(reply_tx)).await {
+        error!("Sending NetworkCommand::Status failed: {}", e);
+        return Vec::new();
+    }
+
+    match reply_rx.await {
+        Ok(Ok(statuses)) => statuses.into_iter().map(ConnectionSummary::from).collect(),
+        Ok(Err(e)) => {
+            warn!("Reading ethernet status for the audit log failed: {}", e);
+            Vec::new()
+        },
+        Err(e) => {
+            error!("Receiving ethernet status failed: {}", e);
+            Vec::new()
+        },
+    }
 }
@@ -0,0 +1,375 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::{mpsc, watch};
+use tracing::error;
+
+use crate::config::Config;
+use crate::network::{InterfaceResetStatus, NetworkCommand};
+
+/// Sender side of the portal state, shared by everything that changes it
+pub type StateSender = Arc<watch::Sender<PortalState>>;
+
+/// When the activity timeout applies
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivityMode {
+    /// Exit if nobody opens the portal in time
+    FirstVisit,
+    /// Exit once nobody used the portal for the timeout, re-armed by every
+    /// HTTP request and DHCP renewal
+    Sliding,
+}
+
+/// What the activity timeout is waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityState {
+    /// No activity timeout configured
+    Disabled,
+    /// Waiting for a user to open the portal
+    Waiting,
+    /// A user opened the portal, so the activity timeout no longer applies
+    Connected,
+    /// Counting down since the last activity
+    Sliding,
+}
+
+/// Why the portal is shutting down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownReason {
+    OverallTimeout,
+    ActivityTimeout,
+    Signal,
+    /// Stopped by an MQTT command
+    Stopped,
+    Error,
+}
+
+/// Why the overall timeout was not extended
+#[derive(Debug, Error)]
+pub enum ExtendError {
+    /// No overall timeout or no maximum lifetime configured
+    #[error("The overall timeout cannot be extended")]
+    Disabled,
+    /// The overall timeout already ran out
+    #[error("The overall timeout already ran out")]
+    Expired,
+    /// The overall timeout already ends at the maximum lifetime
+    #[error("The maximum lifetime is reached")]
+    LimitReached,
+}
+
+/// Overall timeout after an extension
+#[derive(Debug, Serialize)]
+pub struct Extension {
+    /// Seconds actually added, less than requested near the maximum lifetime
+    pub added: u64,
+    pub seconds_remaining: u64,
+    /// Unix time of the overall timeout
+    pub deadline: u64,
+}
+
+/// State of the portal pushed to the UI
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortalState {
+    /// Seconds until the overall timeout, None if disabled
+    pub seconds_remaining: Option<u64>,
+    /// Unix time of the overall timeout, moved by extensions
+    pub deadline: Option<u64>,
+    /// Unix time the overall timeout can be extended to, None if it cannot
+    pub latest_deadline: Option<u64>,
+    pub activity_mode: ActivityMode,
+    pub activity: ActivityState,
+    /// Seconds until the activity timeout while it counts down
+    pub activity_seconds_remaining: Option<u64>,
+    /// Whether a user opened the portal yet
+    pub visited: bool,
+    #[serde(skip)]
+    activity_timeout: u64,
+    /// Progress of the last reset on each interface
+    pub reset: Vec<InterfaceResetStatus>,
+    /// Set once the portal is shutting down
+    pub shutdown: Option<ShutdownReason>,
+}
+
+impl PortalState {
+    pub fn new(config: &Config, reset: Vec<InterfaceResetStatus>) -> Self {
+        let activity = match (config.activity_timeout, config.activity_mode) {
+            (0, _) => ActivityState::Disabled,
+            (_, ActivityMode::FirstVisit) => ActivityState::Waiting,
+            (_, ActivityMode::Sliding) => ActivityState::Sliding,
+        };
+
+        let now = unix_now();
+        let overall = config.overall_timeout > 0;
+
+        Self {
+            seconds_remaining: overall.then_some(config.overall_timeout),
+            deadline: overall.then_some(now + config.overall_timeout),
+            latest_deadline: (overall && config.max_lifetime > 0)
+                .then_some(now + config.max_lifetime),
+            activity_mode: config.activity_mode,
+            activity,
+            activity_seconds_remaining: (config.activity_timeout > 0)
+                .then_some(config.activity_timeout),
+            visited: false,
+            activity_timeout: config.activity_timeout,
+            reset,
+            shutdown: None,
+        }
+    }
+
+    /// Whether the activity timeout is counting down
+    fn activity_counting(&self) -> bool {
+        matches!(self.activity, ActivityState::Waiting | ActivityState::Sliding)
+    }
+
+    /// Whether the activity timeout ran out, rather than being re-armed or
+    /// satisfied since it expired
+    pub fn activity_expired(&self) -> bool {
+        self.activity_counting() && self.activity_seconds_remaining == Some(0)
+    }
+}
+
+/// Add time to the overall timeout, up to the maximum lifetime
+pub fn extend_overall(state: &StateSender, seconds: u64) -> Result<Extension, ExtendError> {
+    let mut outcome = Err(ExtendError::Disabled);
+
+    state.send_if_modified(|state| {
+        let (Some(remaining), Some(latest)) = (state.seconds_remaining, state.latest_deadline)
+        else {
+            return false;
+        };
+        if remaining == 0 || state.shutdown.is_some() {
+            outcome = Err(ExtendError::Expired);
+            return false;
+        }
+
+        let now = unix_now();
+        let extended = (remaining + seconds).min(latest.saturating_sub(now));
+        if extended <= remaining {
+            outcome = Err(ExtendError::LimitReached);
+            return false;
+        }
+
+        state.seconds_remaining = Some(extended);
+        state.deadline = Some(now + extended);
+        outcome = Ok(Extension {
+            added: extended - remaining,
+            seconds_remaining: extended,
+            deadline: now + extended,
+        });
+        true
+    });
+
+    outcome
+}
+
+/// Re-arm the sliding activity timeout
+pub fn rearm_activity(state: &StateSender) {
+    state.send_if_modified(|state| {
+        if state.activity != ActivityState::Sliding
+            || state.activity_seconds_remaining == Some(state.activity_timeout)
+        {
+            return false;
+        }
+        state.activity_seconds_remaining = Some(state.activity_timeout);
+        true
+    });
+}
+
+/// Count down the overall and activity timeouts once a second
+///
+/// Sends the matching command to the network handler when either runs out.
+pub fn spawn_countdown(state: StateSender, network_tx: mpsc::Sender<NetworkCommand>) {
+    let counting = {
+        let current = state.borrow();
+        current.seconds_remaining.is_some() || current.activity_seconds_remaining.is_some()
+    };
+    if !counting {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        // The first tick completes immediately
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let mut expired = Vec::new();
+            state.send_if_modified(|state| {
+                let mut modified = false;
+
+                if let Some(ref mut secs) = state.seconds_remaining {
+                    if *secs > 0 {
+                        *secs -= 1;
+                        modified = true;
+                        if *secs == 0 {
+                            expired.push(NetworkCommand::OverallTimeout);
+                        }
+                    }
+                }
+
+                if !state.activity_counting() {
+                    modified |= state.activity_seconds_remaining.take().is_some();
+                } else if let Some(ref mut secs) = state.activity_seconds_remaining {
+                    if *secs > 0 {
+                        *secs -= 1;
+                        modified = true;
+                        if *secs == 0 {
+                            expired.push(NetworkCommand::ActivityTimeout);
+                        }
+                    }
+                }
+
+                modified
+            });
+
+            for command in expired {
+                if let Err(e) = network_tx.send(command).await {
+                    error!("Sending timeout command failed: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Portal with the overall timeout and maximum lifetime in seconds from now
+    fn portal(remaining: Option<u64>, lifetime: Option<u64>) -> StateSender {
+        let now = unix_now();
+        let mut state = PortalState::new(&Config::for_tests(&[]), vec![]);
+        state.seconds_remaining = remaining;
+        state.deadline = remaining.map(|remaining| now + remaining);
+        state.latest_deadline = lifetime.map(|lifetime| now + lifetime);
+        Arc::new(watch::Sender::new(state))
+    }
+
+    #[test]
+    fn extension_adds_the_requested_time() {
+        let state = portal(Some(100), Some(3600));
+
+        let extension = extend_overall(&state, 300).unwrap();
+        assert_eq!(extension.added, 300);
+        assert_eq!(extension.seconds_remaining, 400);
+        assert_eq!(state.borrow().seconds_remaining, Some(400));
+        assert_eq!(state.borrow().deadline, Some(extension.deadline));
+    }
+
+    #[test]
+    fn extension_is_capped_at_the_latest_deadline() {
+        let state = portal(Some(100), Some(150));
+        let latest = state.borrow().latest_deadline.unwrap();
+
+        let extension = extend_overall(&state, 600).unwrap();
+        assert_eq!(extension.deadline, latest);
+        assert_eq!(extension.added, extension.seconds_remaining - 100);
+        assert!((49..=50).contains(&extension.added), "added {}", extension.added);
+        assert_eq!(state.borrow().deadline, Some(latest));
+    }
+
+    #[test]
+    fn extension_at_the_latest_deadline_is_refused() {
+        let state = portal(Some(100), Some(100));
+
+        let error = extend_overall(&state, 60).unwrap_err();
+        assert!(matches!(error, ExtendError::LimitReached));
+        assert_eq!(state.borrow().seconds_remaining, Some(100));
+    }
+
+    #[test]
+    fn extension_after_the_timeout_ran_out_is_refused() {
+        let state = portal(Some(0), Some(3600));
+        assert!(matches!(extend_overall(&state, 60), Err(ExtendError::Expired)));
+
+        let state = portal(Some(100), Some(3600));
+        state.send_modify(|state| state.shutdown = Some(ShutdownReason::Signal));
+        assert!(matches!(extend_overall(&state, 60), Err(ExtendError::Expired)));
+        assert_eq!(state.borrow().seconds_remaining, Some(100));
+    }
+
+    #[test]
+    fn extension_without_a_maximum_lifetime_is_refused() {
+        let state = portal(Some(100), None);
+        assert!(matches!(extend_overall(&state, 60), Err(ExtendError::Disabled)));
+
+        let state = portal(None, None);
+        assert!(matches!(extend_overall(&state, 60), Err(ExtendError::Disabled)));
+    }
+
+    fn activity(args: &[&str]) -> StateSender {
+        Arc::new(watch::Sender::new(PortalState::new(&Config::for_tests(args), vec![])))
+    }
+
+    #[test]
+    fn activity_rearms_only_while_sliding() {
+        let state = activity(&["-a", "60", "--activity-mode", "sliding"]);
+        state.send_modify(|state| state.activity_seconds_remaining = Some(5));
+
+        rearm_activity(&state);
+        assert_eq!(state.borrow().activity_seconds_remaining, Some(60));
+
+        let state = activity(&["-a", "60"]);
+        state.send_modify(|state| state.activity_seconds_remaining = Some(5));
+
+        rearm_activity(&state);
+        assert_eq!(state.borrow().activity_seconds_remaining, Some(5));
+    }
+
+    #[test]
+    fn activity_expires_only_while_counting() {
+        let state = activity(&["-a", "60", "--activity-mode", "sliding"]);
+        state.send_modify(|state| state.activity_seconds_remaining = Some(0));
+        assert!(state.borrow().activity_expired());
+
+        // A user opening the portal in first visit mode stops the countdown
+        let state = activity(&["-a", "60"]);
+        state.send_modify(|state| {
+            state.activity = ActivityState::Connected;
+            state.activity_seconds_remaining = Some(0);
+        });
+        assert!(!state.borrow().activity_expired());
+    }
+
+    #[tokio::test]
+    async fn countdown_sends_activity_timeout() {
+        let state = activity(&["-a", "1", "--activity-mode", "sliding"]);
+        let (tx, mut rx) = mpsc::channel(1);
+
+        spawn_countdown(Arc::clone(&state), tx);
+
+        let command = tokio::time::timeout(Duration::from_secs(3), rx.recv()).await;
+        assert!(matches!(command, Ok(Some(NetworkCommand::ActivityTimeout))));
+        assert!(state.borrow().activity_expired());
+    }
+
+    #[tokio::test]
+    async fn countdown_stops_once_activity_no_longer_counts() {
+        let state = activity(&["-a", "1"]);
+        state.send_modify(|state| state.activity = ActivityState::Connected);
+        let (tx, mut rx) = mpsc::channel(1);
+
+        spawn_countdown(Arc::clone(&state), tx);
+
+        let command = tokio::time::timeout(Duration::from_millis(1500), rx.recv()).await;
+        assert!(command.is_err(), "unexpected {:?}", command);
+        assert_eq!(state.borrow().activity_seconds_remaining, None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use axum_server::tls_rustls::RustlsConfig;
+use clap::ValueEnum;
+use rcgen::{CertificateParams, DnType, KeyPair, SanType};
+use tracing::info;
+
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+
+const TLS_DIRECTORY: &str = "tls";
+
+/// What the HTTPS listener does with requests
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Redirect every request to the HTTP portal
+    Redirect,
+    /// Serve the portal over HTTPS
+    Serve,
+}
+
+/// HTTPS listener settings with the loaded certificate
+pub struct TlsListener {
+    pub port: u16,
+    pub mode: TlsMode,
+    pub rustls: RustlsConfig,
+}
+
+/// Load the certificate of the HTTPS listener, if one is configured
+///
+/// Uses the configured certificate and key, or a self-signed certificate for
+/// the gateway address and portal hostname that is generated on first start
+/// and cached in the state directory.
+pub async fn load_tls_listener(config: &Config) -> Result<Option<TlsListener>> {
+    let Some(port) = config.tls_port else {
+        return Ok(None);
+    };
+
+    // The process may already have a provider if it was set up before
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let (certificate, key) = match (&config.tls_certificate, &config.tls_key) {
+        (Some(certificate), Some(key)) => (certificate.clone(), key.clone()),
+        _ => self_signed_certificate(
+            &config.state_directory,
+            config.gateway,
+            &config.portal_hostname,
+        )?,
+    };
+
+    info!("Using TLS certificate {}", certificate.display());
+
+    let rustls = RustlsConfig::from_pem_file(&certificate, &key)
+        .await
+        .map_err(|e| AppError::Tls(format!("{}: {}", certificate.display(), e)))?;
+
+    Ok(Some(TlsListener {
+        port,
+        mode: config.tls_mode,
+        rustls,
+    }))
+}
+
+/// Paths of the cached self-signed certificate and key, generating them if missing
+///
+/// The file names include the gateway and hostname so a changed portal
+/// address gets a matching certificate.
+fn self_signed_certificate(
+    state_directory: &Path,
+    gateway: Ipv4Addr,
+    hostname: &str,
+) -> Result<(PathBuf, PathBuf)> {
+    let directory = state_directory.join(TLS_DIRECTORY);
+    let certificate = directory.join(format!("{}-{}.crt", hostname, gateway));
+    let key = directory.join(format!("{}-{}.key", hostname, gateway));
+
+    if certificate.is_file() && key.is_file() {
+        return Ok((certificate, key));
+    }
+
+    info!("Generating self-signed certificate for {} and {}", hostname, gateway);
+
+    let tls_error = |e: rcgen::Error| AppError::Tls(e.to_string());
+
+    let mut params = CertificateParams::new(vec![hostname.to_string()]).map_err(tls_error)?;
+    params
+        .subject_alt_names
+        .push(SanType::IpAddress(IpAddr::V4(gateway)));
+    params.distinguished_name.push(DnType::CommonName, hostname);
+
+    let key_pair = KeyPair::generate().map_err(tls_error)?;
+    let cert = params.self_signed(&key_pair).map_err(tls_error)?;
+
+    fs::create_dir_all(&directory)?;
+    write_file(&key, key_pair.serialize_pem().as_bytes(), 0o600)?;
+    write_file(&certificate, cert.pem().as_bytes(), 0o644)?;
+
+    Ok((certificate, key))
+}
+
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn self_signed_certificate_is_cached() {
+        let state_directory = Config::for_tests(&[]).state_directory;
+        let gateway = Ipv4Addr::new(192, 168, 42, 1);
+
+        let (certificate, key) =
+            self_signed_certificate(&state_directory, gateway, "setup.ember").unwrap();
+        let pem = fs::read_to_string(&certificate).unwrap();
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(fs::read_to_string(&key)
+            .unwrap()
+            .contains("PRIVATE KEY-----"));
+        assert_eq!(mode(&key), 0o600);
+        assert_eq!(mode(&certificate), 0o644);
+
+        let cached = self_signed_certificate(&state_directory, gateway, "setup.ember").unwrap();
+        assert_eq!(cached, (certificate.clone(), key));
+        assert_eq!(fs::read_to_string(&certificate).unwrap(), pem);
+
+        // Another portal address gets a certificate of its own
+        let (other, _) =
+            self_signed_certificate(&state_directory, Ipv4Addr::new(10, 42, 0, 1), "setup.ember")
+                .unwrap();
+        assert_ne!(other, certificate);
+        assert_ne!(fs::read_to_string(&other).unwrap(), pem);
+    }
+
+    #[tokio::test]
+    async fn listener_needs_a_port() {
+        assert!(load_tls_listener(&Config::for_tests(&[]))
+            .await
+            .unwrap()
+            .is_none());
+
+        let config = Config::for_tests(&["--tls-port", "8443", "--tls-mode", "serve"]);
+        let listener = load_tls_listener(&config).await.unwrap().unwrap();
+        assert_eq!(listener.port, 8443);
+        assert_eq!(listener.mode, TlsMode::Serve);
+    }
+
+    #[tokio::test]
+    async fn unreadable_certificate_is_an_error() {
+        let config = Config::for_tests(&[
+            "--tls-port",
+            "8443",
+            "--tls-certificate",
+            "/nonexistent/portal.crt",
+            "--tls-key",
+            "/nonexistent/portal.key",
+        ]);
+
+        assert!(matches!(
+            load_tls_listener(&config).await,
+            Err(AppError::Tls(_))
+        ));
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use crate::ethernet::{DhcpLease, ResetStatus};
+use crate::state::ShutdownReason;
+
+/// Queue of undelivered notifications in the state directory
+const QUEUE_DIRECTORY: &str = "webhooks";
+
+/// Notifications kept while the endpoints cannot be reached; the oldest are
+/// dropped beyond this
+const MAX_QUEUED: usize = 1000;
+
+/// How long a delivery may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry, doubled on every further failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// How long queued notifications are delivered for when exiting
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Header with the HMAC-SHA256 of the body, as `sha256=<hex>`
+const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Header with the event name
+const EVENT_HEADER: &str = "x-webhook-event";
+
+/// Portal lifecycle event sent to the webhooks
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// The access point is up and the portal is serving
+    Started {
+        gateway: Ipv4Addr,
+        interfaces: Vec<String>,
+        /// Unix time of the overall timeout
+        deadline: Option<u64>,
+    },
+    /// The first user opened the portal
+    UserConnected,
+    /// A DHCP reset was requested on an interface
+    ResetRequested { interface: String },
+    /// The interface obtained a lease after a reset
+    LeaseObtained { interface: String, lease: DhcpLease },
+    /// The reset failed, timed out or was rolled back
+    ResetFailed { interface: String, reset: ResetStatus },
+    /// The overall or activity timeout ran out
+    Timeout { timer: &'static str },
+    /// The portal is exiting
+    Exit { reason: ShutdownReason },
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Started { .. } => "started",
+            Self::UserConnected => "user_connected",
+            Self::ResetRequested { .. } => "reset_requested",
+            Self::LeaseObtained { .. } => "lease_obtained",
+            Self::ResetFailed { .. } => "reset_failed",
+            Self::Timeout { .. } => "timeout",
+            Self::Exit { .. } => "exit",
+        }
+    }
+}
+
+/// Body of a notification
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: Uuid,
+    timestamp: DateTime<Utc>,
+    hostname: &'a str,
+    ssid: &'a str,
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+}
+
+/// Notification waiting in the queue for one endpoint
+///
+/// The body is kept as sent so that retries carry the same signature.
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    event: String,
+    body: String,
+}
+
+/// Failed deliveries to an endpoint
+struct Backoff {
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Sends lifecycle events to the configured webhook URLs
+///
+/// Events are written to an on-disk queue first and delivered in order by a
+/// background task, so that they survive the endpoints or the uplink being
+/// down, and a restart of the portal.
+#[derive(Clone)]
+pub struct Webhooks {
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    urls: Vec<Url>,
+    secret: Option<String>,
+    hostname: String,
+    ssid: String,
+    queue: PathBuf,
+    client: Client,
+    /// Wakes the delivery task when an event is queued
+    queued: Notify,
+    /// Notifications left in the queue
+    pending: watch::Sender<usize>,
+    sequence: AtomicU64,
+}
+
+impl Webhooks {
+    /// Start delivering, including what an earlier run left in the queue
+    pub fn start(config: &Config) -> Result<Self> {
+        if config.webhook_urls.is_empty() {
+            return Ok(Self { inner: None });
+        }
+
+        let queue = config.state_directory.join(QUEUE_DIRECTORY);
+        fs::create_dir_all(&queue)
+            .map_err(|e| AppError::Webhook(format!("creating {}: {}", queue.display(), e)))?;
+
+        // Notifications an earlier run was still writing when it stopped
+        for entry in fs::read_dir(&queue).into_iter().flatten().flatten() {
+            if entry.path().extension().is_some_and(|ext| ext == "tmp") {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| AppError::Webhook(e.to_string()))?;
+
+        let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+
+        let inner = Arc::new(Inner {
+            urls: config.webhook_urls.clone(),
+            secret: config.webhook_secret.clone(),
+            hostname,
+            ssid: config.ssid.clone(),
+            queue,
+            client,
+            queued: Notify::new(),
+            pending: watch::Sender::new(0),
+            sequence: AtomicU64::new(0),
+        });
+
+        for url in &inner.urls {
+            info!("Sending lifecycle events to {}", url);
+        }
+        tokio::spawn(Arc::clone(&inner).deliver());
+
+        Ok(Self { inner: Some(inner) })
+    }
+
+    /// Queue an event for every webhook URL
+    pub fn send(&self, event: WebhookEvent) {
+        let Some(ref inner) = self.inner else {
+            return;
+        };
+
+        if let Err(e) = inner.enqueue(&event) {
+            error!("Queueing {} webhook failed: {}", event.name(), e);
+        }
+    }
+
+    /// Give the queued notifications a last chance to go out before exiting
+    ///
+    /// Whatever is left is delivered by the next run.
+    pub async fn flush(&self) {
+        let Some(ref inner) = self.inner else {
+            return;
+        };
+
+        let mut pending = inner.pending.subscribe();
+        let delivered = tokio::time::timeout(FLUSH_TIMEOUT, pending.wait_for(|n| *n == 0)).await;
+        if delivered.is_err() {
+            let left = *inner.pending.borrow();
+            warn!("{} webhook notifications left in {}", left, inner.queue.display());
+        }
+    }
+}
+
+impl Inner {
+    fn enqueue(&self, event: &WebhookEvent) -> Result<()> {
+        let payload = Payload {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            hostname: &self.hostname,
+            ssid: &self.ssid,
+            event,
+        };
+        let body = serde_json::to_string(&payload)?;
+
+        // Names sort in the order the events happened
+        let prefix = format!(
+            "{:020}-{:06}",
+            payload.timestamp.timestamp_micros(),
+            self.sequence.fetch_add(1, Ordering::Relaxed) % 1_000_000
+        );
+
+        for (index, url) in self.urls.iter().enumerate() {
+            let delivery = Delivery {
+                url: url.to_string(),
+                event: event.name().to_string(),
+                body: body.clone(),
+            };
+            // Written under another name first, so the delivery task never
+            // reads a partial file and drops it as unreadable
+            let name = format!("{}-{}.json", prefix, index);
+            let partial = self.queue.join(format!("{}.tmp", name));
+            fs::write(&partial, serde_json::to_vec(&delivery)?)?;
+            fs::rename(&partial, self.queue.join(name))?;
+        }
+
+        self.trim_queue();
+        self.pending.send_modify(|n| *n += self.urls.len());
+        self.queued.notify_one();
+        Ok(())
+    }
+
+    /// Drop the oldest notifications beyond the queue limit
+    fn trim_queue(&self) {
+        let queued = self.queued_files();
+        let excess = queued.len().saturating_sub(MAX_QUEUED);
+        if excess == 0 {
+            return;
+        }
+
+        warn!("Webhook queue is full, dropping the {} oldest notifications", excess);
+        for path in &queued[..excess] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Queued notifications, oldest first
+    fn queued_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = match fs::read_dir(&self.queue) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect(),
+            Err(e) => {
+                error!("Reading {} failed: {}", self.queue.display(), e);
+                Vec::new()
+            },
+        };
+        files.sort();
+        files
+    }
+
+    /// Deliver the queue in order, backing off from endpoints that fail
+    ///
+    /// A failing endpoint holds back its later notifications so they still
+    /// arrive in order, without delaying the other endpoints.
+    async fn deliver(self: Arc<Self>) {
+        let mut backoff: HashMap<String, Backoff> = HashMap::new();
+
+        loop {
+            let queued = self.queued_files();
+            self.pending.send_replace(queued.len());
+
+            let mut next_retry: Option<Instant> = None;
+            let mut held: Vec<String> = Vec::new();
+
+            for path in queued {
+                let Some(delivery) = read_delivery(&path) else {
+                    self.pending.send_modify(|n| *n = n.saturating_sub(1));
+                    continue;
+                };
+
+                if held.contains(&delivery.url) {
+                    continue;
+                }
+
+                if let Some(retry_at) = backoff.get(&delivery.url).map(|b| b.retry_at) {
+                    if Instant::now() < retry_at {
+                        next_retry = Some(next_retry.map_or(retry_at, |at| at.min(retry_at)));
+                        held.push(delivery.url);
+                        continue;
+                    }
+                }
+
+                match self.post(&delivery).await {
+                    Ok(()) => {
+                        debug!("Delivered {} webhook to {}", delivery.event, delivery.url);
+                        backoff.remove(&delivery.url);
+                    },
+                    Err(DeliveryError::Rejected(status)) => {
+                        error!(
+                            "{} rejected the {} webhook with {}, dropping it",
+                            delivery.url, delivery.event, status
+                        );
+                    },
+                    Err(DeliveryError::Retry(reason)) => {
+                        let failures = backoff.get(&delivery.url).map_or(1, |b| b.failures + 1);
+                        let delay = backoff_delay(failures);
+                        warn!(
+                            "Delivering {} webhook to {} failed: {}, retrying in {}s",
+                            delivery.event,
+                            delivery.url,
+                            reason,
+                            delay.as_secs()
+                        );
+
+                        let retry_at = Instant::now() + delay;
+                        next_retry = Some(next_retry.map_or(retry_at, |at| at.min(retry_at)));
+                        backoff.insert(delivery.url.clone(), Backoff { failures, retry_at });
+                        held.push(delivery.url);
+                        continue;
+                    },
+                }
+
+                let _ = fs::remove_file(&path);
+                self.pending.send_modify(|n| *n = n.saturating_sub(1));
+            }
+
+            match next_retry {
+                Some(at) => tokio::select! {
+                    _ = self.queued.notified() => {},
+                    _ = tokio::time::sleep_until(at) => {},
+                },
+                None => self.queued.notified().await,
+            }
+        }
+    }
+
+    async fn post(&self, delivery: &Delivery) -> std::result::Result<(), DeliveryError> {
+        let mut request = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event);
+
+        if let Some(ref secret) = self.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, &delivery.body));
+        }
+
+        let response = request
+            .body(delivery.body.clone())
+            .send()
+            .await
+            .map_err(|e| DeliveryError::Retry(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error()
+            && status != StatusCode::REQUEST_TIMEOUT
+            && status != StatusCode::TOO_MANY_REQUESTS
+        {
+            Err(DeliveryError::Rejected(status))
+        } else {
+            Err(DeliveryError::Retry(status.to_string()))
+        }
+    }
+}
+
+/// Why a delivery failed
+enum DeliveryError {
+    /// The endpoint refused the notification, retrying would not help
+    Rejected(StatusCode),
+    /// The endpoint could not be reached or failed, retry later
+    Retry(String),
+}
+
+fn read_delivery(path: &Path) -> Option<Delivery> {
+    let parsed = fs::read(path)
+        .map_err(AppError::from)
+        .and_then(|data| Ok(serde_json::from_slice(&data)?));
+
+    match parsed {
+        Ok(delivery) => Some(delivery),
+        Err(e) => {
+            error!("Dropping unreadable webhook notification {}: {}", path.display(), e);
+            let _ = fs::remove_file(path);
+            None
+        },
+    }
+}
+
+fn backoff_delay(failures: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of the body keyed with the secret
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// Parse a webhook URL, which must be HTTP or HTTPS
+pub fn parse_webhook_url(value: &str) -> std::result::Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("'{}' is not a URL: {}", value, e))?;
+
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!("'{}' is not an http or https URL ({})", value, scheme)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queue of its own for the given endpoints, without a delivery task
+    fn queue(urls: &[&str]) -> Inner {
+        let queue = Config::for_tests(&[]).state_directory.join(QUEUE_DIRECTORY);
+        fs::create_dir_all(&queue).unwrap();
+
+        Inner {
+            urls: urls.iter().map(|url| Url::parse(url).unwrap()).collect(),
+            secret: None,
+            hostname: "ember".to_string(),
+            ssid: "Ember Setup".to_string(),
+            queue,
+            client: Client::new(),
+            queued: Notify::new(),
+            pending: watch::Sender::new(0),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    fn queued(inner: &Inner) -> Vec<Delivery> {
+        inner
+            .queued_files()
+            .iter()
+            .map(|path| read_delivery(path).unwrap())
+            .collect()
+    }
+
+    fn reset_requested(interface: &str) -> WebhookEvent {
+        WebhookEvent::ResetRequested {
+            interface: interface.to_string(),
+        }
+    }
+
+    #[test]
+    fn signature_is_the_hmac_sha256_of_the_body() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            signature("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        assert_eq!(backoff_delay(1), INITIAL_BACKOFF);
+        assert_eq!(backoff_delay(2), INITIAL_BACKOFF * 2);
+        assert_eq!(backoff_delay(4), INITIAL_BACKOFF * 8);
+        assert_eq!(backoff_delay(8), MAX_BACKOFF);
+        assert_eq!(backoff_delay(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn events_are_queued_in_order_for_every_endpoint() {
+        let inner = queue(&["http://one.example/hook", "https://two.example/hook"]);
+        inner.enqueue(&WebhookEvent::UserConnected).unwrap();
+        inner.enqueue(&reset_requested("eth0")).unwrap();
+
+        let deliveries = queued(&inner);
+        let queued: Vec<(&str, &str)> = deliveries
+            .iter()
+            .map(|delivery| (delivery.event.as_str(), delivery.url.as_str()))
+            .collect();
+        assert_eq!(
+            queued,
+            [
+                ("user_connected", "http://one.example/hook"),
+                ("user_connected", "https://two.example/hook"),
+                ("reset_requested", "http://one.example/hook"),
+                ("reset_requested", "https://two.example/hook"),
+            ]
+        );
+        assert_eq!(*inner.pending.borrow(), 4);
+
+        // Every endpoint gets the same body
+        assert_eq!(deliveries[2].body, deliveries[3].body);
+        let body: serde_json::Value = serde_json::from_str(&deliveries[2].body).unwrap();
+        assert_eq!(body["event"], "reset_requested");
+        assert_eq!(body["interface"], "eth0");
+        assert_eq!(body["ssid"], "Ember Setup");
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest_notifications() {
+        let inner = queue(&["http://one.example/hook"]);
+        for index in 0..MAX_QUEUED + 2 {
+            inner
+                .enqueue(&reset_requested(&format!("eth{}", index)))
+                .unwrap();
+        }
+
+        let deliveries = queued(&inner);
+        assert_eq!(deliveries.len(), MAX_QUEUED);
+        let interface = |delivery: &Delivery| {
+            let body: serde_json::Value = serde_json::from_str(&delivery.body).unwrap();
+            body["interface"].as_str().unwrap().to_string()
+        };
+        assert_eq!(interface(&deliveries[0]), "eth2");
+        assert_eq!(
+            interface(&deliveries[MAX_QUEUED - 1]),
+            format!("eth{}", MAX_QUEUED + 1)
+        );
+    }
+
+    #[test]
+    fn only_http_urls_are_accepted() {
+        assert!(parse_webhook_url("https://hooks.example/portal").is_ok());
+        assert!(parse_webhook_url("ftp://hooks.example/portal").is_err());
+        assert!(parse_webhook_url("hooks.example").is_err());
+    }
+}
@@ -12,6 +12,8 @@ const App = () => {
   const [timer, setTimer] = useState<number>(-1);
   const [isResetting, setIsResetting] = useState(false);
   const [resetSuccess, setResetSuccess] = useState(false);
+  const [pin, setPin] = useState("");
+  const [pinRequired, setPinRequired] = useState(false);
   const intervalRef = useRef<number | null>(null);
 
   // Fetch initial timer value
@@ -63,13 +65,29 @@ const App = () => {
   const handleReset = () => {
     setIsResetting(true);
 
+    const headers: Record<string, string> = {
+      "Content-Type": "application/json",
+    };
+    if (pin) {
+      headers["Authorization"] = `Bearer ${pin}`;
+    }
+
     fetch("/reset_dhcp", {
       method: "POST",
-      headers: {
-        "Content-Type": "application/json",
-      },
+      headers,
     })
       .then((resp) => {
+        if (resp.status === 401) {
+          setPinRequired(true);
+          throw new Error(pin ? t("auth.wrongPin") : t("auth.pinRequired"));
+        }
+        if (resp.status === 403) {
+          throw new Error(t("auth.forbidden"));
+        }
+        if (resp.status === 429) {
+          const seconds = resp.headers.get("Retry-After") ?? "?";
+          throw new Error(t("auth.lockedOut", { seconds }));
+        }
         if (resp.status !== 200) {
           throw new Error(resp.statusText);
         }
@@ -153,7 +171,30 @@ const App = () => {
             <div className='flex flex-col items-center justify-center mt-8'>
               <h3 className='text-xl font-medium text-center mb-6 max-w-lg'>{t("reset.heading")}</h3>
 
-              <Button variant='destructive' size='lg' onClick={handleReset} disabled={isResetting}>
+              {pinRequired && (
+                <label className='flex flex-col gap-2 mb-6 w-full max-w-xs'>
+                  <span className='text-sm font-medium'>{t("auth.pinLabel")}</span>
+                  <input
+                    type='password'
+                    inputMode='numeric'
+                    autoComplete='one-time-code'
+                    autoFocus
+                    value={pin}
+                    onChange={(e) => setPin(e.target.value)}
+                    onKeyDown={(e) => {
+                      if (e.key === "Enter" && pin && !isResetting) handleReset();
+                    }}
+                    className='h-10 rounded-md border border-input bg-background px-3 text-center text-lg tracking-widest outline-none focus-visible:ring-[3px] focus-visible:ring-ring/50'
+                  />
+                </label>
+              )}
+
+              <Button
+                variant='destructive'
+                size='lg'
+                onClick={handleReset}
+                disabled={isResetting || (pinRequired && !pin)}
+              >
                 {isResetting ? t("reset.buttonLoading") : t("reset.button")}
               </Button>
             </div>